@@ -15,17 +15,93 @@ struct Cli {
     command: Commands,
 }
 
+// plan settings that are recorded in the output so the plan can be reproduced
+#[derive(Debug, Args)]
+struct PlanArgs {
+    /// partition/stats predicate, e.g. "dt >= '2026-01-01' AND lang IN ('en','de')"
+    #[arg(long)]
+    filter: Option<String>,
+    #[arg(long)]
+    sample: Option<f64>,
+    #[arg(long = "sample-seed", default_value_t = 0)]
+    sample_seed: u64,
+    /// sample each group of these partition columns separately; needs --sample
+    #[arg(long = "stratify-by")]
+    stratify_by: Option<String>,
+    /// intra-shard order: <column>[:asc|:desc], interleave:<cols> or shuffle:<seed>
+    #[arg(long)]
+    order: Option<String>,
+    /// exclusion list (local path or object-store URI) of quarantined files
+    #[arg(long)]
+    exclude: Option<String>,
+}
+
+impl PlanArgs {
+    fn is_set(&self) -> bool { self.filter.is_some() || self.sample.is_some() || self.order.is_some() || self.exclude.is_some() }
+
+    async fn apply(&self, opts: &mut shard_planner::ShardOptions) -> Result<()> {
+        if let Some(x) = &self.exclude { opts.exclude = core::ExclusionList::load(x).await?; }
+        if let Some(f) = &self.filter { opts.filter = Some(core::Predicate::parse(f)?); }
+        opts.sample = self.sample;
+        opts.sample_seed = self.sample_seed;
+        opts.stratify_by = self.stratify_by.as_deref().map(|x| x.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()).unwrap_or_default();
+        if let Some(o) = &self.order { opts.order = shard_planner::OrderPolicy::parse(o)?; }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     Ls { uri: String },
-    Diff { uri: String, #[arg(long)] from: i64, #[arg(long)] to: i64 },
+    Diff { uri: String, #[arg(long)] from: i64, #[arg(long)] to: i64, #[arg(long)] detail: bool, #[arg(long, value_delimiter = ',')] by: Vec<String>, #[arg(long)] commits: bool },
     Rowcount { uri: String, #[arg(long = "by")] by: Option<String>, #[arg(long)] version: Option<i64> },
     CompactPlan { uri: String, #[arg(long, default_value = "256")] target: u64, #[arg(long = "by")] by: Option<String> },
     PartitionHealth { uri: String, #[arg(long = "by")] by: Option<String> },
-    Manifest { uri: String, #[arg(long)] version: i64, #[arg(long, default_value = "trino")] format: String },
-    VacuumDryRun { uri: String, #[arg(long, default_value = "7")] retention: i64 },
-    Snapshot { uri: String, #[arg(long)] version: i64, #[arg(long)] out: String },
-    ShardManifest { uri: String, #[arg(long)] version: i64, #[arg(long)] shards: u32, #[arg(long, default_value = "bytes")] balance: String, #[arg(long = "by")] by: Option<String>, #[arg(long = "sticky-by")] sticky_by: Option<String>, #[arg(long = "max-files-per-shard")] max_files_per_shard: Option<usize>, #[arg(long = "row-group-aware", default_value_t = false)] row_group_aware: bool },
+    /// --out: a directory or prefix for trino|hive|presto (<out>/<partition dirs>/manifest), the file itself for filelist
+    Manifest { uri: String, #[arg(long)] version: i64, #[arg(long, default_value = "trino")] format: String, #[arg(long)] exclude: Option<String>, #[arg(long)] out: Option<String> },
+    /// --retention in days (or a duration like 36h); defaults to the table's delta.deletedFileRetentionDuration
+    /// --list streams every candidate as NDJSON; --plan-out writes the deletable files in batches for an external tool
+    VacuumDryRun { uri: String, #[arg(long)] retention: Option<String>, #[arg(long, default_value_t = false)] list: bool, #[arg(long = "plan-out")] plan_out: Option<String>, #[arg(long = "batch-size", default_value_t = 1000)] batch_size: usize, #[arg(long = "protect-version", value_delimiter = ',')] protect_versions: Vec<i64>, #[arg(long = "pin-registry")] pin_registry: Option<String> },
+    Snapshot { uri: String, #[arg(long)] version: i64, #[arg(long)] out: String, #[arg(long)] exclude: Option<String> },
+    ShardManifest { uri: String, #[arg(long)] version: i64, #[arg(long)] shards: u32, #[arg(long, default_value = "bytes")] balance: String, #[arg(long = "by")] by: Option<String>, #[arg(long = "sticky-by")] sticky_by: Option<String>, #[arg(long = "max-files-per-shard")] max_files_per_shard: Option<usize>, #[arg(long = "row-group-aware", default_value_t = false)] row_group_aware: bool, #[arg(long)] splits: Option<String>, #[arg(long = "constraint")] constraints: Vec<String>, #[command(flatten)] plan: PlanArgs },
+    ShardMixture { #[arg(long = "source", required = true)] sources: Vec<String>, #[arg(long)] shards: u32, #[arg(long, default_value = "bytes")] balance: String, #[arg(long = "by")] by: Option<String>, #[arg(long = "sticky-by")] sticky_by: Option<String>, #[arg(long = "max-files-per-shard")] max_files_per_shard: Option<usize>, #[arg(long)] exclude: Option<String> },
+    ShardTail { uri: String, #[arg(long)] plan: String, #[arg(long)] from: i64, #[arg(long)] to: i64, #[arg(long, default_value = "bytes")] balance: String, #[arg(long = "by")] by: Option<String>, #[arg(long = "sticky-by")] sticky_by: Option<String>, #[arg(long = "max-files-per-shard")] max_files_per_shard: Option<usize>, #[arg(long = "constraint")] constraints: Vec<String>, #[arg(long)] exclude: Option<String> },
+    ShardServe { #[arg(long)] plan: String, #[arg(long, default_value = "127.0.0.1:7878")] addr: String, #[arg(long)] journal: Option<String>, #[arg(long = "lease-ttl", default_value = "60s")] lease_ttl: String, #[arg(long = "row-groups-from")] row_groups_from: Option<String> },
+    ShardResume { #[arg(long)] plan: String, #[arg(long)] ledger: String, #[arg(long)] failed: Option<String> },
+    /// data-usage ledger: which runs read which files
+    #[command(subcommand)]
+    Ledger(LedgerCommands),
+    /// versions consumers need kept readable; honoured by vacuum-dry-run
+    #[command(subcommand)]
+    Pin(PinCommands),
+    /// compare two snapshots given as <uri>[@version]; exits 0 when equivalent, 2 when not, 1 on error
+    Compare { a: String, b: String },
+    /// write a reproducibility lockfile; --etags records each file's ETag (one HEAD per file), --content each file's blake3 (reads every byte)
+    Lock { uri: String, #[arg(long)] version: i64, #[arg(long)] out: String, #[arg(long)] filter: Option<String>, #[arg(long)] exclude: Option<String>, #[arg(long, default_value_t = false)] etags: bool, #[arg(long, default_value_t = false)] content: bool },
+    /// check a lockfile against storage and the log (--content also re-hashes every byte); exits 0 when nothing drifted, 2 when something did, 1 on error
+    VerifyLock { lock: String, #[arg(long, default_value_t = false)] content: bool },
+    /// HEAD every active file (--deep also checks the Parquet footer); exits 0 when all are fine, 2 when not, 1 on error
+    VerifyFiles { uri: String, #[arg(long)] version: Option<i64>, #[arg(long, default_value_t = false)] deep: bool, #[arg(long)] exclude: Option<String> },
+    /// blake3 every active file (or a --sample fraction) into a content manifest with a Merkle root; --resume names a state file to pick up an interrupted run
+    ContentHash { uri: String, #[arg(long)] version: Option<i64>, #[arg(long)] sample: Option<f64>, #[arg(long, default_value_t = 0)] seed: u64, #[arg(long)] exclude: Option<String>, #[arg(long)] resume: Option<String>, #[arg(long)] out: Option<String> },
+    /// compare two content manifests or lockfiles written with --content byte for byte; exits 0 when identical, 2 when not, 1 on error
+    ContentDiff { a: String, b: String },
+}
+
+// --registry defaults to the table's _deltakit/pins.json sidecar (and the pins/ objects next to it)
+#[derive(Debug, Subcommand)]
+enum PinCommands {
+    /// --expires takes a duration (14d) or an RFC 3339 time
+    Add { uri: String, #[arg(long)] version: i64, #[arg(long)] owner: String, #[arg(long)] expires: Option<String>, #[arg(long)] registry: Option<String> },
+    List { uri: String, #[arg(long)] registry: Option<String> },
+    Remove { uri: String, #[arg(long)] version: i64, #[arg(long)] owner: Option<String>, #[arg(long)] registry: Option<String> },
+}
+
+#[derive(Debug, Subcommand)]
+enum LedgerCommands {
+    Record { #[arg(long)] ledger: String, #[arg(long)] run: String, #[arg(long)] plan: String, #[arg(long)] table: Option<String>, #[arg(long)] version: Option<i64> },
+    RunsForFile { path: String, #[arg(long)] ledger: String, #[arg(long)] table: Option<String>, #[arg(long)] version: Option<i64> },
+    FilesForRun { run: String, #[arg(long)] ledger: String, #[arg(long)] table: Option<String>, #[arg(long)] version: Option<i64> },
 }
 
 #[tokio::main]
@@ -35,14 +111,28 @@ async fn main() -> Result<()> {
 
     match cli.command {
         Commands::Ls { uri } => cmd_ls(&cli.globals, &uri).await?,
-        Commands::Diff { uri, from, to } => cmd_diff(&cli.globals, &uri, from, to).await?,
+        Commands::Diff { uri, from, to, detail, by, commits } => {
+            if commits { cmd_diff_commits(&cli.globals, &uri, from, to, detail, by).await? } else { cmd_diff(&cli.globals, &uri, from, to, detail, by).await? }
+        }
         Commands::Rowcount { uri, by, version } => cmd_rowcount(&cli.globals, &uri, by, version).await?,
         Commands::CompactPlan { uri, target, by } => cmd_compact_plan(&cli.globals, &uri, target, by).await?,
         Commands::PartitionHealth { uri, by } => cmd_partition_health(&cli.globals, &uri, by).await?,
-        Commands::Manifest { uri, version, format } => cmd_manifest(&cli.globals, &uri, version, &format).await?,
-        Commands::VacuumDryRun { uri, retention } => cmd_vacuum(&cli.globals, &uri, retention).await?,
-        Commands::Snapshot { uri, version, out } => cmd_snapshot(&cli.globals, &uri, version, &out).await?,
-        Commands::ShardManifest { uri, version, shards, balance, by, sticky_by, max_files_per_shard, row_group_aware } => cmd_shard_manifest(&cli.globals, &uri, version, shards, &balance, by, sticky_by, max_files_per_shard, row_group_aware).await?,
+        Commands::Manifest { uri, version, format, exclude, out } => cmd_manifest(&cli.globals, &uri, version, &format, exclude, out).await?,
+        Commands::VacuumDryRun { uri, retention, list, plan_out, batch_size, protect_versions, pin_registry } => cmd_vacuum(&cli.globals, &uri, retention, list, plan_out, batch_size, protect_versions, pin_registry).await?,
+        Commands::Snapshot { uri, version, out, exclude } => cmd_snapshot(&cli.globals, &uri, version, &out, exclude).await?,
+        Commands::ShardManifest { uri, version, shards, balance, by, sticky_by, max_files_per_shard, row_group_aware, splits, constraints, plan } => cmd_shard_manifest(&cli.globals, &uri, version, shards, &balance, by, sticky_by, max_files_per_shard, row_group_aware, splits, &constraints, &plan).await?,
+        Commands::ShardMixture { sources, shards, balance, by, sticky_by, max_files_per_shard, exclude } => cmd_shard_mixture(&cli.globals, &sources, shards, &balance, by, sticky_by, max_files_per_shard, exclude).await?,
+        Commands::ShardTail { uri, plan, from, to, balance, by, sticky_by, max_files_per_shard, constraints, exclude } => cmd_shard_tail(&cli.globals, &uri, &plan, from, to, &balance, by, sticky_by, max_files_per_shard, &constraints, exclude).await?,
+        Commands::ShardServe { plan, addr, journal, lease_ttl, row_groups_from } => cmd_shard_serve(&cli.globals, &plan, &addr, journal, &lease_ttl, row_groups_from).await?,
+        Commands::ShardResume { plan, ledger, failed } => cmd_shard_resume(&cli.globals, &plan, &ledger, failed)?,
+        Commands::Ledger(cmd) => cmd_ledger(&cli.globals, cmd).await?,
+        Commands::Pin(cmd) => cmd_pin(&cli.globals, cmd).await?,
+        Commands::Compare { a, b } => cmd_compare(&cli.globals, &a, &b).await?,
+        Commands::Lock { uri, version, out, filter, exclude, etags, content } => cmd_lock(&cli.globals, &uri, version, &out, filter, exclude, etags, content).await?,
+        Commands::VerifyLock { lock, content } => cmd_verify_lock(&cli.globals, &lock, content).await?,
+        Commands::VerifyFiles { uri, version, deep, exclude } => cmd_verify_files(&cli.globals, &uri, version, deep, exclude).await?,
+        Commands::ContentHash { uri, version, sample, seed, exclude, resume, out } => cmd_content_hash(&cli.globals, &uri, version, sample, seed, exclude, resume, out).await?,
+        Commands::ContentDiff { a, b } => cmd_content_diff(&cli.globals, &a, &b).await?,
     }
     Ok(())
 }
@@ -72,9 +162,43 @@ async fn cmd_ls(glob: &GlobalArgs, uri: &str) -> Result<()> {
     }
 }
 
-async fn cmd_diff(glob: &GlobalArgs, uri: &str, from: i64, to: i64) -> Result<()> {
+async fn cmd_diff_commits(glob: &GlobalArgs, uri: &str, from: i64, to: i64, detail: bool, by: Vec<String>) -> Result<()> {
+    if !by.is_empty() { return Err(anyhow::anyhow!("--by is not supported with --commits")); }
     let h = core::load_table(uri).await?;
-    let out = core::diff_versions(&h, from, to).await?;
+    let out = core::diff_commits(&h, from, to, &core::DiffOptions { detail, by }).await?;
+    if glob.json { print_output(true, &out) } else {
+        for c in &out.commits {
+            let at = humantime::format_rfc3339_seconds(std::time::UNIX_EPOCH + std::time::Duration::from_millis(c.timestamp_ms.max(0) as u64));
+            println!("v{} {} {}: +{} files ({}), -{} files ({}){}",
+                c.version,
+                at,
+                c.operation.as_deref().unwrap_or("-"),
+                c.files_added,
+                ByteSize(c.bytes_added as u64),
+                c.files_removed,
+                ByteSize(c.bytes_removed as u64),
+                if c.data_change { "" } else { " [rewrite]" }
+            );
+            if !c.operation_metrics.is_empty() {
+                let metrics: Vec<String> = c.operation_metrics.iter().map(|(k, v)| format!("{}={}", k, v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()))).collect();
+                println!("    {}", metrics.join(" "));
+            }
+            for p in &c.added { println!("    + {}", p); }
+            for p in &c.removed { println!("    - {}", p); }
+        }
+        if !out.transient.is_empty() {
+            println!("transient files (added and removed within v{}..v{}): {}", out.from, out.to, out.transient.len());
+            for t in &out.transient {
+                println!("  {} ({}) v{} -> v{}", t.path, ByteSize(t.size as u64), t.added_in, t.removed_in);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn cmd_diff(glob: &GlobalArgs, uri: &str, from: i64, to: i64, detail: bool, by: Vec<String>) -> Result<()> {
+    let h = core::load_table(uri).await?;
+    let out = core::diff_versions_with(&h, from, to, &core::DiffOptions { detail, by }).await?;
     if glob.json { print_output(true, &out) } else {
         println!("v{}..v{}: +{} files ({}), -{} files ({})",
             out.from,
@@ -84,6 +208,48 @@ async fn cmd_diff(glob: &GlobalArgs, uri: &str, from: i64, to: i64) -> Result<()
             out.files_removed,
             ByteSize(out.bytes_removed as u64)
         );
+        if out.rows_added > 0 || out.rows_removed > 0 {
+            println!("rows: +{}, -{} (net {:+}){}", out.rows_added, out.rows_removed, out.row_delta,
+                if out.files_without_rows > 0 { format!(", {} files without row counts", out.files_without_rows) } else { String::new() });
+        }
+        if out.deletion_vector_updates > 0 { println!("deletion vectors: {} files updated in place", out.deletion_vector_updates); }
+        if let Some(cd) = &out.change_data {
+            println!("change data: {} inserted, {} updated, {} deleted ({} cdc files, {} commits derived from stats)",
+                cd.insert,
+                cd.update_postimage,
+                cd.delete,
+                cd.cdc_files,
+                cd.derived_commits
+            );
+        }
+        if out.rewrite_files_added > 0 || out.rewrite_files_removed > 0 {
+            println!("rewrites (dataChange=false): +{} files ({}), -{} files ({})",
+                out.rewrite_files_added,
+                ByteSize(out.rewrite_bytes_added as u64),
+                out.rewrite_files_removed,
+                ByteSize(out.rewrite_bytes_removed as u64)
+            );
+        }
+        for p in &out.partitions {
+            let name = p.partition.iter().map(|(k, v)| format!("{}={}", k, v.as_deref().unwrap_or("null"))).collect::<Vec<_>>().join("/");
+            println!("  {}: +{} files ({}, {} rows), -{} files ({}, {} rows), net {:+} rows, rewritten +{}/-{}",
+                name,
+                p.files_added,
+                ByteSize(p.bytes_added as u64),
+                p.rows_added,
+                p.files_removed,
+                ByteSize(p.bytes_removed as u64),
+                p.rows_removed,
+                p.row_delta,
+                p.rewrite_files_added,
+                p.rewrite_files_removed
+            );
+        }
+        for (sign, files) in [("+", &out.added), ("-", &out.removed)] {
+            for f in files {
+                println!("{} {} ({}){}", sign, f.path, ByteSize(f.size as u64), if f.data_change { "" } else { " [rewrite]" });
+            }
+        }
         Ok(())
     }
 }
@@ -130,7 +296,17 @@ async fn cmd_partition_health(glob: &GlobalArgs, uri: &str, by: Option<String>)
     }
 }
 
-async fn cmd_manifest(glob: &GlobalArgs, uri: &str, version: i64, format: &str) -> Result<()> {
+async fn load_exclusions(exclude: Option<String>) -> Result<core::ExclusionList> {
+    match exclude { Some(x) => core::ExclusionList::load(&x).await, None => Ok(core::ExclusionList::default()) }
+}
+
+fn print_excluded(excluded: &[core::ExcludedFile]) {
+    if excluded.is_empty() { return; }
+    println!("excluded: {}", excluded.len());
+    for e in excluded { println!("  {} ({}): {}", e.path, e.pattern, e.reason); }
+}
+
+async fn cmd_manifest(glob: &GlobalArgs, uri: &str, version: i64, format: &str, exclude: Option<String>, dest: Option<String>) -> Result<()> {
     let h = core::load_table(uri).await?;
     let fmt = match format.to_ascii_lowercase().as_str() {
         "trino" => core::ManifestFormat::Trino,
@@ -138,45 +314,471 @@ async fn cmd_manifest(glob: &GlobalArgs, uri: &str, version: i64, format: &str)
         "presto" => core::ManifestFormat::Presto,
         _ => core::ManifestFormat::FileList,
     };
-    let out = core::generate_manifest(&h, version, fmt).await?;
-    if glob.json { print_output(true, &out) } else {
+    let exclude = load_exclusions(exclude).await?;
+    let out = core::generate_manifest_excluding(&h, version, fmt, &exclude).await?;
+    let written = match &dest { Some(d) => core::write_manifest(&out, d).await?, None => Vec::new() };
+    if glob.json {
+        #[derive(serde::Serialize)]
+        struct ManifestOut<'a> { #[serde(flatten)] manifest: &'a core::Manifest, #[serde(skip_serializing_if = "Vec::is_empty")] written: Vec<String> }
+        print_output(true, &ManifestOut { manifest: &out, written })
+    } else {
         println!("version: {}", out.version);
         println!("files: {}", out.files.len());
+        match &dest {
+            Some(d) => println!("wrote {} manifest file(s) to {}", written.len(), d),
+            None => println!("manifests: {} (use --out to write them)", out.objects.len()),
+        }
+        print_excluded(&out.excluded);
         Ok(())
     }
 }
 
-async fn cmd_vacuum(glob: &GlobalArgs, uri: &str, retention: i64) -> Result<()> {
+async fn cmd_vacuum(glob: &GlobalArgs, uri: &str, retention: Option<String>, list: bool, plan_out: Option<String>, batch_size: usize, protect_versions: Vec<i64>, pin_registry: Option<String>) -> Result<()> {
     let h = core::load_table(uri).await?;
-    let out = core::vacuum_dry_run(&h, retention).await?;
+    // a bare number keeps meaning days
+    let retention_ms = match retention {
+        Some(r) => Some(match r.trim().parse::<i64>() { Ok(days) => days * 24 * 3600 * 1000, Err(_) => humantime::parse_duration(&r)?.as_millis() as i64 }),
+        None => None,
+    };
+    let spinner = cli_core::pb_spinner(glob.progress && !glob.quiet && !glob.json && !list, "listing table");
+    let progress = spinner.clone().map(|pb| storage::ListProgress(std::sync::Arc::new(move |n| pb.set_message(format!("listed {} objects", n)))));
+    let opts = core::VacuumOptions { retention_ms, protect_versions, pin_registry, concurrency: glob.concurrency, progress, ..Default::default() };
+    let mut kept: Vec<core::VacuumCandidate> = Vec::new();
+    let out = {
+        use std::io::Write;
+        let stdout = std::io::stdout();
+        let mut lines = std::io::BufWriter::new(stdout.lock());
+        let out = core::vacuum_candidates(&h, &opts, |c| {
+            if list { writeln!(lines, "{}", serde_json::to_string(&c)?)?; }
+            if plan_out.is_some() && c.deletable() { kept.push(c); }
+            Ok(())
+        }).await?;
+        lines.flush()?;
+        out
+    };
+    if let Some(pb) = spinner { pb.finish_and_clear(); }
+    if let Some(path) = &plan_out {
+        // written next to the target and renamed so an executor never sees a partial plan
+        let plan = core::DeletionPlan::new(uri, &out, &kept, batch_size);
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&plan)?)?;
+        std::fs::rename(&tmp, path)?;
+        tracing::info!(path = %path, files = plan.files, batches = plan.batches.len(), "wrote deletion plan");
+    }
+    // with --list stdout is reserved for the candidate stream
+    if list {
+        tracing::info!(orphans = out.orphans, deletable = out.deletable_files, safe = out.safe, "vacuum dry run");
+        return Ok(());
+    }
     if glob.json { print_output(true, &out) } else {
+        let class = |c: &core::VacuumClass| format!("{} files ({})", c.files, ByteSize(c.bytes));
         println!("referenced: {}", out.referenced_files);
         println!("existing:   {}", out.existing_files);
         println!("orphans:    {}", out.orphans);
+        println!("retention:  {} ({})", humantime::format_duration(std::time::Duration::from_millis(out.retention_ms.max(0) as u64)), out.retention_source);
+        println!("  removed, past retention:      {}", class(&out.removed_expired));
+        println!("  removed, within retention:    {}", class(&out.removed_retained));
+        println!("  unreferenced, past retention: {}", class(&out.unreferenced_expired));
+        println!("  unreferenced, recent:         {}", class(&out.unreferenced_recent));
+        println!("  change data, past retention:  {}", class(&out.cdc_expired));
+        println!("  change data, still readable:  {}", class(&out.cdc_retained));
+        println!("hidden:     {} (skipped)", out.hidden_files);
+        println!("time travel: v{}..v{} reachable (log retention {}, {}), oldest checkpoint {}",
+            out.earliest_reachable_version,
+            out.latest_version,
+            humantime::format_duration(std::time::Duration::from_millis(out.log_retention_ms.max(0) as u64)),
+            out.log_retention_source,
+            out.oldest_checkpoint.map(|v| v.to_string()).unwrap_or_else(|| "none".into())
+        );
+        if !out.protected_versions.is_empty() { println!("  protected: {:?}", out.protected_versions); }
+        if !out.unreachable_protected.is_empty() { println!("  protected but no longer reconstructable: {:?}", out.unreachable_protected); }
+        println!("  expired but still read by a reachable version: {}", class(&out.breaks_time_travel));
+        for p in &out.pins { println!("  pin {}: v{} by {}", p.id, p.version, p.owner); }
+        if out.pinned.files > 0 { println!("  orphans read by a pin: {}", class(&out.pinned)); }
+        println!("deletable:  {} files ({})", out.deletable_files, ByteSize(out.deletable_bytes));
         println!("safe:       {}", out.safe);
         Ok(())
     }
 }
 
-async fn cmd_snapshot(_glob: &GlobalArgs, uri: &str, version: i64, out: &str) -> Result<()> {
+async fn cmd_snapshot(glob: &GlobalArgs, uri: &str, version: i64, out: &str, exclude: Option<String>) -> Result<()> {
     let h = core::load_table(uri).await?;
-    let manifest = core::generate_manifest(&h, version, core::ManifestFormat::FileList).await?;
+    let exclude = load_exclusions(exclude).await?;
+    let manifest = core::generate_manifest_excluding(&h, version, core::ManifestFormat::FileList, &exclude).await?;
     let mut file = std::fs::File::create(out)?;
     use std::io::Write;
-    for e in manifest.files {
+    for e in &manifest.files {
         writeln!(file, "{}", e.path)?;
     }
-    Ok(())
+    if manifest.excluded.is_empty() { return Ok(()); }
+    if glob.json {
+        #[derive(serde::Serialize)]
+        struct SnapshotOut<'a> { version: i64, out: &'a str, files: usize, excluded: &'a [core::ExcludedFile] }
+        print_output(true, &SnapshotOut { version, out, files: manifest.files.len(), excluded: &manifest.excluded })
+    } else {
+        print_excluded(&manifest.excluded);
+        Ok(())
+    }
 }
 
-async fn cmd_shard_manifest(glob: &GlobalArgs, uri: &str, version: i64, shards: u32, balance: &str, by: Option<String>, sticky_by: Option<String>, max_files: Option<usize>, row_group_aware: bool) -> Result<()> {
+async fn cmd_shard_manifest(glob: &GlobalArgs, uri: &str, version: i64, shards: u32, balance: &str, by: Option<String>, sticky_by: Option<String>, max_files: Option<usize>, row_group_aware: bool, splits: Option<String>, constraints: &[String], plan: &PlanArgs) -> Result<()> {
     use shard_planner as sp;
     let mode = match balance.to_ascii_lowercase().as_str() { "rows" => sp::BalanceMode::Rows, _ => sp::BalanceMode::Bytes };
     let split_csv = |s: Option<String>| -> Vec<String> { s.map(|x| x.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()).unwrap_or_default() };
-    let opts = sp::ShardOptions { by: split_csv(by), sticky_by: split_csv(sticky_by), max_files_per_shard: max_files, balance: mode, row_group_aware };
+    let mut opts = sp::ShardOptions { by: split_csv(by), sticky_by: split_csv(sticky_by), max_files_per_shard: max_files, balance: mode, row_group_aware, ..Default::default() };
+    plan.apply(&mut opts).await?;
+    opts.constraints = constraints.iter().map(|c| sp::Constraint::parse(c)).collect::<Result<_>>()?;
     let h = core::load_table(uri).await?;
+    if let Some(spec) = splits {
+        let splits = sp::parse_splits(&spec)?;
+        let out = sp::plan_splits(&h, version, shards, &splits, opts).await?;
+        if glob.json { return print_output(true, &out); }
+        for s in &out {
+            println!("{}: {} files ({}) in {} shards", s.name, s.files, ByteSize(s.bytes as u64), s.shards.len());
+        }
+        // every split records the same selection
+        if let Some(first) = out.first() { print_excluded(&first.selection.excluded); }
+        return Ok(());
+    }
+    // filtered, sampled or ordered plans carry their settings so they can be reproduced
+    if plan.is_set() {
+        let out = sp::plan_shards_with_selection(&h, version, shards, opts).await?;
+        return print_output(glob.json, &out);
+    }
     let shards = sp::plan_shards(&h, version, shards, opts).await?;
     print_output(glob.json, &shards)
 }
 
+// name=uri@version:weight, e.g. web=s3://bucket/web@12:0.6
+fn parse_mixture_source(spec: &str) -> Result<(String, String, i64, f64)> {
+    let bad = || anyhow::anyhow!("invalid source '{}', expected name=uri@version:weight", spec);
+    let (name, rest) = spec.split_once('=').ok_or_else(bad)?;
+    let (rest, weight) = rest.rsplit_once(':').ok_or_else(bad)?;
+    let (uri, version) = rest.rsplit_once('@').ok_or_else(bad)?;
+    Ok((name.trim().to_string(), uri.to_string(), version.parse().map_err(|_| bad())?, weight.parse().map_err(|_| bad())?))
+}
+
+async fn cmd_shard_mixture(glob: &GlobalArgs, sources: &[String], shards: u32, balance: &str, by: Option<String>, sticky_by: Option<String>, max_files: Option<usize>, exclude: Option<String>) -> Result<()> {
+    use shard_planner as sp;
+    let mode = match balance.to_ascii_lowercase().as_str() { "rows" => sp::BalanceMode::Rows, _ => sp::BalanceMode::Bytes };
+    let split_csv = |s: Option<String>| -> Vec<String> { s.map(|x| x.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()).unwrap_or_default() };
+    let mut opts = sp::ShardOptions { by: split_csv(by), sticky_by: split_csv(sticky_by), max_files_per_shard: max_files, balance: mode, ..Default::default() };
+    opts.exclude = load_exclusions(exclude).await?;
+    let mut srcs = Vec::with_capacity(sources.len());
+    for spec in sources {
+        let (name, uri, version, weight) = parse_mixture_source(spec)?;
+        srcs.push(sp::MixtureSource { name, table: core::load_table(&uri).await?, version, weight });
+    }
+    let out = sp::plan_mixture(&srcs, shards, opts).await?;
+    if glob.json { print_output(true, &out) } else {
+        for s in &out.sources {
+            println!("{}: target {:.3}, actual {:.3}, {} of {} files ({})", s.name, s.weight, s.share, s.selected_files, s.available_files, ByteSize(s.selected_bytes as u64));
+        }
+        println!("shards: {}", out.shards.len());
+        print_excluded(&out.selection.excluded);
+        Ok(())
+    }
+}
+
+async fn cmd_shard_tail(glob: &GlobalArgs, uri: &str, plan: &str, from: i64, to: i64, balance: &str, by: Option<String>, sticky_by: Option<String>, max_files: Option<usize>, constraints: &[String], exclude: Option<String>) -> Result<()> {
+    use shard_planner as sp;
+    let mode = match balance.to_ascii_lowercase().as_str() { "rows" => sp::BalanceMode::Rows, _ => sp::BalanceMode::Bytes };
+    let split_csv = |s: Option<String>| -> Vec<String> { s.map(|x| x.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()).unwrap_or_default() };
+    let mut opts = sp::ShardOptions { by: split_csv(by), sticky_by: split_csv(sticky_by), max_files_per_shard: max_files, balance: mode, ..Default::default() };
+    opts.constraints = constraints.iter().map(|c| sp::Constraint::parse(c)).collect::<Result<_>>()?;
+    opts.exclude = load_exclusions(exclude).await?;
+    // plans that recorded their filter/sample/order apply it to the new files as well
+    let base = match sp::PlanFile::read(std::path::Path::new(plan))? {
+        sp::PlanFile::Shards(v) => v,
+        sp::PlanFile::Plan(p) => {
+            if let Some(f) = &p.selection.filter { opts.filter = Some(core::Predicate::parse(f)?); }
+            opts.sample = p.selection.sample;
+            opts.sample_seed = p.selection.sample_seed;
+            opts.stratify_by = p.selection.stratify_by;
+            opts.order = p.order;
+            p.shards
+        }
+    };
+    let h = core::load_table(uri).await?;
+    let out = sp::plan_tail(&h, &base, from, to, opts).await?;
+    if glob.json { print_output(true, &out) } else {
+        let added: usize = out.added.iter().map(|s| s.files.len()).sum();
+        println!("v{}..v{}: +{} files assigned, {} removed", out.from, out.to, added, out.removed.len());
+        for r in &out.removed { println!("  removed {} (shard {})", r.path, r.shard); }
+        if !out.unplaced.is_empty() { println!("  {} files left unplaced by --max-files-per-shard", out.unplaced.len()); }
+        print_excluded(&out.excluded);
+        Ok(())
+    }
+}
+
+async fn cmd_shard_serve(glob: &GlobalArgs, plan: &str, addr: &str, journal: Option<String>, lease_ttl: &str, row_groups_from: Option<String>) -> Result<()> {
+    use shard_planner::coordinator::{serve, Coordinator};
+    let shards = shard_planner::PlanFile::read(std::path::Path::new(plan))?.into_shards();
+    let ttl = humantime::parse_duration(lease_ttl)?;
+    let journal = journal.unwrap_or_else(|| format!("{}.journal", plan));
+    // with a table to read footers from, every row group is leased on its own
+    let coord = match row_groups_from {
+        Some(table) => {
+            let paths: Vec<String> = shards.iter().flat_map(|s| s.files.iter().map(|f| f.path.clone())).collect();
+            let counts = core::row_group_counts(&table, &paths, glob.concurrency).await?;
+            Coordinator::by_row_group(&shards, ttl, &counts)
+        }
+        None => Coordinator::new(&shards, ttl),
+    };
+    let mut coord = coord.journaled(std::path::Path::new(&journal))?;
+    let st = coord.status(std::time::Instant::now());
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!(addr = %listener.local_addr()?, journal = %journal, total = st.total, done = st.done, fingerprint = %st.fingerprint, "serving shard plan");
+    serve(listener, std::sync::Arc::new(std::sync::Mutex::new(coord))).await
+}
 
+fn cmd_shard_resume(glob: &GlobalArgs, plan: &str, ledger: &str, failed: Option<String>) -> Result<()> {
+    use shard_planner as sp;
+    let shards = sp::PlanFile::read(std::path::Path::new(plan))?.into_shards();
+    let failed: Vec<u32> = failed.map(|x| x.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()).map(|t| t.parse::<u32>()).collect::<std::result::Result<_, _>>()).transpose()?.unwrap_or_default();
+    let ledger = sp::progress::ProgressLedger::open(std::path::Path::new(ledger), &shards)?;
+    let out = ledger.remaining(&failed)?;
+    if glob.json { print_output(true, &out) } else {
+        println!("plan {}: {} done, {} remaining", out.fingerprint, out.completed_files, out.remaining_files);
+        for s in &out.shards {
+            let inherited = if s.inherited_from.is_empty() { String::new() } else { format!(" (inherits {:?})", s.inherited_from) };
+            println!("  rank {}: {} files, {}{}", s.rank, s.files.len(), ByteSize(s.bytes.max(0) as u64), inherited);
+        }
+        Ok(())
+    }
+}
+
+async fn cmd_ledger(glob: &GlobalArgs, cmd: LedgerCommands) -> Result<()> {
+    use shard_planner::usage::{join_table, FileUse, UsageLedger, UsageRecord};
+    // joins against --table (or the one table the records name) at --version, default latest
+    async fn join(records: Vec<UsageRecord>, table: Option<String>, version: Option<i64>) -> Result<Vec<FileUse>> {
+        let named: std::collections::BTreeSet<&str> = records.iter().filter_map(|r| r.table.as_deref()).collect();
+        let table = match table {
+            Some(t) => t,
+            None if named.len() == 1 => named.into_iter().next().unwrap_or_default().to_string(),
+            None => return Ok(records.into_iter().map(|record| FileUse { record, removed: None }).collect()),
+        };
+        let h = core::load_table(&table).await?;
+        let version = match version { Some(v) => v, None => core::current_version(&h).await? };
+        join_table(records, &h, version).await
+    }
+    let show = |uses: &[FileUse]| {
+        for u in uses {
+            let r = &u.record;
+            let flag = if u.removed == Some(true) { "  [removed]" } else { "" };
+            println!("{}\t{}\tshard {}\t{}\t{}{}", r.recorded_at, r.run_id, r.shard, &r.plan[..12.min(r.plan.len())], r.path, flag);
+        }
+    };
+    match cmd {
+        LedgerCommands::Record { ledger, run, plan, table, version } => {
+            let shards = shard_planner::PlanFile::read(std::path::Path::new(&plan))?.into_shards();
+            let out = UsageLedger::open(&ledger).await?.record(&run, &shards, table.as_deref(), version).await?;
+            if glob.json { print_output(true, &out) } else {
+                println!("run {}: recorded {} files ({}) from plan {}", out.run_id, out.files, ByteSize(out.bytes as u64), out.plan);
+                Ok(())
+            }
+        }
+        LedgerCommands::RunsForFile { path, ledger, table, version } => {
+            let records = UsageLedger::open(&ledger).await?.runs_for_file(&path).await?;
+            let out = join(records, table, version).await?;
+            if glob.json { print_output(true, &out) } else {
+                let runs: std::collections::BTreeSet<&str> = out.iter().map(|u| u.record.run_id.as_str()).collect();
+                println!("{}: read by {} runs", path, runs.len());
+                show(&out);
+                Ok(())
+            }
+        }
+        LedgerCommands::FilesForRun { run, ledger, table, version } => {
+            let records = UsageLedger::open(&ledger).await?.files_for_run(&run).await?;
+            let out = join(records, table, version).await?;
+            if glob.json { print_output(true, &out) } else {
+                let removed = out.iter().filter(|u| u.removed == Some(true)).count();
+                println!("run {}: {} files, {} since removed", run, out.len(), removed);
+                show(&out);
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn cmd_pin(glob: &GlobalArgs, cmd: PinCommands) -> Result<()> {
+    use core::pins;
+    let now_ms = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0);
+    let show = |p: &core::Pin| {
+        let expires = match p.expires_at_ms {
+            Some(t) if t <= now_ms() => "expired".to_string(),
+            Some(t) => format!("expires in {}", humantime::format_duration(std::time::Duration::from_secs(((t - now_ms()) / 1000) as u64))),
+            None => "no expiry".to_string(),
+        };
+        println!("{}\tv{}\t{}\t{}", p.id, p.version, p.owner, expires);
+    };
+    match cmd {
+        PinCommands::Add { uri, version, owner, expires, registry } => {
+            let registry = registry.unwrap_or_else(|| pins::default_pin_registry(&uri));
+            let expires_at_ms = match expires {
+                None => None,
+                Some(e) => Some(match humantime::parse_duration(&e) {
+                    Ok(d) => now_ms() + d.as_millis() as i64,
+                    Err(_) => humantime::parse_rfc3339_weak(&e)
+                        .map_err(|_| anyhow::anyhow!("--expires '{}' is neither a duration nor an RFC 3339 time", e))?
+                        .duration_since(std::time::UNIX_EPOCH)?
+                        .as_millis() as i64,
+                }),
+            };
+            let pin = pins::add_pin(&registry, &uri, version, &owner, expires_at_ms).await?;
+            if glob.json { print_output(true, &pin) } else { show(&pin); Ok(()) }
+        }
+        PinCommands::List { uri, registry } => {
+            let registry = registry.unwrap_or_else(|| pins::default_pin_registry(&uri));
+            let out = pins::list_pins(&registry, &uri).await?;
+            if glob.json { print_output(true, &out) } else { out.iter().for_each(show); Ok(()) }
+        }
+        PinCommands::Remove { uri, version, owner, registry } => {
+            let registry = registry.unwrap_or_else(|| pins::default_pin_registry(&uri));
+            let out = pins::remove_pins(&registry, &uri, version, owner.as_deref()).await?;
+            if glob.json { print_output(true, &out) } else { out.iter().for_each(show); Ok(()) }
+        }
+    }
+}
+
+// <uri>[@version]; without a version the latest one is compared
+fn parse_table_at(spec: &str) -> Result<(String, Option<i64>)> {
+    match spec.rsplit_once('@') {
+        Some((uri, v)) if !uri.is_empty() && !v.contains('/') => {
+            let v = v.parse().map_err(|_| anyhow::anyhow!("invalid snapshot '{}', expected <uri>[@version]", spec))?;
+            Ok((uri.to_string(), Some(v)))
+        }
+        _ => Ok((spec.to_string(), None)),
+    }
+}
+
+async fn cmd_compare(glob: &GlobalArgs, a: &str, b: &str) -> Result<()> {
+    let (uri_a, version_a) = parse_table_at(a)?;
+    let (uri_b, version_b) = parse_table_at(b)?;
+    let ha = core::load_table(&uri_a).await?;
+    let hb = core::load_table(&uri_b).await?;
+    let out = core::compare_snapshots(&ha, version_a, &hb, version_b).await?;
+    if glob.json { print_output(true, &out)?; } else {
+        println!("a: {}@{} ({} files, {})", out.a.uri, out.a.version, out.files_a, ByteSize(out.bytes_a as u64));
+        println!("b: {}@{} ({} files, {})", out.b.uri, out.b.version, out.files_b, ByteSize(out.bytes_b as u64));
+        println!("matching files: {}", out.matching_files);
+        for f in &out.missing_in_b { println!("  only in a: {} ({})", f.path, ByteSize(f.size as u64)); }
+        for f in &out.missing_in_a { println!("  only in b: {} ({})", f.path, ByteSize(f.size as u64)); }
+        for m in &out.size_mismatches { println!("  size differs: {} ({} vs {})", m.path, m.size_a, m.size_b); }
+        for m in &out.stats_mismatches { println!("  stats differ: {} ({})", m.path, m.fields.join(", ")); }
+        for d in out.metadata_differences.iter().chain(&out.protocol_differences) {
+            if d.field == "schema" { println!("  schema differs"); } else { println!("  {}: {} vs {}", d.field, d.a, d.b); }
+        }
+        println!("{}", if out.equivalent { "equivalent" } else { "NOT equivalent" });
+    }
+    if !out.equivalent { std::process::exit(2); }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn cmd_lock(glob: &GlobalArgs, uri: &str, version: i64, out: &str, filter: Option<String>, exclude: Option<String>, etags: bool, content: bool) -> Result<()> {
+    let h = core::load_table(uri).await?;
+    let opts = core::LockOptions {
+        filter: filter.as_deref().map(core::Predicate::parse).transpose()?,
+        exclude: load_exclusions(exclude).await?,
+        etags,
+        content,
+        concurrency: glob.concurrency,
+    };
+    let lock = core::create_lock(&h, version, &opts).await?;
+    core::write_lock(&lock, out).await?;
+    if glob.json {
+        #[derive(serde::Serialize)]
+        struct LockOut<'a> { out: &'a str, table: &'a str, version: i64, integrity_hash: &'a str, #[serde(skip_serializing_if = "Option::is_none")] content_root: Option<&'a str>, files: usize, bytes: i64 }
+        print_output(true, &LockOut { out, table: &lock.table, version, integrity_hash: &lock.integrity_hash, content_root: lock.content_root.as_deref(), files: lock.total_files, bytes: lock.total_bytes })
+    } else {
+        println!("locked {}@{}: {} files ({}) -> {}", lock.table, lock.version, lock.total_files, ByteSize(lock.total_bytes as u64), out);
+        println!("integrity: {}", lock.integrity_hash);
+        if let Some(root) = &lock.content_root { println!("content root: {}", root); }
+        Ok(())
+    }
+}
+
+async fn cmd_verify_lock(glob: &GlobalArgs, path: &str, content: bool) -> Result<()> {
+    let lock = core::read_lock(path).await?;
+    let out = core::verify_lock(&lock, glob.concurrency).await?;
+    // re-hashing reads every byte; only worth it once the cheap checks pass
+    let content = if content && out.ok { Some(core::verify_lock_content(&lock, glob.concurrency).await?) } else { None };
+    let ok = out.ok && content.as_ref().map(|c| c.identical).unwrap_or(true);
+    if glob.json {
+        #[derive(serde::Serialize)]
+        struct VerifyLockOut<'a> { #[serde(flatten)] lock: &'a core::LockVerification, #[serde(skip_serializing_if = "Option::is_none")] content: Option<&'a core::ContentComparison> }
+        print_output(true, &VerifyLockOut { lock: &out, content: content.as_ref() })?;
+    } else {
+        println!("{}@{}: {} files checked", out.table, out.version, out.files_checked);
+        for p in &out.missing { println!("  missing: {}", p); }
+        for d in &out.size_changed { println!("  size changed: {} ({} -> {})", d.path, d.locked, d.actual); }
+        for d in &out.etag_changed { println!("  etag changed: {} ({} -> {})", d.path, d.locked, d.actual.as_deref().unwrap_or("none")); }
+        for u in &out.unreadable { println!("  unreadable: {} ({})", u.path, u.error); }
+        if out.integrity_hash_matches == Some(false) { println!("  the log at version {} no longer hashes to the locked integrity hash", out.version); }
+        if out.metadata_hash_matches == Some(false) { println!("  table metadata at version {} changed", out.version); }
+        if let Some(e) = &out.log_error { println!("  log not checked: {}", e); }
+        if let Some(c) = &content {
+            for p in &c.changed { println!("  content changed: {}", p); }
+            for p in &c.only_in_a { println!("  content not readable: {}", p); }
+            println!("  content: {} files re-hashed", c.files_compared);
+        }
+        println!("{}", if ok { "ok" } else { "DRIFTED" });
+    }
+    if !ok { std::process::exit(2); }
+    Ok(())
+}
+
+async fn cmd_verify_files(glob: &GlobalArgs, uri: &str, version: Option<i64>, deep: bool, exclude: Option<String>) -> Result<()> {
+    let h = core::load_table(uri).await?;
+    let version = match version { Some(v) => v, None => core::current_version(&h).await? };
+    let opts = core::VerifyOptions { deep, exclude: load_exclusions(exclude).await?, concurrency: glob.concurrency };
+    let out = core::verify_files(&h, version, &opts).await?;
+    if glob.json { print_output(true, &out)?; } else {
+        println!("{}@{}: {} files ({}) checked{}", out.table, out.version, out.files_checked, ByteSize(out.bytes_checked as u64), if out.deep { ", footers included" } else { "" });
+        for i in &out.issues {
+            let what = match i.problem {
+                core::FileProblem::Missing => "missing".to_string(),
+                core::FileProblem::SizeMismatch => format!("size {} in the log, {} in storage", i.expected_size, i.actual_size.unwrap_or(0)),
+                core::FileProblem::Unreadable => format!("unreadable: {}", i.error.as_deref().unwrap_or("")),
+                core::FileProblem::BadFooter => format!("bad footer: {}", i.error.as_deref().unwrap_or("")),
+            };
+            println!("  {}: {}", i.path, what);
+        }
+        println!("missing: {}, size mismatches: {}, unreadable: {}, bad footers: {}", out.missing, out.size_mismatches, out.unreadable, out.bad_footers);
+    }
+    if !out.ok { std::process::exit(2); }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn cmd_content_hash(glob: &GlobalArgs, uri: &str, version: Option<i64>, sample: Option<f64>, seed: u64, exclude: Option<String>, resume: Option<String>, out: Option<String>) -> Result<()> {
+    let h = core::load_table(uri).await?;
+    let version = match version { Some(v) => v, None => core::current_version(&h).await? };
+    let spinner = cli_core::pb_spinner(glob.progress && !glob.quiet && !glob.json, "hashing files");
+    let progress = spinner.clone().map(|pb| core::HashProgress(std::sync::Arc::new(move |n, bytes| pb.set_message(format!("hashed {} files ({})", n, ByteSize(bytes as u64))))));
+    let opts = core::ContentHashOptions { sample, sample_seed: seed, exclude: load_exclusions(exclude).await?, concurrency: glob.concurrency, resume, progress, ..Default::default() };
+    let manifest = core::content_hash(&h, version, &opts).await?;
+    if let Some(pb) = spinner { pb.finish_and_clear(); }
+    if let Some(dest) = &out { core::write_content_manifest(&manifest, dest).await?; }
+    if glob.json { return print_output(true, &manifest); }
+    println!("{}@{}: {} of {} files hashed ({})", manifest.table, manifest.version, manifest.files_hashed, manifest.files_total, ByteSize(manifest.bytes_hashed as u64));
+    println!("merkle root: {}", manifest.merkle_root);
+    if let Some(dest) = &out { println!("wrote {}", dest); }
+    Ok(())
+}
+
+async fn cmd_content_diff(glob: &GlobalArgs, a: &str, b: &str) -> Result<()> {
+    let out = core::compare_content(&core::read_content_manifest(a).await?, &core::read_content_manifest(b).await?);
+    if glob.json { print_output(true, &out)?; } else {
+        println!("a: {}", out.merkle_root_a);
+        println!("b: {}", out.merkle_root_b);
+        println!("files compared: {}", out.files_compared);
+        for p in &out.changed { println!("  content differs: {}", p); }
+        for p in &out.only_in_a { println!("  only in a: {}", p); }
+        for p in &out.only_in_b { println!("  only in b: {}", p); }
+        println!("{}", if out.identical { "identical" } else { "NOT identical" });
+    }
+    if !out.identical { std::process::exit(2); }
+    Ok(())
+}
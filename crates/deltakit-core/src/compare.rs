@@ -0,0 +1,165 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{current_version, list_active_files, read_table_state, AddFileLite, DeltaTableHandle};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotRef {
+    pub uri: String,
+    pub version: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRef {
+    pub path: String,
+    pub size: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SizeMismatch {
+    pub path: String,
+    pub size_a: i64,
+    pub size_b: i64,
+}
+
+/// A file present on both sides with the same size whose log entries disagree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsMismatch {
+    pub path: String,
+    /// `num_records`, `min_values.<col>`, `max_values.<col>`, `null_count.<col>`, `stats` or `partition_values`
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldDifference {
+    pub field: String,
+    pub a: Value,
+    pub b: Value,
+}
+
+/// Two snapshots matched file by file on relative path, size and stats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompareReport {
+    pub a: SnapshotRef,
+    pub b: SnapshotRef,
+    /// no missing files, no mismatches and no schema, metadata or protocol differences
+    pub equivalent: bool,
+    pub files_a: usize,
+    pub files_b: usize,
+    pub bytes_a: i64,
+    pub bytes_b: i64,
+    pub matching_files: usize,
+    pub missing_in_a: Vec<FileRef>,
+    pub missing_in_b: Vec<FileRef>,
+    pub size_mismatches: Vec<SizeMismatch>,
+    pub stats_mismatches: Vec<StatsMismatch>,
+    pub schema_equal: bool,
+    /// schema, partition columns, format and configuration; table id and creation time are ignored
+    pub metadata_differences: Vec<FieldDifference>,
+    pub protocol_differences: Vec<FieldDifference>,
+}
+
+/// Compares two tables (or two versions of one); None means the latest version.
+pub async fn compare_snapshots(a: &DeltaTableHandle, version_a: Option<i64>, b: &DeltaTableHandle, version_b: Option<i64>) -> Result<CompareReport> {
+    let version_a = match version_a { Some(v) => v, None => current_version(a).await? };
+    let version_b = match version_b { Some(v) => v, None => current_version(b).await? };
+    let files_a = list_active_files(a, Some(version_a)).await?;
+    let files_b = list_active_files(b, Some(version_b)).await?;
+    let state_a = read_table_state(a, version_a).await?;
+    let state_b = read_table_state(b, version_b).await?;
+
+    let map_a: BTreeMap<&str, &AddFileLite> = files_a.iter().map(|f| (f.path.as_str(), f)).collect();
+    let map_b: BTreeMap<&str, &AddFileLite> = files_b.iter().map(|f| (f.path.as_str(), f)).collect();
+    let missing = |from: &BTreeMap<&str, &AddFileLite>, other: &BTreeMap<&str, &AddFileLite>| -> Vec<FileRef> {
+        from.values().filter(|f| !other.contains_key(f.path.as_str())).map(|f| FileRef { path: f.path.clone(), size: f.size }).collect()
+    };
+    let missing_in_a = missing(&map_b, &map_a);
+    let missing_in_b = missing(&map_a, &map_b);
+    let mut size_mismatches = Vec::new();
+    let mut stats_mismatches = Vec::new();
+    let mut matching_files = 0;
+    for (path, fa) in &map_a {
+        let Some(fb) = map_b.get(path) else { continue };
+        if fa.size != fb.size {
+            size_mismatches.push(SizeMismatch { path: path.to_string(), size_a: fa.size, size_b: fb.size });
+            continue;
+        }
+        let fields = file_differences(fa, fb);
+        if fields.is_empty() { matching_files += 1; } else { stats_mismatches.push(StatsMismatch { path: path.to_string(), fields }); }
+    }
+
+    let mut metadata_differences = Vec::new();
+    let schema = |m: &Value| -> Value {
+        let raw = m.get("schemaString").and_then(|s| s.as_str()).unwrap_or("");
+        serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+    };
+    let schema_equal = schema(&state_a.metadata) == schema(&state_b.metadata);
+    if !schema_equal {
+        metadata_differences.push(FieldDifference { field: "schema".to_string(), a: schema(&state_a.metadata), b: schema(&state_b.metadata) });
+    }
+    for key in ["partitionColumns", "format"] {
+        push_if_different(&mut metadata_differences, key.to_string(), state_a.metadata.get(key), state_b.metadata.get(key));
+    }
+    let conf = |m: &Value| m.get("configuration").and_then(|c| c.as_object()).cloned().unwrap_or_default();
+    let (conf_a, conf_b) = (conf(&state_a.metadata), conf(&state_b.metadata));
+    for key in conf_a.keys().chain(conf_b.keys()).collect::<BTreeSet<_>>() {
+        push_if_different(&mut metadata_differences, format!("configuration.{}", key), conf_a.get(key), conf_b.get(key));
+    }
+    let mut protocol_differences = Vec::new();
+    let keys = |p: &Value| p.as_object().map(|o| o.keys().cloned().collect::<Vec<_>>()).unwrap_or_default();
+    for key in keys(&state_a.protocol).into_iter().chain(keys(&state_b.protocol)).collect::<BTreeSet<_>>() {
+        push_if_different(&mut protocol_differences, key.clone(), state_a.protocol.get(&key), state_b.protocol.get(&key));
+    }
+
+    let equivalent = missing_in_a.is_empty()
+        && missing_in_b.is_empty()
+        && size_mismatches.is_empty()
+        && stats_mismatches.is_empty()
+        && metadata_differences.is_empty()
+        && protocol_differences.is_empty();
+    Ok(CompareReport {
+        a: SnapshotRef { uri: a.uri.clone(), version: version_a },
+        b: SnapshotRef { uri: b.uri.clone(), version: version_b },
+        equivalent,
+        files_a: files_a.len(),
+        files_b: files_b.len(),
+        bytes_a: files_a.iter().map(|f| f.size).sum(),
+        bytes_b: files_b.iter().map(|f| f.size).sum(),
+        matching_files,
+        missing_in_a,
+        missing_in_b,
+        size_mismatches,
+        stats_mismatches,
+        schema_equal,
+        metadata_differences,
+        protocol_differences,
+    })
+}
+
+fn push_if_different(out: &mut Vec<FieldDifference>, field: String, a: Option<&Value>, b: Option<&Value>) {
+    if a != b {
+        out.push(FieldDifference { field, a: a.cloned().unwrap_or(Value::Null), b: b.cloned().unwrap_or(Value::Null) });
+    }
+}
+
+fn file_differences(a: &AddFileLite, b: &AddFileLite) -> Vec<String> {
+    let mut out = Vec::new();
+    if a.partition_values != b.partition_values { out.push("partition_values".to_string()); }
+    let (sa, sb) = match (&a.stats, &b.stats) {
+        (Some(sa), Some(sb)) => (sa, sb),
+        (None, None) => return out,
+        _ => { out.push("stats".to_string()); return out; }
+    };
+    if sa.num_records != sb.num_records { out.push("num_records".to_string()); }
+    fn keyed<V: PartialEq>(out: &mut Vec<String>, name: &str, a: &BTreeMap<String, V>, b: &BTreeMap<String, V>) {
+        for k in a.keys().chain(b.keys()).collect::<BTreeSet<_>>() {
+            if a.get(k) != b.get(k) { out.push(format!("{}.{}", name, k)); }
+        }
+    }
+    keyed(&mut out, "min_values", &sa.min_values, &sb.min_values);
+    keyed(&mut out, "max_values", &sa.max_values, &sb.max_values);
+    keyed(&mut out, "null_count", &sa.null_count, &sb.null_count);
+    out
+}
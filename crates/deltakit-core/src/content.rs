@@ -0,0 +1,285 @@
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use storage::{make_object_store, object_path_from_url, parse_uri, StorageOptions};
+
+use crate::{list_active_files, DeltaTableHandle, ExclusionList, Lockfile, TableRoot};
+
+const DEFAULT_HASH_CONCURRENCY: usize = 8;
+const DEFAULT_CHECKPOINT_EVERY: usize = 100;
+
+/// blake3 of one data file's bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileHash {
+    pub path: String,
+    pub size: i64,
+    pub blake3: String,
+}
+
+/// Content hashes of (a sample of) the files of one version, committed to by `merkle_root`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentManifest {
+    pub table: String,
+    pub version: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<f64>,
+    #[serde(default)]
+    pub sample_seed: u64,
+    /// active files of the version, after exclusions
+    pub files_total: usize,
+    pub files_hashed: usize,
+    pub bytes_hashed: i64,
+    pub merkle_root: String,
+    /// sorted by path
+    pub files: Vec<FileHash>,
+}
+
+/// Called with (files hashed, bytes hashed) so far.
+#[derive(Clone)]
+pub struct HashProgress(pub Arc<dyn Fn(usize, i64) + Send + Sync>);
+
+impl std::fmt::Debug for HashProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("HashProgress") }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContentHashOptions {
+    /// hash a deterministic fraction of the files, picked by path and `sample_seed`
+    pub sample: Option<f64>,
+    pub sample_seed: u64,
+    pub exclude: ExclusionList,
+    pub concurrency: Option<usize>,
+    /// state file (local path or URI) holding finished hashes; an interrupted run picks up from it
+    pub resume: Option<String>,
+    /// files between state saves
+    pub checkpoint_every: Option<usize>,
+    pub progress: Option<HashProgress>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HashState {
+    table: String,
+    version: i64,
+    files: Vec<FileHash>,
+}
+
+/// Paths present in both manifests whose bytes differ, and paths hashed on one side only.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContentComparison {
+    pub identical: bool,
+    pub merkle_root_a: String,
+    pub merkle_root_b: String,
+    pub files_compared: usize,
+    pub changed: Vec<String>,
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+}
+
+fn sampled(seed: u64, path: &str, fraction: f64) -> bool {
+    let h = blake3::Hasher::new().update(&seed.to_le_bytes()).update(path.as_bytes()).finalize();
+    let x = u64::from_le_bytes(h.as_bytes()[..8].try_into().unwrap_or([0; 8]));
+    (x as f64 / u64::MAX as f64) < fraction
+}
+
+/// Root of a binary Merkle tree over the files sorted by path. Leaves commit to path, size and
+/// content hash; an odd node is carried up unchanged. An empty list hashes to blake3 of nothing.
+pub fn merkle_root(files: &[FileHash]) -> String {
+    let mut sorted: Vec<&FileHash> = files.iter().collect();
+    sorted.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut level: Vec<blake3::Hash> = sorted
+        .iter()
+        .map(|f| {
+            let mut h = blake3::Hasher::new();
+            h.update(&[0u8]).update(&(f.path.len() as u64).to_le_bytes()).update(f.path.as_bytes()).update(&f.size.to_le_bytes()).update(f.blake3.as_bytes());
+            h.finalize()
+        })
+        .collect();
+    if level.is_empty() { return blake3::hash(b"").to_hex().to_string(); }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [l, r] => blake3::Hasher::new().update(&[1u8]).update(l.as_bytes()).update(r.as_bytes()).finalize(),
+                [one] => *one,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0].to_hex().to_string()
+}
+
+async fn load_state(uri: &str) -> Result<Option<HashState>> {
+    let store = make_object_store(uri, &StorageOptions::default()).await?;
+    match storage::get_if_exists(store, &object_path_from_url(&parse_uri(uri)?.url)).await? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data).map_err(|e| anyhow!("hash state {}: {}", uri, e))?)),
+        None => Ok(None),
+    }
+}
+
+async fn save_state(uri: &str, state: &HashState) -> Result<()> {
+    let store = make_object_store(uri, &StorageOptions::default()).await?;
+    storage::put_bytes(store, &object_path_from_url(&parse_uri(uri)?.url), serde_json::to_vec(state)?.into()).await
+}
+
+/// Streams the files at `paths` of `table_uri` through blake3, `concurrency` at a time, in input order.
+///
+/// Each hash covers the bytes actually read, so its size may disagree with the log; None when the
+/// file is missing, Err when it cannot be read. Only setting up the stores fails the whole call.
+pub(crate) async fn hash_files(table_uri: &str, paths: &[String], concurrency: usize) -> Result<Vec<Result<Option<FileHash>>>> {
+    let root = TableRoot::new(table_uri)?;
+    let store = root.store().await?;
+    let jobs = paths.iter().map(|path| {
+        let (root, store) = (&root, store.clone());
+        async move {
+            let (store, loc) = root.resolve(store, path).await?;
+            Ok(hash_one(store, &loc, path).await)
+        }
+    });
+    stream::iter(jobs).buffered(concurrency.max(1)).try_collect().await
+}
+
+async fn hash_one(store: Arc<object_store::DynObjectStore>, loc: &object_store::path::Path, path: &str) -> Result<Option<FileHash>> {
+    let mut body = match store.get(loc).await {
+        Ok(r) => r.into_stream(),
+        Err(object_store::Error::NotFound { .. }) => return Ok(None),
+        Err(e) => return Err(anyhow!("reading {}: {}", path, e)),
+    };
+    let mut hasher = blake3::Hasher::new();
+    let mut read = 0i64;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| anyhow!("reading {}: {}", path, e))?;
+        read += chunk.len() as i64;
+        hasher.update(&chunk);
+    }
+    Ok(Some(FileHash { path: path.to_string(), size: read, blake3: hasher.finalize().to_hex().to_string() }))
+}
+
+/// The hash of a file read back at the size the log records, or why it was not.
+pub(crate) fn logged_size(path: &str, size: i64, hashed: Result<Option<FileHash>>) -> std::result::Result<FileHash, String> {
+    match hashed {
+        Ok(Some(f)) if f.size == size => Ok(f),
+        Ok(Some(f)) => Err(format!("{} has {} bytes, the log says {}", path, f.size, size)),
+        Ok(None) => Err(format!("{} does not exist", path)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn content_hash(h: &DeltaTableHandle, version: i64, opts: &ContentHashOptions) -> Result<ContentManifest> {
+    if let Some(s) = opts.sample { if !(s > 0.0 && s <= 1.0) { return Err(anyhow!("sample must be in (0, 1], got {}", s)); } }
+    let (files, _) = opts.exclude.apply(list_active_files(h, Some(version)).await?);
+    let files_total = files.len();
+    let todo: Vec<(String, i64)> = files
+        .into_iter()
+        .filter(|f| match opts.sample { Some(s) => sampled(opts.sample_seed, &f.path, s), None => true })
+        .map(|f| (f.path, f.size))
+        .collect();
+
+    let mut done: BTreeMap<String, FileHash> = BTreeMap::new();
+    if let Some(uri) = &opts.resume {
+        if let Some(state) = load_state(uri).await? {
+            if state.table.trim_end_matches('/') != h.uri.trim_end_matches('/') || state.version != version {
+                return Err(anyhow!("hash state {} is for {}@{}, not {}@{}", uri, state.table, state.version, h.uri, version));
+            }
+            let wanted: BTreeMap<&str, i64> = todo.iter().map(|(p, s)| (p.as_str(), *s)).collect();
+            done.extend(state.files.into_iter().filter(|f| wanted.get(f.path.as_str()) == Some(&f.size)).map(|f| (f.path.clone(), f)));
+        }
+    }
+    let remaining: Vec<(String, i64)> = todo.iter().filter(|(p, _)| !done.contains_key(p)).cloned().collect();
+    let concurrency = opts.concurrency.unwrap_or(DEFAULT_HASH_CONCURRENCY);
+    let report = |done: &BTreeMap<String, FileHash>| {
+        if let Some(p) = &opts.progress { (p.0)(done.len(), done.values().map(|f| f.size).sum()); }
+    };
+    report(&done);
+    // a bad file does not stop the run; what did hash is saved before the failures are reported
+    let mut failed = Vec::new();
+    for batch in remaining.chunks(opts.checkpoint_every.unwrap_or(DEFAULT_CHECKPOINT_EVERY).max(1)) {
+        let paths: Vec<String> = batch.iter().map(|(p, _)| p.clone()).collect();
+        for ((path, size), hashed) in batch.iter().zip(hash_files(&h.uri, &paths, concurrency).await?) {
+            match logged_size(path, *size, hashed) {
+                Ok(f) => { done.insert(f.path.clone(), f); }
+                Err(e) => failed.push(e),
+            }
+        }
+        report(&done);
+        if let Some(uri) = &opts.resume {
+            save_state(uri, &HashState { table: h.uri.clone(), version, files: done.values().cloned().collect() }).await?;
+        }
+    }
+    if !failed.is_empty() { return Err(anyhow!("{} of {} files could not be hashed: {}", failed.len(), todo.len(), failed.join("; "))); }
+
+    let files: Vec<FileHash> = done.into_values().collect();
+    Ok(ContentManifest {
+        table: h.uri.clone(),
+        version,
+        sample: opts.sample,
+        sample_seed: opts.sample_seed,
+        files_total,
+        files_hashed: files.len(),
+        bytes_hashed: files.iter().map(|f| f.size).sum(),
+        merkle_root: merkle_root(&files),
+        files,
+    })
+}
+
+pub fn compare_content(a: &ContentManifest, b: &ContentManifest) -> ContentComparison {
+    let map_a: BTreeMap<&str, &FileHash> = a.files.iter().map(|f| (f.path.as_str(), f)).collect();
+    let map_b: BTreeMap<&str, &FileHash> = b.files.iter().map(|f| (f.path.as_str(), f)).collect();
+    let mut out = ContentComparison { merkle_root_a: a.merkle_root.clone(), merkle_root_b: b.merkle_root.clone(), ..Default::default() };
+    for (path, fa) in &map_a {
+        match map_b.get(path) {
+            Some(fb) => {
+                out.files_compared += 1;
+                if fa.size != fb.size || fa.blake3 != fb.blake3 { out.changed.push(path.to_string()); }
+            }
+            None => out.only_in_a.push(path.to_string()),
+        }
+    }
+    out.only_in_b = map_b.keys().filter(|p| !map_a.contains_key(*p)).map(|p| p.to_string()).collect();
+    out.identical = out.changed.is_empty() && out.only_in_a.is_empty() && out.only_in_b.is_empty();
+    out
+}
+
+impl ContentManifest {
+    /// Content hashes recorded in a lockfile written with `LockOptions::content`.
+    pub fn from_lock(lock: &Lockfile) -> Result<ContentManifest> {
+        let files = lock
+            .files
+            .iter()
+            .map(|f| f.blake3.clone().map(|blake3| FileHash { path: f.path.clone(), size: f.size, blake3 }))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("lockfile for {}@{} has no content hashes", lock.table, lock.version))?;
+        Ok(ContentManifest {
+            table: lock.table.clone(),
+            version: lock.version,
+            sample: None,
+            sample_seed: 0,
+            files_total: lock.total_files,
+            files_hashed: files.len(),
+            bytes_hashed: files.iter().map(|f| f.size).sum(),
+            merkle_root: merkle_root(&files),
+            files,
+        })
+    }
+}
+
+pub async fn write_content_manifest(m: &ContentManifest, dest: &str) -> Result<()> {
+    let store = make_object_store(dest, &StorageOptions::default()).await?;
+    storage::put_bytes(store, &object_path_from_url(&parse_uri(dest)?.url), serde_json::to_vec_pretty(m)?.into()).await
+}
+
+/// Reads a content manifest, or the content hashes of a lockfile.
+pub async fn read_content_manifest(src: &str) -> Result<ContentManifest> {
+    let store = make_object_store(src, &StorageOptions::default()).await?;
+    let data = storage::get_bytes(store, &object_path_from_url(&parse_uri(src)?.url)).await.map_err(|e| anyhow!("reading {}: {}", src, e))?;
+    let value: serde_json::Value = serde_json::from_slice(&data).map_err(|e| anyhow!("{}: {}", src, e))?;
+    if value.get("lock_format").is_some() {
+        let lock: Lockfile = serde_json::from_value(value).map_err(|e| anyhow!("lockfile {}: {}", src, e))?;
+        return ContentManifest::from_lock(&lock);
+    }
+    let m: ContentManifest = serde_json::from_value(value).map_err(|e| anyhow!("content manifest {}: {}", src, e))?;
+    if merkle_root(&m.files) != m.merkle_root { return Err(anyhow!("content manifest {}: merkle root does not match its files", src)); }
+    Ok(m)
+}
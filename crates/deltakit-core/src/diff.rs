@@ -0,0 +1,406 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use storage::{make_object_store, StorageOptions};
+
+use crate::{list_active_files, parse_stats, read_log, read_table_state, AddFileLite, DeltaTableHandle, TableRoot};
+
+/// Table property that turns on the change data feed.
+pub const CHANGE_DATA_FEED_PROPERTY: &str = "delta.enableChangeDataFeed";
+
+/// Net change between two versions. The headline counts cover data changes only; files
+/// added or removed by rewrites (`dataChange=false`, e.g. OPTIMIZE) are counted under `rewrite_*`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiffReport {
+    pub from: i64,
+    pub to: i64,
+    pub files_added: usize,
+    pub files_removed: usize,
+    pub bytes_added: i64,
+    pub bytes_removed: i64,
+    #[serde(default)]
+    pub rows_added: u64,
+    #[serde(default)]
+    pub rows_removed: u64,
+    /// rows_added - rows_removed
+    #[serde(default)]
+    pub row_delta: i64,
+    /// data files added, removed or given a new deletion vector without `numRecords`; their rows are not counted
+    #[serde(default)]
+    pub files_without_rows: usize,
+    /// files live at both versions whose deletion vector changed; the rows it newly marks
+    /// deleted count in `rows_removed` (rows it no longer marks, in `rows_added`)
+    #[serde(default)]
+    pub deletion_vector_updates: usize,
+    #[serde(default)]
+    pub rewrite_files_added: usize,
+    #[serde(default)]
+    pub rewrite_files_removed: usize,
+    #[serde(default)]
+    pub rewrite_bytes_added: i64,
+    #[serde(default)]
+    pub rewrite_bytes_removed: i64,
+    /// every added file, with `detail`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<DiffFile>,
+    /// every removed file, with `detail`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<DiffFile>,
+    /// per-partition breakdown over the `by` columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<PartitionDiff>,
+    /// row changes by type, when the change data feed was on for any commit in the range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_data: Option<ChangeDataSummary>,
+}
+
+/// Row changes from the change data feed. Commits with `cdc` files are counted from the
+/// files' `_change_type` column; other commits count added rows as inserts and removed rows as deletes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeDataSummary {
+    pub insert: u64,
+    pub update_preimage: u64,
+    pub update_postimage: u64,
+    pub delete: u64,
+    pub cdc_files: usize,
+    /// commits whose changes were derived from add/remove stats
+    pub derived_commits: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffFile {
+    pub path: String,
+    pub size: i64,
+    /// live rows: `numRecords` less the rows the file's deletion vector marks deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub partition_values: BTreeMap<String, Option<String>>,
+    /// false when the file came or went with a rewrite
+    pub data_change: bool,
+}
+
+/// Changes within one combination of the `by` columns; rows count data changes only.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartitionDiff {
+    pub partition: BTreeMap<String, Option<String>>,
+    pub files_added: usize,
+    pub files_removed: usize,
+    pub bytes_added: i64,
+    pub bytes_removed: i64,
+    pub rows_added: u64,
+    pub rows_removed: u64,
+    pub row_delta: i64,
+    pub rewrite_files_added: usize,
+    pub rewrite_files_removed: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// list every added and removed file
+    pub detail: bool,
+    /// partition columns to break the changes down by
+    pub by: Vec<String>,
+}
+
+pub async fn diff_versions(h: &DeltaTableHandle, from: i64, to: i64) -> Result<DiffReport> {
+    diff_versions_with(h, from, to, &DiffOptions::default()).await
+}
+
+pub async fn diff_versions_with(h: &DeltaTableHandle, from: i64, to: i64, opts: &DiffOptions) -> Result<DiffReport> {
+    if to < from { return Err(anyhow!("to must be >= from")); }
+    let files_from = list_active_files(h, Some(from)).await?;
+    let files_to = list_active_files(h, Some(to)).await?;
+    if let Some(col) = opts.by.iter().find(|c| files_from.iter().chain(&files_to).any(|f| !f.partition_values.contains_key(*c))) {
+        return Err(anyhow!("'{}' is not a partition column of {}", col, h.uri));
+    }
+
+    // the last add/remove of a path within (from, to] says whether it changed data
+    let mut add_dc: HashMap<String, bool> = HashMap::new();
+    let mut remove_dc: HashMap<String, bool> = HashMap::new();
+    let mut change_data = None;
+    if to > from {
+        let root = TableRoot::new(&h.uri)?;
+        let log = read_log(h, Some(to)).await?;
+        for c in log.commits.iter().filter(|c| c.version > from) {
+            for a in &c.actions {
+                for (key, map) in [("add", &mut add_dc), ("remove", &mut remove_dc)] {
+                    if let Some(p) = a.get(key).and_then(|x| x.get("path")).and_then(|p| p.as_str()) {
+                        let dc = a[key].get("dataChange").and_then(|d| d.as_bool()).unwrap_or(true);
+                        map.insert(root.canonical(p), dc);
+                    }
+                }
+            }
+        }
+        change_data = change_data_summary(h, &root, &log.commits, from, &files_from).await?;
+    }
+
+    let map_from: HashMap<&str, &AddFileLite> = files_from.iter().map(|f| (f.path.as_str(), f)).collect();
+    let map_to: HashMap<&str, &AddFileLite> = files_to.iter().map(|f| (f.path.as_str(), f)).collect();
+    let mut added: Vec<DiffFile> = files_to.iter().filter(|f| !map_from.contains_key(f.path.as_str()))
+        .map(|f| diff_file(f, add_dc.get(&f.path).copied().unwrap_or(true))).collect();
+    let mut removed: Vec<DiffFile> = files_from.iter().filter(|f| !map_to.contains_key(f.path.as_str()))
+        .map(|f| diff_file(f, remove_dc.get(&f.path).copied().unwrap_or(true))).collect();
+    added.sort_by(|a, b| a.path.cmp(&b.path));
+    removed.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut report = DiffReport { from, to, ..Default::default() };
+    let mut parts: BTreeMap<Vec<Option<String>>, PartitionDiff> = BTreeMap::new();
+    for (f, is_add) in added.iter().map(|f| (f, true)).chain(removed.iter().map(|f| (f, false))) {
+        let part = partition_entry(&mut parts, &opts.by, &f.partition_values);
+        let rows = f.rows.unwrap_or(0);
+        match (is_add, f.data_change) {
+            (true, true) => {
+                report.files_added += 1; report.bytes_added += f.size; report.rows_added += rows;
+                if let Some(p) = part { p.files_added += 1; p.bytes_added += f.size; p.rows_added += rows; }
+            }
+            (false, true) => {
+                report.files_removed += 1; report.bytes_removed += f.size; report.rows_removed += rows;
+                if let Some(p) = part { p.files_removed += 1; p.bytes_removed += f.size; p.rows_removed += rows; }
+            }
+            (true, false) => {
+                report.rewrite_files_added += 1; report.rewrite_bytes_added += f.size;
+                if let Some(p) = part { p.rewrite_files_added += 1; }
+            }
+            (false, false) => {
+                report.rewrite_files_removed += 1; report.rewrite_bytes_removed += f.size;
+                if let Some(p) = part { p.rewrite_files_removed += 1; }
+            }
+        }
+    }
+    report.files_without_rows = added.iter().chain(&removed).filter(|f| f.data_change && f.rows.is_none()).count();
+    // a deletion-vector update keeps the path but changes which of its rows are live
+    for f in &files_to {
+        let Some(old) = map_from.get(f.path.as_str()) else { continue };
+        if old.deletion_vector == f.deletion_vector || !add_dc.get(&f.path).copied().unwrap_or(true) { continue; }
+        report.deletion_vector_updates += 1;
+        let (Some(before), Some(after)) = (old.live_rows(), f.live_rows()) else {
+            report.files_without_rows += 1;
+            continue;
+        };
+        let part = partition_entry(&mut parts, &opts.by, &f.partition_values);
+        if before >= after {
+            report.rows_removed += before - after;
+            if let Some(p) = part { p.rows_removed += before - after; }
+        } else {
+            report.rows_added += after - before;
+            if let Some(p) = part { p.rows_added += after - before; }
+        }
+    }
+    report.row_delta = report.rows_added as i64 - report.rows_removed as i64;
+    report.partitions = parts.into_values().map(|mut p| { p.row_delta = p.rows_added as i64 - p.rows_removed as i64; p }).collect();
+    report.change_data = change_data;
+    if opts.detail {
+        report.added = added;
+        report.removed = removed;
+    }
+    Ok(report)
+}
+
+// None when `by` is empty
+fn partition_entry<'a>(parts: &'a mut BTreeMap<Vec<Option<String>>, PartitionDiff>, by: &[String], values: &BTreeMap<String, Option<String>>) -> Option<&'a mut PartitionDiff> {
+    if by.is_empty() { return None; }
+    let key: Vec<Option<String>> = by.iter().map(|c| values.get(c).cloned().flatten()).collect();
+    Some(parts.entry(key).or_insert_with(|| PartitionDiff {
+        partition: by.iter().map(|c| (c.clone(), values.get(c).cloned().flatten())).collect(),
+        ..Default::default()
+    }))
+}
+
+fn diff_file(f: &AddFileLite, data_change: bool) -> DiffFile {
+    DiffFile {
+        path: f.path.clone(),
+        size: f.size,
+        rows: f.live_rows(),
+        partition_values: f.partition_values.clone(),
+        data_change,
+    }
+}
+
+/// What one commit did, as recorded in the log.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommitDiff {
+    pub version: i64,
+    pub timestamp_ms: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    /// `commitInfo.operationMetrics` as written by the engine
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub operation_metrics: BTreeMap<String, serde_json::Value>,
+    pub files_added: usize,
+    pub files_removed: usize,
+    pub bytes_added: i64,
+    pub bytes_removed: i64,
+    /// false when every add and remove of the commit is a rewrite
+    pub data_change: bool,
+    /// paths added, with `detail`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<String>,
+    /// paths removed, with `detail`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+/// A file added and removed again inside the range; invisible to a snapshot diff.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransientFile {
+    pub path: String,
+    pub size: i64,
+    pub added_in: i64,
+    pub removed_in: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayDiff {
+    pub from: i64,
+    pub to: i64,
+    /// one entry per version in (from, to], oldest first
+    pub commits: Vec<CommitDiff>,
+    pub transient: Vec<TransientFile>,
+}
+
+/// Walks the commits after `from` up to `to`. Every commit in the range must still be in the log.
+pub async fn diff_commits(h: &DeltaTableHandle, from: i64, to: i64, opts: &DiffOptions) -> Result<ReplayDiff> {
+    if to < from { return Err(anyhow!("to must be >= from")); }
+    // sizes of files removed without a `size` come from the snapshot at `from` or a later add
+    let mut sizes: HashMap<String, i64> = list_active_files(h, Some(from)).await?.into_iter().map(|f| (f.path, f.size)).collect();
+    // files live at `from` are never transient, even when a deletion-vector update re-adds them
+    let mut live_at_from: HashSet<String> = sizes.keys().cloned().collect();
+    let root = TableRoot::new(&h.uri)?;
+    let log = read_log(h, Some(to)).await?;
+    let commits: Vec<_> = log.commits.iter().filter(|c| c.version > from).collect();
+    if let Some(missing) = (from + 1..=to).find(|v| !commits.iter().any(|c| c.version == *v)) {
+        return Err(anyhow!("commit {} is no longer in the log of {}; replay needs every commit after {}", missing, h.uri, from));
+    }
+
+    let mut out = ReplayDiff { from, to, ..Default::default() };
+    let mut added_in: HashMap<String, i64> = HashMap::new();
+    for c in commits {
+        let mut cd = CommitDiff { version: c.version, timestamp_ms: c.timestamp_ms, ..Default::default() };
+        let mut any_data_change = false;
+        let mut removed_from_live: HashSet<String> = HashSet::new();
+        for a in c.removes_first() {
+            if let Some(info) = a.get("commitInfo") {
+                cd.operation = info.get("operation").and_then(|o| o.as_str()).map(|o| o.to_string());
+                if let Some(m) = info.get("operationMetrics").and_then(|m| m.as_object()) {
+                    cd.operation_metrics = m.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                }
+            }
+            if let Some(add) = a.get("add") {
+                let Some(path) = add.get("path").and_then(|p| p.as_str()) else { continue };
+                let path = root.canonical(path);
+                let size = add.get("size").and_then(|s| s.as_i64()).unwrap_or(0);
+                any_data_change |= add.get("dataChange").and_then(|d| d.as_bool()).unwrap_or(true);
+                cd.files_added += 1;
+                cd.bytes_added += size;
+                if opts.detail { cd.added.push(path.clone()); }
+                sizes.insert(path.clone(), size);
+                if removed_from_live.contains(&path) || live_at_from.contains(&path) {
+                    live_at_from.insert(path);
+                    continue;
+                }
+                // re-added in the commit that removed it (a deletion-vector update): the same file, still live
+                let since = match out.transient.iter().position(|t| t.path == path && t.removed_in == c.version) {
+                    Some(i) => out.transient.remove(i).added_in,
+                    None => c.version,
+                };
+                added_in.insert(path, since);
+            }
+            if let Some(rm) = a.get("remove") {
+                let Some(path) = rm.get("path").and_then(|p| p.as_str()) else { continue };
+                let path = root.canonical(path);
+                let size = rm.get("size").and_then(|s| s.as_i64()).or_else(|| sizes.get(&path).copied()).unwrap_or(0);
+                any_data_change |= rm.get("dataChange").and_then(|d| d.as_bool()).unwrap_or(true);
+                cd.files_removed += 1;
+                cd.bytes_removed += size;
+                if opts.detail { cd.removed.push(path.clone()); }
+                if live_at_from.remove(&path) { removed_from_live.insert(path.clone()); }
+                if let Some(v) = added_in.remove(&path) {
+                    out.transient.push(TransientFile { path, size, added_in: v, removed_in: c.version });
+                }
+            }
+        }
+        cd.data_change = any_data_change || (cd.files_added == 0 && cd.files_removed == 0);
+        out.commits.push(cd);
+    }
+    // a path re-added after its removal survives to `to` and is not transient
+    out.transient.retain(|t| !added_in.contains_key(&t.path));
+    Ok(out)
+}
+
+fn num_records(action: &serde_json::Value) -> Option<u64> {
+    action.get("stats").and_then(|s| s.as_str()).and_then(parse_stats).and_then(|s| s.num_records)
+}
+
+fn cdf_enabled(metadata: &serde_json::Value) -> bool {
+    metadata.get("configuration").and_then(|c| c.get(CHANGE_DATA_FEED_PROPERTY)).and_then(|v| v.as_str()).is_some_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+/// None unless the change data feed is on for at least one commit after `from`.
+async fn change_data_summary(h: &DeltaTableHandle, root: &TableRoot, commits: &[crate::Commit], from: i64, files_from: &[AddFileLite]) -> Result<Option<ChangeDataSummary>> {
+    let mut rows: HashMap<String, u64> = files_from.iter().filter_map(|f| Some((f.path.clone(), f.stats.as_ref()?.num_records?))).collect();
+    // the metadata as of `from` may only be in a checkpoint once old commits are cleaned up
+    let mut enabled = cdf_enabled(&read_table_state(h, from).await?.metadata);
+    let mut seen = false;
+    let mut out = ChangeDataSummary::default();
+    let mut cdc_paths = Vec::new();
+    for c in commits.iter().filter(|c| c.version > from) {
+        if let Some(meta) = c.actions.iter().find_map(|a| a.get("metaData")) { enabled = cdf_enabled(meta); }
+        let cdc: Vec<String> = c.actions.iter().filter_map(|a| a.get("cdc")?.get("path")?.as_str()).map(|p| root.canonical(p)).collect();
+        let derive = enabled && cdc.is_empty();
+        seen |= enabled || !cdc.is_empty();
+        cdc_paths.extend(cdc);
+        if derive { out.derived_commits += 1; }
+        for a in c.removes_first() {
+            let data_change = |x: &serde_json::Value| x.get("dataChange").and_then(|d| d.as_bool()).unwrap_or(true);
+            if let Some(add) = a.get("add") {
+                let Some(p) = add.get("path").and_then(|p| p.as_str()) else { continue };
+                let n = num_records(add);
+                if let Some(n) = n { rows.insert(root.canonical(p), n); }
+                if derive && data_change(add) { out.insert += n.unwrap_or(0); }
+            }
+            if let Some(rm) = a.get("remove") {
+                let Some(p) = rm.get("path").and_then(|p| p.as_str()) else { continue };
+                if derive && data_change(rm) { out.delete += num_records(rm).or_else(|| rows.get(&root.canonical(p)).copied()).unwrap_or(0); }
+            }
+        }
+    }
+    if !seen { return Ok(None); }
+
+    let store = make_object_store(&h.uri, &StorageOptions::default()).await?;
+    for p in &cdc_paths {
+        let loc = root.location(p)?;
+        let data = storage::get_bytes(store.clone(), &loc).await.map_err(|e| anyhow!("reading change data file {}: {}", p, e))?;
+        count_change_types(data, &mut out).map_err(|e| anyhow!("change data file {}: {}", p, e))?;
+        out.cdc_files += 1;
+    }
+    Ok(Some(out))
+}
+
+fn count_change_types(data: bytes::Bytes, out: &mut ChangeDataSummary) -> Result<()> {
+    use deltalake::arrow::array::{Array, StringArray};
+    use deltalake::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use deltalake::parquet::arrow::ProjectionMask;
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(data)?;
+    let idx = builder
+        .schema()
+        .index_of("_change_type")
+        .map_err(|_| anyhow!("no _change_type column"))?;
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [idx]);
+    for batch in builder.with_projection(mask).build()? {
+        let batch = batch?;
+        let col = batch.column(0).as_any().downcast_ref::<StringArray>().ok_or_else(|| anyhow!("_change_type is not a string column"))?;
+        for v in col.iter().flatten() {
+            match v {
+                "insert" => out.insert += 1,
+                "update_preimage" => out.update_preimage += 1,
+                "update_postimage" => out.update_postimage += 1,
+                "delete" => out.delete += 1,
+                other => return Err(anyhow!("unknown change type '{}'", other)),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::AddFileLite;
+
+/// One quarantined path or glob, relative to the table root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub pattern: String,
+    pub reason: String,
+}
+
+/// A file left out of a plan, manifest or snapshot because of an exclusion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExcludedFile {
+    pub path: String,
+    pub size: i64,
+    pub pattern: String,
+    pub reason: String,
+}
+
+/// Known-bad files to keep out of every output without rewriting the table.
+///
+/// Text form, one entry per line: `<pattern>  # <reason>` (or `<pattern><TAB><reason>`).
+/// Patterns support `*` (within a path segment), `**` (across segments) and `?`; a pattern
+/// ending in `/` covers everything below it. Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExclusionList {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub entries: Vec<Exclusion>,
+}
+
+fn glob_match(pat: &[u8], s: &[u8]) -> bool {
+    match pat.first() {
+        None => s.is_empty(),
+        Some(b'*') if pat.get(1) == Some(&b'*') => {
+            let rest = pat[2..].strip_prefix(b"/").unwrap_or(&pat[2..]);
+            (0..=s.len()).any(|i| (i == 0 || s[i - 1] == b'/') && glob_match(rest, &s[i..])) || glob_match(&pat[2..], s)
+        }
+        Some(b'*') => (0..=s.len()).take_while(|i| *i == 0 || s[i - 1] != b'/').any(|i| glob_match(&pat[1..], &s[i..])),
+        Some(b'?') => s.first().is_some_and(|c| *c != b'/') && glob_match(&pat[1..], &s[1..]),
+        Some(c) => s.first() == Some(c) && glob_match(&pat[1..], &s[1..]),
+    }
+}
+
+impl ExclusionList {
+    pub fn parse(text: &str, source: Option<&str>) -> Result<ExclusionList> {
+        let mut entries = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let split = line.split_once('\t').or_else(|| {
+                line.char_indices().find(|(i, c)| *c == '#' && line[..*i].ends_with(char::is_whitespace)).map(|(i, _)| (&line[..i], &line[i + 1..]))
+            });
+            let Some((pattern, reason)) = split else {
+                return Err(anyhow!("exclusion list line {}: '{}' has no reason; use '<pattern>  # <reason>'", n + 1, line));
+            };
+            let (pattern, reason) = (pattern.trim().trim_start_matches('/'), reason.trim().trim_start_matches('#').trim());
+            if pattern.is_empty() || reason.is_empty() {
+                return Err(anyhow!("exclusion list line {}: both a pattern and a reason are required", n + 1));
+            }
+            entries.push(Exclusion { pattern: pattern.to_string(), reason: reason.to_string() });
+        }
+        Ok(ExclusionList { source: source.map(|s| s.to_string()), entries })
+    }
+
+    /// Reads an exclusion list from a local path or any supported object-store URI.
+    pub async fn load(uri: &str) -> Result<ExclusionList> {
+        let parsed = storage::parse_uri(uri)?;
+        let store = storage::make_object_store(uri, &storage::StorageOptions::default()).await?;
+        let data = storage::get_bytes(store, &storage::object_path_from_url(&parsed.url)).await
+            .map_err(|e| anyhow!("reading exclusion list {}: {}", uri, e))?;
+        ExclusionList::parse(std::str::from_utf8(&data)?, Some(uri))
+    }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// First entry covering `path`, if any.
+    pub fn matching(&self, path: &str) -> Option<&Exclusion> {
+        self.entries.iter().find(|e| {
+            if let Some(dir) = e.pattern.strip_suffix('/') {
+                path.starts_with(&e.pattern) || glob_match(format!("{}/**", dir).as_bytes(), path.as_bytes())
+            } else {
+                glob_match(e.pattern.as_bytes(), path.as_bytes())
+            }
+        })
+    }
+
+    /// Splits `files` into the ones to keep and the ones skipped, with the entry that caught each.
+    pub fn apply(&self, files: Vec<AddFileLite>) -> (Vec<AddFileLite>, Vec<ExcludedFile>) {
+        if self.is_empty() { return (files, Vec::new()); }
+        let mut kept = Vec::with_capacity(files.len());
+        let mut skipped = Vec::new();
+        for f in files {
+            match self.matching(&f.path) {
+                Some(e) => skipped.push(ExcludedFile { path: f.path, size: f.size, pattern: e.pattern.clone(), reason: e.reason.clone() }),
+                None => kept.push(f),
+            }
+        }
+        (kept, skipped)
+    }
+}
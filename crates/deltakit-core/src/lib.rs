@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use blake3::Hasher;
 use deltalake::{DeltaTable, DeltaTableBuilder};
 use serde::{Deserialize, Serialize};
@@ -6,6 +6,27 @@ use std::collections::{BTreeMap, HashMap};
 
 use storage::{object_path_from_url, parse_uri, make_object_store, StorageOptions};
 
+pub mod compare;
+pub mod content;
+pub mod diff;
+pub mod exclusions;
+pub mod lock;
+pub mod paths;
+pub mod pins;
+pub mod predicate;
+pub mod vacuum;
+pub mod verify;
+pub use compare::{compare_snapshots, CompareReport, FieldDifference, FileRef, SizeMismatch, SnapshotRef, StatsMismatch};
+pub use content::{compare_content, content_hash, merkle_root, read_content_manifest, write_content_manifest, ContentComparison, ContentHashOptions, ContentManifest, FileHash, HashProgress};
+pub use diff::{diff_commits, diff_versions, diff_versions_with, CommitDiff, DiffFile, DiffOptions, DiffReport, PartitionDiff, ReplayDiff, TransientFile};
+pub use exclusions::{ExcludedFile, ExclusionList};
+pub use lock::{create_lock, read_lock, verify_lock, verify_lock_content, write_lock, LockOptions, LockVerification, LockedFile, Lockfile};
+pub use paths::TableRoot;
+pub use pins::Pin;
+pub use predicate::Predicate;
+pub use vacuum::{vacuum_candidates, vacuum_dry_run, vacuum_report, DeletionPlan, VacuumCandidate, VacuumCategory, VacuumClass, VacuumOptions, VacuumReport};
+pub use verify::{verify_files, FileIssue, FileProblem, FileVerification, VerifyOptions};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeltaTableHandle {
     pub uri: String,
@@ -17,6 +38,71 @@ pub struct AddFileLite {
     pub path: String,
     pub size: i64,
     pub partition_values: BTreeMap<String, Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<FileStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletion_vector: Option<DeletionVectorLite>,
+}
+
+impl AddFileLite {
+    /// `numRecords` less the rows the deletion vector marks deleted.
+    pub fn live_rows(&self) -> Option<u64> {
+        let n = self.stats.as_ref()?.num_records?;
+        Some(n.saturating_sub(self.deletion_vector.as_ref().map(|dv| dv.cardinality).unwrap_or(0)))
+    }
+}
+
+/// The deletion vector of an add action; storage type, path and offset identify it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletionVectorLite {
+    pub storage_type: String,
+    pub path_or_inline_dv: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    pub cardinality: u64,
+}
+
+impl DeletionVectorLite {
+    fn from_action(add: &serde_json::Map<String, serde_json::Value>) -> Option<Self> {
+        let dv = add.get("deletionVector")?;
+        Some(DeletionVectorLite {
+            storage_type: dv.get("storageType")?.as_str()?.to_string(),
+            path_or_inline_dv: dv.get("pathOrInlineDv")?.as_str()?.to_string(),
+            offset: dv.get("offset").and_then(|o| o.as_i64()),
+            cardinality: dv.get("cardinality").and_then(|c| c.as_u64()).unwrap_or(0),
+        })
+    }
+}
+
+/// Column stats from the add action; nested struct columns are flattened to dotted names.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FileStats {
+    pub num_records: Option<u64>,
+    pub min_values: BTreeMap<String, serde_json::Value>,
+    pub max_values: BTreeMap<String, serde_json::Value>,
+    pub null_count: BTreeMap<String, u64>,
+}
+
+fn flatten_stats(prefix: &str, v: &serde_json::Value, out: &mut BTreeMap<String, serde_json::Value>) {
+    if let Some(obj) = v.as_object() {
+        for (k, v) in obj {
+            let name = if prefix.is_empty() { k.clone() } else { format!("{}.{}", prefix, k) };
+            if v.is_object() { flatten_stats(&name, v, out); } else { out.insert(name, v.clone()); }
+        }
+    }
+}
+
+pub fn parse_stats(raw: &str) -> Option<FileStats> {
+    let v: serde_json::Value = serde_json::from_str(raw).ok()?;
+    let mut stats = FileStats { num_records: v.get("numRecords").and_then(|n| n.as_u64()), ..Default::default() };
+    if let Some(m) = v.get("minValues") { flatten_stats("", m, &mut stats.min_values); }
+    if let Some(m) = v.get("maxValues") { flatten_stats("", m, &mut stats.max_values); }
+    if let Some(m) = v.get("nullCount") {
+        let mut flat = BTreeMap::new();
+        flatten_stats("", m, &mut flat);
+        stats.null_count = flat.into_iter().filter_map(|(k, v)| v.as_u64().map(|n| (k, n))).collect();
+    }
+    Some(stats)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,20 +141,28 @@ pub struct PartitionReport {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DiffReport {
-    pub from: i64,
-    pub to: i64,
-    pub files_added: usize,
-    pub files_removed: usize,
-    pub bytes_added: i64,
-    pub bytes_removed: i64,
-}
+pub struct ManifestEntry { pub path: String, pub size: i64 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ManifestEntry { pub path: String, pub size: i64 }
+pub struct Manifest {
+    pub version: i64,
+    pub format: ManifestFormat,
+    pub files: Vec<ManifestEntry>,
+    /// the artefacts of `format`, rendered but not yet written
+    pub objects: Vec<ManifestObject>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<ExcludedFile>,
+}
 
+/// One manifest file: absolute data file URIs, one per line, sorted.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Manifest { pub version: i64, pub files: Vec<ManifestEntry> }
+pub struct ManifestObject {
+    /// relative to the destination; empty for a file list, which is written to the destination itself
+    pub path: String,
+    pub files: usize,
+    #[serde(skip)]
+    pub body: String,
+}
 
 pub async fn load_table(uri: &str) -> Result<DeltaTableHandle> {
     Ok(DeltaTableHandle { uri: uri.to_string(), version: None })
@@ -100,10 +194,13 @@ pub async fn list_active_files(h: &DeltaTableHandle, version: Option<i64>) -> Re
     logs.retain(|m| m.location.as_ref().ends_with(".json"));
     logs.sort_by_key(|m| m.location.clone());
     let target_v: Option<i64> = version;
+    let table_root = TableRoot::new(&h.uri)?;
     use std::collections::{HashMap, HashSet};
     let mut active: HashSet<String> = HashSet::new();
     let mut parts_map: HashMap<String, BTreeMap<String, Option<String>>> = HashMap::new();
     let mut size_map: HashMap<String, i64> = HashMap::new();
+    let mut stats_map: HashMap<String, FileStats> = HashMap::new();
+    let mut dv_map: HashMap<String, DeletionVectorLite> = HashMap::new();
     for m in logs {
         let name = m.location.filename().unwrap_or("");
         if let Some(stripped) = name.strip_suffix(".json") {
@@ -112,44 +209,234 @@ pub async fn list_active_files(h: &DeltaTableHandle, version: Option<i64>) -> Re
             }
         }
         let bytes = store.get(&m.location).await?.bytes().await?;
-        for line in bytes.split(|b| *b == b'\n') {
-            if line.is_empty() { continue; }
-            if let Ok(val) = serde_json::from_slice::<serde_json::Value>(line) {
-                if let Some(obj) = val.get("add").and_then(|v| v.as_object()) {
-                    if let Some(path) = obj.get("path").and_then(|v| v.as_str()) {
-                        let path_s = path.to_string();
-                        active.insert(path_s.clone());
-                        let mut pm = BTreeMap::new();
-                        if let Some(pv) = obj.get("partitionValues").and_then(|v| v.as_object()) {
-                            for (k, v) in pv {
-                                pm.insert(k.clone(), v.as_str().map(|s| s.to_string()));
-                            }
+        let actions: Vec<serde_json::Value> = bytes.split(|b| *b == b'\n').filter(|l| !l.is_empty()).filter_map(|l| serde_json::from_slice(l).ok()).collect();
+        // removes first, so an add and remove of the same path (a deletion-vector update) leave it live
+        let is_remove = |a: &&serde_json::Value| a.get("remove").is_some();
+        for val in actions.iter().filter(is_remove).chain(actions.iter().filter(|a| !is_remove(a))) {
+            if let Some(obj) = val.get("add").and_then(|v| v.as_object()) {
+                if let Some(path) = obj.get("path").and_then(|v| v.as_str()) {
+                    let path_s = table_root.canonical(path);
+                    active.insert(path_s.clone());
+                    let mut pm = BTreeMap::new();
+                    if let Some(pv) = obj.get("partitionValues").and_then(|v| v.as_object()) {
+                        for (k, v) in pv {
+                            pm.insert(k.clone(), v.as_str().map(|s| s.to_string()));
                         }
-                        parts_map.insert(path_s.clone(), pm);
-                        if let Some(sz) = obj.get("size").and_then(|v| v.as_i64()) { size_map.insert(path_s.clone(), sz); }
                     }
-                } else if let Some(obj) = val.get("remove").and_then(|v| v.as_object()) {
-                    if let Some(path) = obj.get("path").and_then(|v| v.as_str()) {
-                        active.remove(path);
-                        parts_map.remove(path);
-                        size_map.remove(path);
+                    parts_map.insert(path_s.clone(), pm);
+                    if let Some(sz) = obj.get("size").and_then(|v| v.as_i64()) { size_map.insert(path_s.clone(), sz); }
+                    match obj.get("stats").and_then(|v| v.as_str()).and_then(parse_stats) {
+                        Some(st) => { stats_map.insert(path_s.clone(), st); }
+                        None => { stats_map.remove(&path_s); }
                     }
+                    match DeletionVectorLite::from_action(obj) {
+                        Some(dv) => { dv_map.insert(path_s.clone(), dv); }
+                        None => { dv_map.remove(&path_s); }
+                    }
+                }
+            } else if let Some(obj) = val.get("remove").and_then(|v| v.as_object()) {
+                if let Some(path) = obj.get("path").and_then(|v| v.as_str()) {
+                    let path = table_root.canonical(path);
+                    active.remove(&path);
+                    parts_map.remove(&path);
+                    size_map.remove(&path);
+                    stats_map.remove(&path);
+                    dv_map.remove(&path);
                 }
             }
         }
     }
     let mut out = Vec::with_capacity(active.len());
     for p in active.into_iter() {
-        let key = root.child(p.as_str());
-        let size = size_map.get(&p).copied().unwrap_or_else(|| {
-            futures::executor::block_on(async { store.head(&key).await.map(|m| m.size as i64).unwrap_or(0) })
-        });
-        out.push(AddFileLite { path: p.clone(), size, partition_values: parts_map.remove(&p).unwrap_or_default() });
+        let size = match (size_map.get(&p).copied(), table_root.location(&p)) {
+            (Some(size), _) => size,
+            (None, Ok(key)) => futures::executor::block_on(async { store.head(&key).await.map(|m| m.size as i64).unwrap_or(0) }),
+            (None, Err(_)) => 0,
+        };
+        out.push(AddFileLite { path: p.clone(), size, partition_values: parts_map.remove(&p).unwrap_or_default(), stats: stats_map.remove(&p), deletion_vector: dv_map.remove(&p) });
     }
     out.sort_by(|a,b| a.path.cmp(&b.path));
     Ok(out)
 }
 
+/// One `_delta_log/<version>.json` commit.
+#[derive(Debug, Clone)]
+pub(crate) struct Commit {
+    pub version: i64,
+    /// commitInfo.timestamp, or the commit file's modification time when absent
+    pub timestamp_ms: i64,
+    pub actions: Vec<serde_json::Value>,
+}
+
+impl Commit {
+    /// The commit's actions with every `remove` ahead of the rest. A deletion-vector update writes
+    /// `add` (new DV) and `remove` (old DV) for the same path in either order; replaying removes
+    /// first leaves the file live, as the spec's (path, DV id) reconciliation does.
+    pub fn removes_first(&self) -> impl Iterator<Item = &serde_json::Value> {
+        let is_remove = |a: &&serde_json::Value| a.get("remove").is_some();
+        self.actions.iter().filter(is_remove).chain(self.actions.iter().filter(move |a| !is_remove(a)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeltaLog {
+    /// oldest first
+    pub commits: Vec<Commit>,
+    /// versions that have a (possibly multi-part) checkpoint, oldest first
+    pub checkpoints: Vec<i64>,
+}
+
+/// Parsed commits up to `version` (all when None) plus the checkpoint versions present in the log.
+pub(crate) async fn read_log(h: &DeltaTableHandle, version: Option<i64>) -> Result<DeltaLog> {
+    let parsed = parse_uri(&h.uri)?;
+    let store = make_object_store(&h.uri, &StorageOptions::default()).await?;
+    let log_prefix = object_path_from_url(&parsed.url).child("_delta_log");
+    let mut logs = storage::list_recursively(store.clone(), &log_prefix).await?;
+    logs.sort_by_key(|m| m.location.clone());
+    let mut out = DeltaLog::default();
+    for m in logs {
+        let name = m.location.filename().unwrap_or("");
+        if name.contains(".checkpoint.") && name.ends_with(".parquet") {
+            if let Ok(v) = name[..name.find('.').unwrap_or(0)].parse::<i64>() {
+                if out.checkpoints.last() != Some(&v) { out.checkpoints.push(v); }
+            }
+            continue;
+        }
+        let Some(v) = name.strip_suffix(".json").and_then(|n| n.parse::<i64>().ok()) else { continue };
+        if version.is_some_and(|t| v > t) { continue; }
+        let bytes = store.get(&m.location).await?.bytes().await?;
+        let actions: Vec<serde_json::Value> = bytes.split(|b| *b == b'\n').filter(|l| !l.is_empty()).filter_map(|l| serde_json::from_slice(l).ok()).collect();
+        let timestamp_ms = actions
+            .iter()
+            .find_map(|a| a.get("commitInfo").and_then(|c| c.get("timestamp")).and_then(|t| t.as_i64()))
+            .unwrap_or_else(|| m.last_modified.timestamp_millis());
+        out.commits.push(Commit { version: v, timestamp_ms, actions });
+    }
+    Ok(out)
+}
+
+/// Latest `protocol` and `metaData` actions as of a version.
+#[derive(Debug, Clone)]
+pub(crate) struct TableState {
+    pub protocol: serde_json::Value,
+    pub metadata: serde_json::Value,
+}
+
+pub(crate) async fn read_table_state(h: &DeltaTableHandle, version: i64) -> Result<TableState> {
+    let log = read_log(h, Some(version)).await?;
+    // a checkpoint supersedes every commit up to its version, which may since have been cleaned up
+    let checkpoint = log.checkpoints.iter().rev().find(|v| **v <= version).copied();
+    let newer = |key: &str| {
+        log.commits
+            .iter()
+            .rev()
+            .filter(|c| !matches!(checkpoint, Some(cp) if c.version <= cp))
+            .find_map(|c| c.actions.iter().find_map(|a| a.get(key).cloned()))
+    };
+    let (mut protocol, mut metadata) = (newer("protocol"), newer("metaData"));
+    if let (Some(cp), true) = (checkpoint, protocol.is_none() || metadata.is_none()) {
+        let actions = read_checkpoint_actions(h, cp, &["protocol", "metaData"]).await?;
+        protocol = protocol.or_else(|| actions.iter().find_map(|a| a.get("protocol").cloned()));
+        metadata = metadata.or_else(|| actions.iter().find_map(|a| a.get("metaData").cloned()));
+    }
+    match (protocol, metadata) {
+        (Some(protocol), Some(metadata)) => Ok(TableState { protocol, metadata }),
+        _ => Err(anyhow::anyhow!("no protocol/metaData in the log of {} up to version {}", h.uri, version)),
+    }
+}
+
+/// The `keys` actions (e.g. `add`, `metaData`) of the (possibly multi-part) checkpoint at `version`,
+/// each as a single-key object like the lines of a commit.
+pub(crate) async fn read_checkpoint_actions(h: &DeltaTableHandle, version: i64, keys: &[&str]) -> Result<Vec<serde_json::Value>> {
+    use deltalake::parquet::file::reader::{FileReader, SerializedFileReader};
+    use deltalake::parquet::schema::types::Type;
+
+    let parsed = parse_uri(&h.uri)?;
+    let store = make_object_store(&h.uri, &StorageOptions::default()).await?;
+    let prefix = format!("{:020}.checkpoint", version);
+    let parts: Vec<_> = storage::list_recursively(store.clone(), &object_path_from_url(&parsed.url).child("_delta_log"))
+        .await?
+        .into_iter()
+        .filter(|m| m.location.filename().is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".parquet")))
+        .collect();
+    let mut actions = Vec::new();
+    for m in parts {
+        let data = storage::get_bytes(store.clone(), &m.location).await?;
+        let reader = SerializedFileReader::new(data).map_err(|e| anyhow::anyhow!("checkpoint {}: {}", m.location, e))?;
+        let schema = reader.metadata().file_metadata().schema();
+        let fields: Vec<_> = schema
+            .get_fields()
+            .iter()
+            .filter(|f| keys.contains(&f.name()))
+            .cloned()
+            .collect();
+        if fields.is_empty() { continue; }
+        let projection = Type::group_type_builder(schema.name()).with_fields(fields).build()?;
+        for row in reader.get_row_iter(Some(projection))? {
+            for (name, field) in row?.get_column_iter() {
+                if matches!(field, deltalake::parquet::record::Field::Null) { continue; }
+                let mut action = serde_json::Map::new();
+                action.insert(name.clone(), field_to_json(field));
+                actions.push(serde_json::Value::Object(action));
+            }
+        }
+    }
+    Ok(actions)
+}
+
+fn field_to_json(field: &deltalake::parquet::record::Field) -> serde_json::Value {
+    use deltalake::parquet::record::Field;
+    use serde_json::Value;
+    match field {
+        Field::Null => Value::Null,
+        Field::Bool(b) => Value::Bool(*b),
+        Field::Byte(n) => (*n).into(),
+        Field::Short(n) => (*n).into(),
+        Field::Int(n) => (*n).into(),
+        Field::Long(n) => (*n).into(),
+        Field::Str(s) => Value::String(s.clone()),
+        Field::Group(row) => Value::Object(row.get_column_iter().map(|(k, v)| (k.clone(), field_to_json(v))).collect()),
+        Field::ListInternal(list) => Value::Array(list.elements().iter().map(field_to_json).collect()),
+        Field::MapInternal(map) => Value::Object(
+            map.entries()
+                .iter()
+                .map(|(k, v)| (match k { Field::Str(s) => s.clone(), other => other.to_string() }, field_to_json(v)))
+                .collect(),
+        ),
+        other => Value::String(other.to_string()),
+    }
+}
+
+/// Row groups per file of `table_uri`, read from each file's Parquet footer, `concurrency` (default 32) at a time.
+pub async fn row_group_counts(table_uri: &str, paths: &[String], concurrency: Option<usize>) -> Result<BTreeMap<String, u32>> {
+    use futures::stream::{self, StreamExt, TryStreamExt};
+    let root = TableRoot::new(table_uri)?;
+    let store = root.store().await?;
+    let reads = paths.iter().map(|path| {
+        let (root, store) = (&root, store.clone());
+        async move {
+            let (store, loc) = root.resolve(store, path).await?;
+            let n = footer_row_groups(store, &loc).await.map_err(|e| anyhow::anyhow!("reading the footer of {}: {:#}", path, e))?;
+            Ok::<_, anyhow::Error>((path.clone(), n))
+        }
+    });
+    stream::iter(reads).buffer_unordered(concurrency.unwrap_or(32).max(1)).try_collect().await
+}
+
+// decode_footer/decode_metadata are deprecated from parquet 53 on, but their replacement does not exist before it
+#[allow(deprecated)]
+async fn footer_row_groups(store: std::sync::Arc<object_store::DynObjectStore>, loc: &object_store::path::Path) -> Result<u32> {
+    use deltalake::parquet::file::footer::{decode_footer, decode_metadata};
+    let size = storage::head_if_exists(store.clone(), loc).await?.ok_or_else(|| anyhow::anyhow!("file not found"))?.size;
+    if size < 12 { return Err(anyhow::anyhow!("{} bytes is too small for Parquet", size)); }
+    let tail = storage::head_range(store.clone(), loc, size - 8..size).await?;
+    let tail: [u8; 8] = tail[..].try_into()?;
+    let footer_len = decode_footer(&tail)?;
+    if footer_len > size - 12 { return Err(anyhow::anyhow!("footer length {} exceeds the file", footer_len)); }
+    let footer = storage::head_range(store, loc, size - 8 - footer_len..size - 8).await?;
+    Ok(decode_metadata(&footer)?.num_row_groups() as u32)
+}
+
 pub async fn compute_integrity_hash(h: &DeltaTableHandle, version: Option<i64>) -> Result<String> {
     let files = list_active_files(h, version).await?;
     let mut hasher = Hasher::new();
@@ -290,68 +577,78 @@ pub async fn partition_health(h: &DeltaTableHandle, by: &[String]) -> Result<Par
     Ok(PartitionReport { by: by.to_vec(), cardinality, empty_partitions, total_files: files.len() })
 }
 
-pub async fn diff_versions(h: &DeltaTableHandle, from: i64, to: i64) -> Result<DiffReport> {
-    if to < from { return Err(anyhow!("to must be >= from")); }
-    let files_from = list_active_files(h, Some(from)).await?;
-    let files_to = list_active_files(h, Some(to)).await?;
-
-    use std::collections::HashSet;
-    let mut map_from: HashMap<String, i64> = HashMap::new();
-    for f in files_from { map_from.insert(f.path.clone(), f.size as i64); }
-    let mut map_to: HashMap<String, i64> = HashMap::new();
-    for f in files_to { map_to.insert(f.path.clone(), f.size as i64); }
-
-    let set_from: HashSet<String> = map_from.keys().cloned().collect();
-    let set_to: HashSet<String> = map_to.keys().cloned().collect();
-    let added: HashSet<_> = set_to.difference(&set_from).collect();
-    let removed: HashSet<_> = set_from.difference(&set_to).collect();
-
-    let files_added = added.len();
-    let files_removed = removed.len();
-    let bytes_added: i64 = added.into_iter().map(|p| map_to.get(p).copied().unwrap_or(0)).sum();
-    let bytes_removed: i64 = removed.into_iter().map(|p| map_from.get(p).copied().unwrap_or(0)).sum();
-
-    Ok(DiffReport { from, to, files_added, files_removed, bytes_added, bytes_removed })
+pub async fn generate_manifest(h: &DeltaTableHandle, version: i64, format: ManifestFormat) -> Result<Manifest> {
+    generate_manifest_excluding(h, version, format, &ExclusionList::default()).await
 }
 
-pub async fn generate_manifest(h: &DeltaTableHandle, version: i64, _format: ManifestFormat) -> Result<Manifest> {
+/// Like `generate_manifest`, leaving out quarantined files and listing them under `excluded`.
+pub async fn generate_manifest_excluding(h: &DeltaTableHandle, version: i64, format: ManifestFormat, exclude: &ExclusionList) -> Result<Manifest> {
     let files = list_active_files(h, Some(version)).await?;
+    let (files, excluded) = exclude.apply(files);
+    let root = TableRoot::new(&h.uri)?;
+    let absolute = |f: &AddFileLite| root.uri(&f.path);
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    match format {
+        ManifestFormat::FileList => { groups.entry(String::new()).or_default().extend(files.iter().map(absolute)); }
+        ManifestFormat::Trino | ManifestFormat::Hive | ManifestFormat::Presto => {
+            // one manifest per partition directory, columns in table order
+            let partition_cols: Vec<String> = if files.iter().any(|f| !f.partition_values.is_empty()) {
+                let meta = read_table_state(h, version).await?.metadata;
+                meta.get("partitionColumns").and_then(|c| c.as_array()).map(|c| c.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect()).unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            if files.is_empty() { groups.entry("manifest".to_string()).or_default(); }
+            for f in &files {
+                let mut dir: Vec<String> = partition_cols
+                    .iter()
+                    .map(|c| match f.partition_values.get(c).cloned().flatten() {
+                        Some(v) => format!("{}={}", c, paths::escape_partition_value(&v)),
+                        None => format!("{}={}", c, HIVE_DEFAULT_PARTITION),
+                    })
+                    .collect();
+                dir.push("manifest".to_string());
+                groups.entry(dir.join("/")).or_default().push(absolute(f));
+            }
+        }
+    }
+    let objects = groups
+        .into_iter()
+        .map(|(path, mut uris)| {
+            uris.sort();
+            let body = uris.iter().map(|u| format!("{}\n", u)).collect();
+            ManifestObject { path, files: uris.len(), body }
+        })
+        .collect();
     let entries = files.into_iter().map(|f| ManifestEntry { path: f.path, size: f.size }).collect();
-    Ok(Manifest { version, files: entries })
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ManifestFormat { Trino, Hive, Presto, FileList }
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VacuumReport {
-    pub referenced_files: usize,
-    pub existing_files: usize,
-    pub orphans: usize,
-    pub safe: bool,
+    Ok(Manifest { version, format, files: entries, objects, excluded })
 }
 
-pub async fn vacuum_dry_run(h: &DeltaTableHandle, _retention_days: i64) -> Result<VacuumReport> {
-    let parsed = parse_uri(&h.uri)?;
-    let store = make_object_store(&h.uri, &StorageOptions::default()).await?;
-    let active = list_active_files(h, None).await?;
-    let prefix = object_path_from_url(&parsed.url);
-    let listing = storage::list_recursively(store, &prefix).await?;
-
-    use std::collections::HashSet;
-    let mut referenced: HashSet<String> = HashSet::new();
-    for f in active { referenced.insert(f.path); }
-    let root_str = prefix.as_ref();
-    let mut norm_existing: HashSet<String> = HashSet::new();
-    for m in listing {
-        let full = m.location.as_ref();
-        let rel = full.strip_prefix(root_str).unwrap_or(full).trim_start_matches('/') .to_string();
-        if rel.starts_with("_delta_log/") || rel.is_empty() { continue; }
-        norm_existing.insert(rel);
+/// Directory value Hive uses for a null partition.
+pub const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Default destination for the symlink formats, matching Delta's own `GENERATE symlink_format_manifest`.
+pub const SYMLINK_MANIFEST_DIR: &str = "_symlink_format_manifest";
+
+/// Writes the rendered manifest under `dest` (a local path or object-store URI) and returns the URIs written.
+/// A file list is written to `dest` itself; the symlink formats write `<dest>/<partition dirs>/manifest`.
+pub async fn write_manifest(manifest: &Manifest, dest: &str) -> Result<Vec<String>> {
+    let dest = dest.trim_end_matches('/');
+    if dest.split('/').any(|c| c == "_delta_log") { return Err(anyhow::anyhow!("refusing to write a manifest inside _delta_log: {}", dest)); }
+    let store = make_object_store(dest, &StorageOptions::default()).await?;
+    let base = object_path_from_url(&parse_uri(dest)?.url);
+    let mut written = Vec::with_capacity(manifest.objects.len());
+    for o in &manifest.objects {
+        let loc = if o.path.is_empty() { base.clone() } else { storage::object_path_join(&base, &o.path)? };
+        storage::put_bytes(store.clone(), &loc, o.body.clone().into()).await?;
+        written.push(if o.path.is_empty() { dest.to_string() } else { format!("{}/{}", dest, o.path) });
     }
-    let orphans: usize = norm_existing.difference(&referenced).count();
-    let safe = orphans == 0;
-    Ok(VacuumReport { referenced_files: referenced.len(), existing_files: norm_existing.len(), orphans, safe })
+    Ok(written)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat { Trino, Hive, Presto, FileList }
+
+
 
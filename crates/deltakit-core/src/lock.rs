@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use storage::{make_object_store, object_path_from_url, parse_uri, StorageOptions};
+
+use crate::content::{compare_content, hash_files, logged_size, merkle_root, ContentComparison, ContentManifest, FileHash};
+use crate::{compute_integrity_hash, list_active_files, load_table, read_table_state, DeltaTableHandle, ExclusionList, Predicate, TableRoot};
+
+/// Bumped when the lockfile layout changes incompatibly.
+pub const LOCK_FORMAT: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedFile {
+    pub path: String,
+    pub size: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+/// Filters that narrowed the table down to `files`, kept so the selection can be reproduced.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LockFilters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<ExclusionList>,
+}
+
+/// Pins a training dataset to one table version and the exact files read from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub lock_format: u32,
+    pub table: String,
+    pub version: i64,
+    /// RFC 3339, UTC
+    pub created_at: String,
+    pub protocol: serde_json::Value,
+    /// blake3 of the `metaData` action (schema, partitioning, configuration)
+    pub metadata_hash: String,
+    /// `compute_integrity_hash` of the whole version, before filters
+    pub integrity_hash: String,
+    #[serde(default)]
+    pub filters: LockFilters,
+    pub total_files: usize,
+    pub total_bytes: i64,
+    /// Merkle root over the files' content hashes, when locked with `content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_root: Option<String>,
+    pub files: Vec<LockedFile>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LockOptions {
+    pub filter: Option<Predicate>,
+    pub exclude: ExclusionList,
+    /// record each file's ETag (one HEAD per file)
+    pub etags: bool,
+    /// record each file's blake3 (reads every byte)
+    pub content: bool,
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SizeDrift {
+    pub path: String,
+    pub locked: i64,
+    pub actual: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EtagDrift {
+    pub path: String,
+    pub locked: String,
+    pub actual: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnreadableFile {
+    pub path: String,
+    pub error: String,
+}
+
+/// What changed since a lockfile was written. The log checks are None when the locked version can no longer be read.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LockVerification {
+    pub table: String,
+    pub version: i64,
+    /// every file present with its locked size and ETag, and the log still agrees
+    pub ok: bool,
+    pub files_checked: usize,
+    pub missing: Vec<String>,
+    pub size_changed: Vec<SizeDrift>,
+    pub etag_changed: Vec<EtagDrift>,
+    pub unreadable: Vec<UnreadableFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity_hash_matches: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_hash_matches: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_error: Option<String>,
+}
+
+const DEFAULT_HEAD_CONCURRENCY: usize = 32;
+const DEFAULT_CONTENT_CONCURRENCY: usize = 8;
+
+fn metadata_hash(metadata: &serde_json::Value) -> String {
+    // serde_json maps are ordered, so this is stable across runs
+    blake3::hash(metadata.to_string().as_bytes()).to_hex().to_string()
+}
+
+pub async fn create_lock(h: &DeltaTableHandle, version: i64, opts: &LockOptions) -> Result<Lockfile> {
+    let state = read_table_state(h, version).await?;
+    let integrity_hash = compute_integrity_hash(h, Some(version)).await?;
+    let mut files = list_active_files(h, Some(version)).await?;
+    if let Some(p) = &opts.filter { files.retain(|f| p.may_match(f)); }
+    let (files, _) = opts.exclude.apply(files);
+    let mut locked: Vec<LockedFile> = files.into_iter().map(|f| LockedFile { path: f.path, size: f.size, etag: None, blake3: None }).collect();
+    if opts.etags {
+        let root = TableRoot::new(&h.uri)?;
+        let paths: Vec<String> = locked.iter().map(|f| f.path.clone()).collect();
+        for (f, head) in locked.iter_mut().zip(root.head_all(&paths, opts.concurrency.unwrap_or(DEFAULT_HEAD_CONCURRENCY)).await?) {
+            let meta = head?.ok_or_else(|| anyhow!("{} is in version {} but does not exist", f.path, version))?;
+            f.etag = meta.e_tag;
+        }
+    }
+    let mut content_root = None;
+    if opts.content {
+        let paths: Vec<String> = locked.iter().map(|f| f.path.clone()).collect();
+        let hashed = hash_files(&h.uri, &paths, opts.concurrency.unwrap_or(DEFAULT_CONTENT_CONCURRENCY)).await?;
+        let (mut hashes, mut failed) = (Vec::with_capacity(locked.len()), Vec::new());
+        for (f, r) in locked.iter().zip(hashed) {
+            match logged_size(&f.path, f.size, r) {
+                Ok(hash) => hashes.push(hash),
+                Err(e) => failed.push(e),
+            }
+        }
+        if !failed.is_empty() { return Err(anyhow!("{} files could not be hashed: {}", failed.len(), failed.join("; "))); }
+        content_root = Some(merkle_root(&hashes));
+        for (f, hash) in locked.iter_mut().zip(hashes) { f.blake3 = Some(hash.blake3); }
+    }
+    Ok(Lockfile {
+        lock_format: LOCK_FORMAT,
+        table: h.uri.clone(),
+        version,
+        created_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        protocol: state.protocol,
+        metadata_hash: metadata_hash(&state.metadata),
+        integrity_hash,
+        filters: LockFilters {
+            filter: opts.filter.as_ref().map(|p| p.text().to_string()),
+            exclude: (!opts.exclude.is_empty()).then(|| opts.exclude.clone()),
+        },
+        total_files: locked.len(),
+        total_bytes: locked.iter().map(|f| f.size).sum(),
+        content_root,
+        files: locked,
+    })
+}
+
+pub async fn write_lock(lock: &Lockfile, dest: &str) -> Result<()> {
+    let store = make_object_store(dest, &StorageOptions::default()).await?;
+    storage::put_bytes(store, &object_path_from_url(&parse_uri(dest)?.url), serde_json::to_vec_pretty(lock)?.into()).await
+}
+
+pub async fn read_lock(src: &str) -> Result<Lockfile> {
+    let store = make_object_store(src, &StorageOptions::default()).await?;
+    let data = storage::get_bytes(store, &object_path_from_url(&parse_uri(src)?.url)).await.map_err(|e| anyhow!("reading lockfile {}: {}", src, e))?;
+    let lock: Lockfile = serde_json::from_slice(&data).map_err(|e| anyhow!("lockfile {}: {}", src, e))?;
+    if lock.lock_format > LOCK_FORMAT { return Err(anyhow!("lockfile {} has format {}; this deltakit reads up to {}", src, lock.lock_format, LOCK_FORMAT)); }
+    Ok(lock)
+}
+
+/// HEADs every locked file and re-reads the locked version of the log.
+pub async fn verify_lock(lock: &Lockfile, concurrency: Option<usize>) -> Result<LockVerification> {
+    let mut out = LockVerification { table: lock.table.clone(), version: lock.version, files_checked: lock.files.len(), ..Default::default() };
+    let root = TableRoot::new(&lock.table)?;
+    let paths: Vec<String> = lock.files.iter().map(|f| f.path.clone()).collect();
+    for (f, head) in lock.files.iter().zip(root.head_all(&paths, concurrency.unwrap_or(DEFAULT_HEAD_CONCURRENCY)).await?) {
+        match head {
+            Err(e) => out.unreadable.push(UnreadableFile { path: f.path.clone(), error: e.to_string() }),
+            Ok(None) => out.missing.push(f.path.clone()),
+            Ok(Some(meta)) => {
+                if meta.size as i64 != f.size {
+                    out.size_changed.push(SizeDrift { path: f.path.clone(), locked: f.size, actual: meta.size as i64 });
+                }
+                if let Some(locked) = &f.etag {
+                    if meta.e_tag.as_ref() != Some(locked) {
+                        out.etag_changed.push(EtagDrift { path: f.path.clone(), locked: locked.clone(), actual: meta.e_tag });
+                    }
+                }
+            }
+        }
+    }
+
+    let h = load_table(&lock.table).await?;
+    match (read_table_state(&h, lock.version).await, compute_integrity_hash(&h, Some(lock.version)).await) {
+        (Ok(state), Ok(hash)) => {
+            out.metadata_hash_matches = Some(metadata_hash(&state.metadata) == lock.metadata_hash);
+            out.integrity_hash_matches = Some(hash == lock.integrity_hash);
+        }
+        (Err(e), _) | (_, Err(e)) => out.log_error = Some(e.to_string()),
+    }
+    out.ok = out.missing.is_empty()
+        && out.size_changed.is_empty()
+        && out.etag_changed.is_empty()
+        && out.unreadable.is_empty()
+        && out.integrity_hash_matches != Some(false)
+        && out.metadata_hash_matches != Some(false);
+    Ok(out)
+}
+
+/// Re-hashes every locked file and compares it byte for byte with the hashes in the lockfile.
+/// Files that are now missing or unreadable are `only_in_a`; resized ones are `changed`.
+pub async fn verify_lock_content(lock: &Lockfile, concurrency: Option<usize>) -> Result<ContentComparison> {
+    let locked = ContentManifest::from_lock(lock)?;
+    let paths: Vec<String> = lock.files.iter().map(|f| f.path.clone()).collect();
+    let files: Vec<FileHash> = hash_files(&lock.table, &paths, concurrency.unwrap_or(DEFAULT_CONTENT_CONCURRENCY)).await?.into_iter().filter_map(|r| r.ok().flatten()).collect();
+    let actual = ContentManifest { merkle_root: merkle_root(&files), files_hashed: files.len(), bytes_hashed: files.iter().map(|f| f.size).sum(), files, ..locked.clone() };
+    Ok(compare_content(&locked, &actual))
+}
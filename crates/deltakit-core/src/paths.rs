@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use object_store::path::Path as ObjPath;
+use object_store::{DynObjectStore, ObjectMeta};
+use std::sync::Arc;
+use url::Url;
+
+use storage::{make_object_store, object_path_from_url, object_path_join, parse_uri, StorageOptions};
+
+/// Resolves the `path` of add, remove and cdc actions against a table root.
+///
+/// The log stores URI-encoded paths relative to the table, or absolute URIs (shallow clones,
+/// some writers). The canonical form used throughout deltakit is the decoded relative path for
+/// files under the root (the object key below it) and the absolute URI, unchanged, for anything else.
+#[derive(Debug, Clone)]
+pub struct TableRoot {
+    url: Url,
+}
+
+fn is_absolute(raw: &str) -> bool {
+    match raw.split_once(':') {
+        Some((scheme, rest)) => rest.starts_with('/') && !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')),
+        None => false,
+    }
+}
+
+// '+' stays literal: these are paths, not form data
+fn decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        match (bytes[i], bytes.get(i + 1).and_then(|b| hex(*b)), bytes.get(i + 2).and_then(|b| hex(*b))) {
+            (b'%', Some(h), Some(l)) => { out.push((h * 16 + l) as u8); i += 3; }
+            (b, _, _) => { out.push(b); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl TableRoot {
+    pub fn new(table_uri: &str) -> Result<TableRoot> {
+        let mut url = parse_uri(table_uri)?.url;
+        if !url.path().ends_with('/') { url.set_path(&format!("{}/", url.path())); }
+        Ok(TableRoot { url })
+    }
+
+    /// Canonical form of a path as written in the log.
+    pub fn canonical(&self, raw: &str) -> String {
+        if !is_absolute(raw) { return decode(raw); }
+        match Url::parse(raw) {
+            Ok(u) if u.scheme() == self.url.scheme() && u.host_str() == self.url.host_str() => {
+                let root = decode(self.url.path());
+                match decode(u.path()).strip_prefix(&root) {
+                    Some(rel) if !rel.is_empty() => rel.to_string(),
+                    _ => raw.to_string(),
+                }
+            }
+            _ => raw.to_string(),
+        }
+    }
+
+    pub fn is_external(canonical: &str) -> bool { is_absolute(canonical) }
+
+    /// Fully-qualified, percent-encoded URI of a canonical path.
+    pub fn uri(&self, canonical: &str) -> String {
+        if is_absolute(canonical) { return canonical.to_string(); }
+        let mut url = self.url.clone();
+        if let Ok(mut segs) = url.path_segments_mut() {
+            segs.pop_if_empty().extend(canonical.split('/').filter(|s| !s.is_empty()));
+        }
+        url.to_string()
+    }
+
+    /// HEADs every canonical path, `concurrency` at a time; results are in input order and
+    /// `Ok(None)` means the object does not exist. External URIs go to their own store.
+    pub async fn head_all(&self, paths: &[String], concurrency: usize) -> Result<Vec<Result<Option<ObjectMeta>>>> {
+        let store = self.store().await?;
+        let heads = paths.iter().map(|p| {
+            let store = store.clone();
+            async move {
+                let (store, loc) = self.resolve(store, p).await?;
+                storage::head_if_exists(store, &loc).await
+            }
+        });
+        Ok(stream::iter(heads).buffered(concurrency.max(1)).collect().await)
+    }
+
+    pub(crate) async fn store(&self) -> Result<Arc<DynObjectStore>> {
+        make_object_store(self.url.as_str(), &StorageOptions::default()).await
+    }
+
+    /// Store and location of a canonical path: `root_store` for paths under the root, a store of their own for external URIs.
+    pub(crate) async fn resolve(&self, root_store: Arc<DynObjectStore>, canonical: &str) -> Result<(Arc<DynObjectStore>, ObjPath)> {
+        if !is_absolute(canonical) { return Ok((root_store, self.location(canonical)?)); }
+        let external = make_object_store(canonical, &StorageOptions::default()).await?;
+        Ok((external, object_path_from_url(&parse_uri(canonical)?.url)))
+    }
+
+    /// Object-store location of a canonical path under the root.
+    pub fn location(&self, canonical: &str) -> Result<ObjPath> {
+        if is_absolute(canonical) { return Err(anyhow!("{} is outside the table root {}", canonical, self.url)); }
+        object_path_join(&object_path_from_url(&self.url), canonical)
+    }
+}
+
+/// Hive's escaping of a partition value in a directory name (`escapePathName`), as Delta writers use it.
+pub fn escape_partition_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_control() || matches!(c, '"' | '#' | '%' | '\'' | '*' | '/' | ':' | '=' | '?' | '\\' | '{' | '[' | ']' | '^') {
+            out.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
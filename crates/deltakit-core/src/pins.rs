@@ -0,0 +1,175 @@
+use anyhow::{anyhow, Context, Result};
+use object_store::path::Path as ObjPath;
+use object_store::{DynObjectStore, PutMode, UpdateVersion};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use storage::{make_object_store, object_path_from_url, object_path_join, parse_uri, StorageOptions};
+
+/// Sidecar directory under the table root for deltakit's own state; never part of the Delta log.
+pub const SIDECAR_DIR: &str = "_deltakit";
+
+/// A version a consumer needs to stay readable until `expires_at_ms`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    pub id: String,
+    pub table: String,
+    pub version: i64,
+    pub owner: String,
+    pub created_at_ms: i64,
+    /// None pins until removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<i64>,
+}
+
+impl Pin {
+    pub fn active(&self, now_ms: i64) -> bool { match self.expires_at_ms { Some(t) => t > now_ms, None => true } }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Registry {
+    pins: Vec<Pin>,
+}
+
+/// conditional writes that lost a race are retried this many times before giving up
+const MAX_REGISTRY_ATTEMPTS: usize = 8;
+
+fn same_table(a: &str, b: &str) -> bool { a.trim_end_matches('/') == b.trim_end_matches('/') }
+
+/// `<table>/_deltakit/pins.json`; new pins are stored one object each under the sibling `pins/` prefix
+pub fn default_pin_registry(table_uri: &str) -> String {
+    format!("{}/{}/pins.json", table_uri.trim_end_matches('/'), SIDECAR_DIR)
+}
+
+/// A registry is a JSON file of pins plus a directory of one object per pin next to it
+/// (`pins.json` and `pins/<id>.json`). Pins are added as new objects with `put_if_absent`, so
+/// concurrent adds never overwrite each other; the file is only rewritten with a conditional
+/// update against the e_tag it was read with.
+struct RegistryStore {
+    name: String,
+    store: Arc<DynObjectStore>,
+    file: ObjPath,
+    objects: ObjPath,
+}
+
+impl RegistryStore {
+    async fn open(registry: &str) -> Result<Self> {
+        let parsed = parse_uri(registry)?;
+        let store = make_object_store(registry, &StorageOptions::default()).await?;
+        let file = object_path_from_url(&parsed.url);
+        let objects = ObjPath::from(file.as_ref().trim_end_matches(".json"));
+        Ok(Self { name: registry.to_string(), store, file, objects })
+    }
+
+    fn parse<T: for<'de> Deserialize<'de>>(&self, data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(|e| anyhow!("pin registry {}: {}", self.name, e))
+    }
+
+    /// The registry file and the version to update it against; None when there is no file.
+    async fn read_file(&self) -> Result<Option<(Registry, UpdateVersion)>> {
+        match self.store.get(&self.file).await {
+            Ok(r) => {
+                let version = UpdateVersion { e_tag: r.meta.e_tag.clone(), version: r.meta.version.clone() };
+                Ok(Some((self.parse(&r.bytes().await?)?, version)))
+            }
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.file)),
+        }
+    }
+
+    async fn read_objects(&self) -> Result<Vec<(ObjPath, Pin)>> {
+        let mut out = Vec::new();
+        for meta in storage::list_recursively(self.store.clone(), &self.objects).await? {
+            // removed since it was listed
+            let Some(data) = storage::get_if_exists(self.store.clone(), &meta.location).await? else { continue };
+            out.push((meta.location, self.parse(&data)?));
+        }
+        Ok(out)
+    }
+
+    async fn pins(&self) -> Result<Vec<Pin>> {
+        let mut pins = self.read_file().await?.map(|(reg, _)| reg.pins).unwrap_or_default();
+        pins.extend(self.read_objects().await?.into_iter().map(|(_, p)| p));
+        Ok(pins)
+    }
+
+    async fn add(&self, pin: &Pin) -> Result<()> {
+        let loc = object_path_join(&self.objects, &format!("{}.json", pin.id))?;
+        if !storage::put_if_absent(self.store.clone(), &loc, serde_json::to_vec_pretty(pin)?.into()).await? {
+            return Err(anyhow!("pin {} already exists in {}", pin.id, self.name));
+        }
+        Ok(())
+    }
+
+    /// Removes the pins matching `pred` from the object directory and the file.
+    async fn remove(&self, pred: impl Fn(&Pin) -> bool) -> Result<Vec<Pin>> {
+        let mut removed = Vec::new();
+        for (loc, pin) in self.read_objects().await? {
+            if !pred(&pin) { continue; }
+            match self.store.delete(&loc).await {
+                Ok(()) => removed.push(pin),
+                // a concurrent remove got there first
+                Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e).with_context(|| format!("deleting {}", loc)),
+            }
+        }
+        for _ in 0..MAX_REGISTRY_ATTEMPTS {
+            let Some((reg, version)) = self.read_file().await? else { return Ok(removed) };
+            let (gone, kept): (Vec<Pin>, Vec<Pin>) = reg.pins.into_iter().partition(|p| pred(p));
+            if gone.is_empty() { return Ok(removed); }
+            let data = serde_json::to_vec_pretty(&Registry { pins: kept })?;
+            match self.store.put_opts(&self.file, data.into(), PutMode::Update(version).into()).await {
+                Ok(_) => {
+                    removed.extend(gone);
+                    return Ok(removed);
+                }
+                Err(object_store::Error::Precondition { .. }) => continue,
+                Err(object_store::Error::NotImplemented) => {
+                    return Err(anyhow!("{} cannot be updated safely on this store (no conditional update); edit it by hand", self.name))
+                }
+                Err(e) => return Err(e).with_context(|| format!("writing {}", self.file)),
+            }
+        }
+        Err(anyhow!("{} kept changing; gave up after {} attempts", self.name, MAX_REGISTRY_ATTEMPTS))
+    }
+}
+
+/// Pins recorded for `table` in `registry`, oldest first; an absent registry has none.
+pub async fn list_pins(registry: &str, table: &str) -> Result<Vec<Pin>> {
+    let mut pins: Vec<Pin> = RegistryStore::open(registry).await?.pins().await?.into_iter().filter(|p| same_table(&p.table, table)).collect();
+    pins.sort_by(|a, b| (a.created_at_ms, &a.id).cmp(&(b.created_at_ms, &b.id)));
+    Ok(pins)
+}
+
+pub async fn add_pin(registry: &str, table: &str, version: i64, owner: &str, expires_at_ms: Option<i64>) -> Result<Pin> {
+    if owner.trim().is_empty() { return Err(anyhow!("a pin needs an owner")); }
+    let latest = crate::current_version(&crate::load_table(table).await?).await?;
+    if version < 0 || version > latest { return Err(anyhow!("version {} does not exist (latest is {})", version, latest)); }
+    let created_at_ms = chrono::Utc::now().timestamp_millis();
+    let mut h = blake3::Hasher::new();
+    h.update(table.trim_end_matches('/').as_bytes());
+    h.update(&version.to_le_bytes());
+    h.update(owner.as_bytes());
+    h.update(&created_at_ms.to_le_bytes());
+    let pin = Pin {
+        id: h.finalize().to_hex()[..12].to_string(),
+        table: table.trim_end_matches('/').to_string(),
+        version,
+        owner: owner.trim().to_string(),
+        created_at_ms,
+        expires_at_ms,
+    };
+    RegistryStore::open(registry).await?.add(&pin).await?;
+    Ok(pin)
+}
+
+/// Removes the pins of `table` at `version` (only `owner`'s when given) and returns them.
+pub async fn remove_pins(registry: &str, table: &str, version: i64, owner: Option<&str>) -> Result<Vec<Pin>> {
+    let mut removed = RegistryStore::open(registry)
+        .await?
+        .remove(|p| same_table(&p.table, table) && p.version == version && (owner.is_none() || owner == Some(p.owner.as_str())))
+        .await?;
+    if removed.is_empty() { return Err(anyhow!("no pin of {} at version {}{}", table, version, owner.map(|o| format!(" owned by {}", o)).unwrap_or_default())); }
+    removed.sort_by(|a, b| (a.created_at_ms, &a.id).cmp(&(b.created_at_ms, &b.id)));
+    Ok(removed)
+}
@@ -0,0 +1,374 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::AddFileLite;
+
+/// A file-pruning predicate such as `dt >= '2026-01-01' AND lang IN ('en','de')`.
+///
+/// Evaluated per file against partition values (exact) and min/max/null-count stats (conservative):
+/// a file is only pruned when no row in it can match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Predicate {
+    text: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal { Str(String), Num(f64) }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp { Eq, Ne, Lt, Le, Gt, Ge }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp { col: String, op: CmpOp, value: Literal },
+    In { col: String, values: Vec<Literal>, negated: bool },
+    IsNull { col: String, negated: bool },
+}
+
+impl Predicate {
+    pub fn parse(text: &str) -> Result<Predicate> {
+        let tokens = tokenize(text)?;
+        let mut p = Parser { tokens, pos: 0 };
+        let expr = p.parse_or()?;
+        if p.pos != p.tokens.len() { return Err(anyhow!("unexpected '{}' in predicate", p.tokens[p.pos])); }
+        Ok(Predicate { text: text.trim().to_string(), expr })
+    }
+
+    pub fn text(&self) -> &str { &self.text }
+
+    pub fn expr(&self) -> &Expr { &self.expr }
+
+    /// Columns the predicate refers to, in order of appearance.
+    pub fn columns(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        collect_columns(&self.expr, &mut out);
+        out
+    }
+
+    /// false only when the file provably holds no matching row
+    pub fn may_match(&self, f: &AddFileLite) -> bool {
+        eval(&self.expr, f) != Some(false)
+    }
+}
+
+impl TryFrom<String> for Predicate {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> Result<Self> { Predicate::parse(&s) }
+}
+
+impl From<Predicate> for String {
+    fn from(p: Predicate) -> String { p.text }
+}
+
+impl std::fmt::Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str(&self.text) }
+}
+
+fn collect_columns(e: &Expr, out: &mut Vec<String>) {
+    match e {
+        Expr::And(a, b) | Expr::Or(a, b) => { collect_columns(a, out); collect_columns(b, out); }
+        Expr::Not(a) => collect_columns(a, out),
+        Expr::Cmp { col, .. } | Expr::In { col, .. } | Expr::IsNull { col, .. } => {
+            if !out.contains(col) { out.push(col.clone()); }
+        }
+    }
+}
+
+// --- evaluation: Some(true) = every non-null row matches, Some(false) = no row matches, None = unknown
+
+enum ColumnView<'a> {
+    Partition(Option<&'a str>),
+    Stats { min: Option<&'a serde_json::Value>, max: Option<&'a serde_json::Value>, nulls: Option<u64>, rows: Option<u64> },
+}
+
+fn column<'a>(f: &'a AddFileLite, col: &str) -> Option<ColumnView<'a>> {
+    if let Some(v) = f.partition_values.get(col) {
+        return Some(ColumnView::Partition(v.as_deref()));
+    }
+    let st = f.stats.as_ref()?;
+    Some(ColumnView::Stats {
+        min: st.min_values.get(col),
+        max: st.max_values.get(col),
+        nulls: st.null_count.get(col).copied(),
+        rows: st.num_records,
+    })
+}
+
+fn cmp_str(a: &str, b: &Literal) -> Ordering {
+    match b {
+        Literal::Num(n) => match a.parse::<f64>() {
+            Ok(x) => x.partial_cmp(n).unwrap_or(Ordering::Equal),
+            Err(_) => a.cmp(&n.to_string()),
+        },
+        Literal::Str(s) => match (a.parse::<f64>(), s.parse::<f64>()) {
+            (Ok(x), Ok(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            _ => a.cmp(s.as_str()),
+        },
+    }
+}
+
+fn cmp_json(a: &serde_json::Value, b: &Literal) -> Option<Ordering> {
+    match a {
+        serde_json::Value::Number(n) => Some(cmp_str(&n.to_string(), b)),
+        serde_json::Value::String(s) => Some(cmp_str(s, b)),
+        serde_json::Value::Bool(v) => Some(cmp_str(&v.to_string(), b)),
+        _ => None,
+    }
+}
+
+fn eval_op(ord: Ordering, op: CmpOp) -> bool {
+    match op {
+        CmpOp::Eq => ord == Ordering::Equal,
+        CmpOp::Ne => ord != Ordering::Equal,
+        CmpOp::Lt => ord == Ordering::Less,
+        CmpOp::Le => ord != Ordering::Greater,
+        CmpOp::Gt => ord == Ordering::Greater,
+        CmpOp::Ge => ord != Ordering::Less,
+    }
+}
+
+fn eval_range(min: Option<Ordering>, max: Option<Ordering>, op: CmpOp) -> Option<bool> {
+    // min/max are orderings of the column bounds relative to the literal
+    let (lo, hi) = (min?, max?);
+    let all = match op {
+        CmpOp::Ne => lo == Ordering::Greater || hi == Ordering::Less,
+        _ => eval_op(lo, op) && eval_op(hi, op),
+    };
+    let none = match op {
+        CmpOp::Eq => lo == Ordering::Greater || hi == Ordering::Less,
+        CmpOp::Ne => lo == Ordering::Equal && hi == Ordering::Equal,
+        CmpOp::Lt => lo != Ordering::Less,
+        CmpOp::Le => lo == Ordering::Greater,
+        CmpOp::Gt => hi != Ordering::Greater,
+        CmpOp::Ge => hi == Ordering::Less,
+    };
+    if none { Some(false) } else if all { Some(true) } else { None }
+}
+
+fn all_null(nulls: Option<u64>, rows: Option<u64>) -> bool {
+    matches!((nulls, rows), (Some(n), Some(r)) if n == r)
+}
+
+fn eval(e: &Expr, f: &AddFileLite) -> Option<bool> {
+    match e {
+        Expr::And(a, b) => match (eval(a, f), eval(b, f)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        Expr::Or(a, b) => match (eval(a, f), eval(b, f)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Expr::Not(a) => eval(a, f).map(|b| !b),
+        Expr::Cmp { col, op, value } => match column(f, col)? {
+            ColumnView::Partition(None) => Some(false),
+            ColumnView::Partition(Some(v)) => Some(eval_op(cmp_str(v, value), *op)),
+            ColumnView::Stats { min, max, nulls, rows } => {
+                if all_null(nulls, rows) { return Some(false); }
+                eval_range(min.and_then(|m| cmp_json(m, value)), max.and_then(|m| cmp_json(m, value)), *op)
+            }
+        },
+        Expr::In { col, values, negated } => {
+            let hit = match column(f, col)? {
+                ColumnView::Partition(None) => return Some(false),
+                ColumnView::Partition(Some(v)) => Some(values.iter().any(|l| cmp_str(v, l) == Ordering::Equal)),
+                ColumnView::Stats { min, max, nulls, rows } => {
+                    if all_null(nulls, rows) { return Some(false); }
+                    let mut any_maybe = false;
+                    for l in values {
+                        match eval_range(min.and_then(|m| cmp_json(m, l)), max.and_then(|m| cmp_json(m, l)), CmpOp::Eq) {
+                            Some(true) => return Some(!negated),
+                            Some(false) => {}
+                            None => any_maybe = true,
+                        }
+                    }
+                    if any_maybe { None } else { Some(false) }
+                }
+            };
+            hit.map(|h| h != *negated)
+        }
+        Expr::IsNull { col, negated } => {
+            let is_null = match column(f, col)? {
+                ColumnView::Partition(v) => Some(v.is_none()),
+                ColumnView::Stats { nulls, rows, .. } => match (nulls, rows) {
+                    (Some(0), _) => Some(false),
+                    (Some(n), Some(r)) if n == r => Some(true),
+                    _ => None,
+                },
+            };
+            is_null.map(|b| b != *negated)
+        }
+    }
+}
+
+// --- parsing
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token { Ident(String), Str(String), Num(f64), Op(&'static str), LParen, RParen, Comma }
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Str(s) => write!(f, "'{}'", s),
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Op(o) => write!(f, "{}", o),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() { i += 1; continue; }
+        match c {
+            '(' => { out.push(Token::LParen); i += 1; }
+            ')' => { out.push(Token::RParen); i += 1; }
+            ',' => { out.push(Token::Comma); i += 1; }
+            '\'' => {
+                let mut v = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(anyhow!("unterminated string in predicate")),
+                        Some('\'') if chars.get(i + 1) == Some(&'\'') => { v.push('\''); i += 2; }
+                        Some('\'') => { i += 1; break; }
+                        Some(ch) => { v.push(*ch); i += 1; }
+                    }
+                }
+                out.push(Token::Str(v));
+            }
+            '=' => { out.push(Token::Op("=")); i += 1; }
+            '!' if chars.get(i + 1) == Some(&'=') => { out.push(Token::Op("!=")); i += 2; }
+            '<' | '>' => {
+                let next = chars.get(i + 1).copied();
+                let (op, len) = match (c, next) {
+                    ('<', Some('=')) => ("<=", 2),
+                    ('<', Some('>')) => ("!=", 2),
+                    ('>', Some('=')) => (">=", 2),
+                    ('<', _) => ("<", 1),
+                    _ => (">", 1),
+                };
+                i += len;
+                out.push(Token::Op(op));
+            }
+            c if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == 'e' || chars[i] == 'E') { i += 1; }
+                let lit: String = chars[start..i].iter().collect();
+                out.push(Token::Num(lit.parse().map_err(|_| anyhow!("invalid number '{}' in predicate", lit))?));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '`' => {
+                let quoted = c == '`';
+                let start = if quoted { i + 1 } else { i };
+                i += 1;
+                while i < chars.len() && (if quoted { chars[i] != '`' } else { chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.' }) { i += 1; }
+                let ident: String = chars[start..i].iter().collect();
+                if quoted {
+                    if i >= chars.len() { return Err(anyhow!("unterminated identifier in predicate")); }
+                    i += 1;
+                }
+                out.push(Token::Ident(ident));
+            }
+            _ => return Err(anyhow!("unexpected character '{}' in predicate", c)),
+        }
+    }
+    Ok(out)
+}
+
+struct Parser { tokens: Vec<Token>, pos: usize }
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.pos) }
+
+    fn next(&mut self) -> Result<Token> {
+        let t = self.tokens.get(self.pos).cloned().ok_or_else(|| anyhow!("unexpected end of predicate"))?;
+        self.pos += 1;
+        Ok(t)
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        if let Some(Token::Ident(s)) = self.peek() {
+            if s.eq_ignore_ascii_case(kw) { self.pos += 1; return true; }
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_and()?;
+        while self.keyword("or") { lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?)); }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_unary()?;
+        while self.keyword("and") { lhs = Expr::And(Box::new(lhs), Box::new(self.parse_unary()?)); }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.keyword("not") { return Ok(Expr::Not(Box::new(self.parse_unary()?))); }
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let e = self.parse_or()?;
+            if self.next()? != Token::RParen { return Err(anyhow!("expected ')' in predicate")); }
+            return Ok(e);
+        }
+        let col = match self.next()? {
+            Token::Ident(s) => s,
+            t => return Err(anyhow!("expected column name, found '{}'", t)),
+        };
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+            if !self.keyword("null") { return Err(anyhow!("expected NULL after IS")); }
+            return Ok(Expr::IsNull { col, negated });
+        }
+        let negated = self.keyword("not");
+        if self.keyword("in") {
+            if self.next()? != Token::LParen { return Err(anyhow!("expected '(' after IN")); }
+            let mut values = vec![self.literal()?];
+            loop {
+                match self.next()? {
+                    Token::Comma => values.push(self.literal()?),
+                    Token::RParen => break,
+                    t => return Err(anyhow!("expected ',' or ')' in IN list, found '{}'", t)),
+                }
+            }
+            return Ok(Expr::In { col, values, negated });
+        }
+        if negated { return Err(anyhow!("expected IN after NOT")); }
+        let op = match self.next()? {
+            Token::Op("=") => CmpOp::Eq,
+            Token::Op("!=") => CmpOp::Ne,
+            Token::Op("<") => CmpOp::Lt,
+            Token::Op("<=") => CmpOp::Le,
+            Token::Op(">") => CmpOp::Gt,
+            Token::Op(">=") => CmpOp::Ge,
+            t => return Err(anyhow!("expected comparison after '{}', found '{}'", col, t)),
+        };
+        Ok(Expr::Cmp { col, op, value: self.literal()? })
+    }
+
+    fn literal(&mut self) -> Result<Literal> {
+        match self.next()? {
+            Token::Str(s) => Ok(Literal::Str(s)),
+            Token::Num(n) => Ok(Literal::Num(n)),
+            t => Err(anyhow!("expected literal, found '{}'", t)),
+        }
+    }
+}
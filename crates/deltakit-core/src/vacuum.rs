@@ -0,0 +1,459 @@
+use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::pins::{self, Pin};
+use crate::{read_checkpoint_actions, read_log, Commit, DeltaTableHandle, TableRoot};
+use storage::{make_object_store, object_path_from_url, parse_uri, PrefixListing, StorageOptions};
+
+const RETENTION_PROPERTY: &str = "delta.deletedFileRetentionDuration";
+// Delta's default for delta.deletedFileRetentionDuration
+const DEFAULT_RETENTION_MS: i64 = 7 * 24 * 3600 * 1000;
+const DEFAULT_LIST_CONCURRENCY: usize = 16;
+const LOG_RETENTION_PROPERTY: &str = "delta.logRetentionDuration";
+const DEFAULT_LOG_RETENTION_MS: i64 = 30 * 24 * 3600 * 1000;
+
+#[derive(Debug, Clone, Default)]
+pub struct VacuumOptions {
+    /// overrides the table's `delta.deletedFileRetentionDuration`
+    pub retention_ms: Option<i64>,
+    /// reference time in epoch millis; now when None
+    pub now_ms: Option<i64>,
+    /// pinned snapshots whose files must survive regardless of log retention
+    pub protect_versions: Vec<i64>,
+    /// pin registry to honour; the table's `_deltakit/pins.json` sidecar when None
+    pub pin_registry: Option<String>,
+    /// concurrent listing requests, one partition prefix each; 1 lists the table root as a single stream
+    pub concurrency: Option<usize>,
+    pub progress: Option<storage::ListProgress>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VacuumClass {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl VacuumClass {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VacuumReport {
+    pub referenced_files: usize,
+    pub existing_files: usize,
+    pub orphans: usize,
+    /// every orphan is past retention: nothing a reader or in-flight writer may need would be touched
+    pub safe: bool,
+    pub retention_ms: i64,
+    /// "option", "table" (delta.deletedFileRetentionDuration) or "default"
+    pub retention_source: String,
+    pub checked_at_ms: i64,
+    /// tombstoned and past retention; a vacuum would delete these
+    pub removed_expired: VacuumClass,
+    /// tombstoned but still within retention; time travel may read them
+    pub removed_retained: VacuumClass,
+    /// never referenced by the log and older than retention; a vacuum would delete these
+    pub unreferenced_expired: VacuumClass,
+    /// never referenced and recent; possibly an in-flight write
+    pub unreferenced_recent: VacuumClass,
+    /// `_change_data/` files whose commit is no longer reachable and past retention
+    pub cdc_expired: VacuumClass,
+    /// `_change_data/` files still readable as change data feed
+    pub cdc_retained: VacuumClass,
+    /// `_`/`.` prefixed paths (other than partition directories and `_change_data/`) that vacuum never touches
+    pub hidden_files: usize,
+    pub deletable_files: usize,
+    pub deletable_bytes: u64,
+    pub latest_version: i64,
+    pub log_retention_ms: i64,
+    pub log_retention_source: String,
+    pub oldest_checkpoint: Option<i64>,
+    /// oldest version still reconstructable from the log within `delta.logRetentionDuration`
+    pub earliest_reachable_version: i64,
+    pub protected_versions: Vec<i64>,
+    /// protected versions the log can no longer reconstruct
+    pub unreachable_protected: Vec<i64>,
+    /// expired candidates that a reachable or protected version still reads; left out of `deletable_*`
+    pub breaks_time_travel: VacuumClass,
+    /// unexpired pins from the registry; their versions are protected
+    pub pins: Vec<Pin>,
+    pub expired_pins: usize,
+    /// orphans that an unexpired pin still reads
+    pub pinned: VacuumClass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VacuumCategory { RemovedExpired, RemovedRetained, UnreferencedExpired, UnreferencedRecent, CdcExpired, CdcRetained }
+
+impl VacuumCategory {
+    pub fn deletable(&self) -> bool { matches!(self, VacuumCategory::RemovedExpired | VacuumCategory::UnreferencedExpired | VacuumCategory::CdcExpired) }
+}
+
+/// One file under the table root that the latest snapshot does not reference.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VacuumCandidate {
+    pub path: String,
+    pub size: u64,
+    pub last_modified_ms: i64,
+    pub category: VacuumCategory,
+    pub reason: String,
+    /// oldest reachable or protected version that still reads this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub needed_by: Option<i64>,
+    /// ids of the unexpired pins whose version reads this file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned_by: Vec<String>,
+}
+
+impl VacuumCandidate {
+    /// past retention and not needed for time travel
+    pub fn deletable(&self) -> bool { self.category.deletable() && self.needed_by.is_none() }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletionBatch {
+    pub batch: usize,
+    pub bytes: u64,
+    /// relative to `root`
+    pub paths: Vec<String>,
+}
+
+/// Deletable files grouped for an external executor; deltakit never deletes anything itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletionPlan {
+    pub root: String,
+    pub checked_at_ms: i64,
+    pub retention_ms: i64,
+    pub files: usize,
+    pub bytes: u64,
+    pub batches: Vec<DeletionBatch>,
+}
+
+impl DeletionPlan {
+    pub fn new(root: &str, report: &VacuumReport, candidates: &[VacuumCandidate], batch_size: usize) -> DeletionPlan {
+        let mut deletable: Vec<&VacuumCandidate> = candidates.iter().filter(|c| c.deletable()).collect();
+        deletable.sort_by(|a, b| a.path.cmp(&b.path));
+        let batches: Vec<DeletionBatch> = deletable
+            .chunks(batch_size.max(1))
+            .enumerate()
+            .map(|(i, chunk)| DeletionBatch { batch: i, bytes: chunk.iter().map(|c| c.size).sum(), paths: chunk.iter().map(|c| c.path.clone()).collect() })
+            .collect();
+        DeletionPlan {
+            root: root.to_string(),
+            checked_at_ms: report.checked_at_ms,
+            retention_ms: report.retention_ms,
+            files: deletable.len(),
+            bytes: deletable.iter().map(|c| c.size).sum(),
+            batches,
+        }
+    }
+}
+
+const Z85: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ.-:+=^!/*?&<>()[]{}@%$#";
+
+fn z85_decode(s: &str) -> Option<Vec<u8>> {
+    let chunks = s.as_bytes().chunks_exact(5);
+    if !chunks.remainder().is_empty() { return None; }
+    let mut out = Vec::with_capacity(s.len() / 5 * 4);
+    for chunk in chunks {
+        let mut v: u64 = 0;
+        for c in chunk { v = v * 85 + Z85.iter().position(|z| z == c)? as u64; }
+        out.extend_from_slice(&u32::try_from(v).ok()?.to_be_bytes());
+    }
+    Some(out)
+}
+
+/// Table-relative path of the deletion-vector file an add/remove action points at, if it has one on disk.
+fn dv_path(action: &serde_json::Value, root: &TableRoot) -> Option<String> {
+    let dv = action.get("deletionVector")?;
+    let raw = dv.get("pathOrInlineDv")?.as_str()?;
+    match dv.get("storageType")?.as_str()? {
+        // <random prefix><20 chars of Z85-encoded uuid>
+        "u" if raw.len() >= 20 => {
+            let (prefix, encoded) = raw.split_at(raw.len() - 20);
+            let b = z85_decode(encoded)?;
+            let uuid = format!(
+                "{}-{}-{}-{}-{}",
+                hex(&b[0..4]), hex(&b[4..6]), hex(&b[6..8]), hex(&b[8..10]), hex(&b[10..16])
+            );
+            let file = format!("deletion_vector_{}.bin", uuid);
+            Some(if prefix.is_empty() { file } else { format!("{}/{}", prefix, file) })
+        }
+        "p" => Some(root.canonical(raw)).filter(|p| !TableRoot::is_external(p)),
+        _ => None,
+    }
+}
+
+fn hex(b: &[u8]) -> String { b.iter().map(|x| format!("{:02x}", x)).collect() }
+
+/// Delta's vacuum rule: any `_` or `.` prefixed path component is hidden, except partition
+/// directories (`<col>=...`) and the `_change_data` directory.
+fn is_hidden(rel: &str, partition_cols: &[String]) -> bool {
+    let parts: Vec<&str> = rel.split('/').collect();
+    parts.iter().enumerate().any(|(i, name)| {
+        if !(name.starts_with('_') || name.starts_with('.')) { return false; }
+        let is_dir = i + 1 < parts.len();
+        if is_dir && i == 0 && *name == "_change_data" { return false; }
+        !(is_dir && partition_cols.iter().any(|c| name.starts_with(&format!("{}=", c))))
+    })
+}
+
+/// Parses Delta interval strings such as `interval 7 days`, `interval 1 week` or `168 hours` into millis.
+pub fn parse_interval_ms(s: &str) -> Result<i64> {
+    let bad = || anyhow!("invalid interval '{}'", s);
+    let lower = s.trim().to_ascii_lowercase();
+    let body = lower.strip_prefix("interval").unwrap_or(&lower).trim();
+    let mut it = body.split_whitespace();
+    let n: f64 = it.next().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+    let unit_ms: f64 = match it.next().ok_or_else(bad)?.trim_end_matches('s') {
+        "millisecond" => 1.0,
+        "second" => 1e3,
+        "minute" => 60e3,
+        "hour" => 3600e3,
+        "day" => 86400e3,
+        "week" => 7.0 * 86400e3,
+        _ => return Err(bad()),
+    };
+    if it.next().is_some() || !n.is_finite() || n < 0.0 { return Err(bad()); }
+    Ok((n * unit_ms).round() as i64)
+}
+
+pub async fn vacuum_dry_run(h: &DeltaTableHandle, retention_days: i64) -> Result<VacuumReport> {
+    vacuum_report(h, &VacuumOptions { retention_ms: Some(retention_days * 24 * 3600 * 1000), ..Default::default() }).await
+}
+
+/// Classifies every file under the table root that the latest snapshot does not reference.
+pub async fn vacuum_report(h: &DeltaTableHandle, opts: &VacuumOptions) -> Result<VacuumReport> {
+    vacuum_candidates(h, opts, |_| Ok(())).await
+}
+
+/// Same as `vacuum_report`, handing each candidate to `sink` as it is classified.
+pub async fn vacuum_candidates<F>(h: &DeltaTableHandle, opts: &VacuumOptions, mut sink: F) -> Result<VacuumReport>
+where
+    F: FnMut(VacuumCandidate) -> Result<()>,
+{
+    let log = read_log(h, None).await?;
+    let root = TableRoot::new(&h.uri)?;
+    // replay from commit 0 while it exists; once early commits are cleaned up, live files, tombstones
+    // and metadata are only in a checkpoint, so seed from the oldest one the remaining commits continue
+    let latest_version = log.commits.last().map(|c| c.version).unwrap_or(0);
+    let base = if log.commits.first().is_some_and(|c| c.version == 0) {
+        None
+    } else {
+        let continues = |cp: i64| log.commits.iter().map(|c| c.version).filter(|v| *v > cp).eq(cp + 1..=latest_version);
+        let cp = log.checkpoints.iter().copied().find(|cp| continues(*cp)).ok_or_else(|| {
+            anyhow!("the log of {} has no commit 0 and no checkpoint followed by every later commit; refusing to classify files for deletion", h.uri)
+        })?;
+        Some(cp)
+    };
+    let seed = match base {
+        Some(cp) => vec![Commit { version: cp, timestamp_ms: 0, actions: read_checkpoint_actions(h, cp, &["add", "remove", "metaData"]).await? }],
+        None => Vec::new(),
+    };
+    let replayed = seed.iter().chain(log.commits.iter().filter(|c| !matches!(base, Some(cp) if c.version <= cp)));
+    let mut referenced: HashSet<String> = HashSet::new();
+    // path -> deletionTimestamp of its latest remove; cleared when the path is added again
+    let mut tombstones: HashMap<String, i64> = HashMap::new();
+    // path -> versions [from, to) during which it was active; None means still active,
+    // a start of -1 means it was added before the first commit we can read
+    let mut lifetimes: HashMap<String, Vec<(i64, Option<i64>)>> = HashMap::new();
+    let mut config: HashMap<String, String> = HashMap::new();
+    let mut partition_cols: Vec<String> = Vec::new();
+    // _change_data file -> (version, commit timestamp) of the cdc action that wrote it
+    let mut cdc: HashMap<String, (i64, i64)> = HashMap::new();
+    let mut has_metadata = false;
+    for c in replayed {
+        for a in c.removes_first() {
+            if let Some(add) = a.get("add") {
+                let Some(path) = add.get("path").and_then(|p| p.as_str()) else { continue };
+                // a file's deletion vector lives and dies with it
+                for path in std::iter::once(root.canonical(path)).chain(dv_path(add, &root)) {
+                    tombstones.remove(&path);
+                    referenced.insert(path.clone());
+                    let spans = lifetimes.entry(path).or_default();
+                    if !spans.last().is_some_and(|(_, end)| end.is_none()) { spans.push((c.version, None)); }
+                }
+            } else if let Some(rm) = a.get("remove") {
+                let Some(path) = rm.get("path").and_then(|p| p.as_str()) else { continue };
+                let deleted_at = rm.get("deletionTimestamp").and_then(|t| t.as_i64()).unwrap_or(0);
+                for path in std::iter::once(root.canonical(path)).chain(dv_path(rm, &root)) {
+                    referenced.remove(&path);
+                    tombstones.insert(path.clone(), deleted_at);
+                    let spans = lifetimes.entry(path).or_default();
+                    match spans.last_mut() {
+                        Some((_, end @ None)) => *end = Some(c.version),
+                        _ => spans.push((-1, Some(c.version))),
+                    }
+                }
+            } else if let Some(path) = a.get("cdc").and_then(|o| o.get("path")).and_then(|p| p.as_str()) {
+                cdc.insert(root.canonical(path), (c.version, c.timestamp_ms));
+            } else if let Some(meta) = a.get("metaData") {
+                has_metadata = true;
+                if let Some(conf) = meta.get("configuration").and_then(|c| c.as_object()) {
+                    config = conf.iter().filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string()))).collect();
+                }
+                if let Some(cols) = meta.get("partitionColumns").and_then(|c| c.as_array()) {
+                    partition_cols = cols.iter().filter_map(|c| c.as_str().map(|c| c.to_string())).collect();
+                }
+            }
+        }
+    }
+
+    if !has_metadata { return Err(anyhow!("no metaData in the log of {}", h.uri)); }
+
+    let (retention_ms, retention_source) = match (opts.retention_ms, config.get(RETENTION_PROPERTY)) {
+        (Some(ms), _) => (ms, "option"),
+        (None, Some(v)) => (parse_interval_ms(v).map_err(|e| anyhow!("{}: {}", RETENTION_PROPERTY, e))?, "table"),
+        (None, None) => (DEFAULT_RETENTION_MS, "default"),
+    };
+    let (log_retention_ms, log_retention_source) = match config.get(LOG_RETENTION_PROPERTY) {
+        Some(v) => (parse_interval_ms(v).map_err(|e| anyhow!("{}: {}", LOG_RETENTION_PROPERTY, e))?, "table"),
+        None => (DEFAULT_LOG_RETENTION_MS, "default"),
+    };
+    let now = opts.now_ms.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    let cutoff = now - retention_ms;
+
+    // versions are reconstructable from commit 0, or from the checkpoint replay started at once early commits are gone;
+    // commits older than the log retention may be cleaned up at the next checkpoint, so only newer ones count
+    let base = base.unwrap_or(0);
+    let earliest_reachable_version = log
+        .commits
+        .iter()
+        .find(|c| c.version >= base && c.timestamp_ms >= now - log_retention_ms)
+        .map(|c| c.version)
+        .unwrap_or(latest_version);
+    let registry = opts.pin_registry.clone().unwrap_or_else(|| pins::default_pin_registry(&h.uri));
+    let (active_pins, expired): (Vec<Pin>, Vec<Pin>) = pins::list_pins(&registry, &h.uri).await?.into_iter().partition(|p| p.active(now));
+    let mut protected: Vec<i64> = opts.protect_versions.iter().copied().chain(active_pins.iter().map(|p| p.version)).collect();
+    protected.sort_unstable();
+    protected.dedup();
+    if let Some(v) = protected.iter().find(|v| **v > latest_version) {
+        return Err(anyhow!("protected version {} is newer than the latest version {}", v, latest_version));
+    }
+    let unreachable_protected: Vec<i64> = protected.iter().copied().filter(|v| *v < base).collect();
+    let pinned_by = |path: &str| -> Vec<String> {
+        let Some(spans) = lifetimes.get(path) else { return Vec::new() };
+        active_pins
+            .iter()
+            .filter(|p| p.version >= base && spans.iter().any(|(from, to)| p.version >= *from && p.version < to.unwrap_or(i64::MAX)))
+            .map(|p| p.id.clone())
+            .collect()
+    };
+    // oldest version in [from, to) that users can still read
+    let needed_by = |path: &str| -> Option<i64> {
+        let spans = lifetimes.get(path)?;
+        spans
+            .iter()
+            .filter_map(|(from, to)| {
+                let to = to.unwrap_or(i64::MAX);
+                let reachable = (*from).max(earliest_reachable_version);
+                let pinned = protected.iter().copied().find(|p| *p >= *from && *p < to && *p >= base);
+                [(reachable < to).then_some(reachable), pinned].into_iter().flatten().min()
+            })
+            .min()
+    };
+
+    let parsed = parse_uri(&h.uri)?;
+    let store = make_object_store(&h.uri, &StorageOptions::default()).await?;
+    let prefix = object_path_from_url(&parsed.url);
+    let concurrency = opts.concurrency.unwrap_or(DEFAULT_LIST_CONCURRENCY);
+    let root_str = prefix.as_ref();
+    // one delimiter level per partition column (at least one, to split off _delta_log and friends);
+    // the log is never listed and hidden directories are only listed to be counted
+    let depth = partition_cols.len().max(1);
+    let filter = |p: &object_store::path::Path| {
+        let rel = p.as_ref().strip_prefix(root_str).unwrap_or(p.as_ref()).trim_start_matches('/');
+        if rel == "_delta_log" {
+            PrefixListing::Skip
+        } else if is_hidden(&format!("{}/", rel), &partition_cols) {
+            PrefixListing::Flat
+        } else {
+            PrefixListing::Descend
+        }
+    };
+    let mut listing = storage::list_partitioned(store, &prefix, depth, concurrency, filter, opts.progress.clone()).await?;
+
+    let mut report = VacuumReport {
+        referenced_files: referenced.len(),
+        existing_files: 0,
+        orphans: 0,
+        safe: true,
+        retention_ms,
+        retention_source: retention_source.to_string(),
+        checked_at_ms: now,
+        removed_expired: VacuumClass::default(),
+        removed_retained: VacuumClass::default(),
+        unreferenced_expired: VacuumClass::default(),
+        unreferenced_recent: VacuumClass::default(),
+        cdc_expired: VacuumClass::default(),
+        cdc_retained: VacuumClass::default(),
+        hidden_files: 0,
+        deletable_files: 0,
+        deletable_bytes: 0,
+        latest_version,
+        log_retention_ms,
+        log_retention_source: log_retention_source.to_string(),
+        oldest_checkpoint: log.checkpoints.first().copied(),
+        earliest_reachable_version,
+        protected_versions: protected.clone(),
+        unreachable_protected,
+        breaks_time_travel: VacuumClass::default(),
+        pins: active_pins.clone(),
+        expired_pins: expired.len(),
+        pinned: VacuumClass::default(),
+    };
+    while let Some(m) = listing.try_next().await? {
+        let full = m.location.as_ref();
+        let rel = full.strip_prefix(root_str).unwrap_or(full).trim_start_matches('/');
+        if rel.is_empty() { continue; }
+        if is_hidden(rel, &partition_cols) { report.hidden_files += 1; continue; }
+        report.existing_files += 1;
+        if referenced.contains(rel) { continue; }
+        report.orphans += 1;
+        let bytes = m.size as u64;
+        let modified = m.last_modified.timestamp_millis();
+        let age = |at: i64| humantime::format_duration(std::time::Duration::from_secs(((now - at).max(0) / 1000) as u64));
+        let (category, mut reason) = match (cdc.get(rel), tombstones.get(rel)) {
+            // change data is read through the change data feed for as long as its commit is reachable
+            (Some((v, _)), _) if *v >= earliest_reachable_version || protected.contains(v) => (VacuumCategory::CdcRetained, format!("change data of version {}, still reachable", v)),
+            (Some((_, ts)), _) if *ts >= cutoff => (VacuumCategory::CdcRetained, format!("change data written {} ago, within retention", age(*ts))),
+            (Some((v, _)), _) => (VacuumCategory::CdcExpired, format!("change data of version {}, past retention", v)),
+            (None, Some(deleted_at)) if *deleted_at < cutoff => (VacuumCategory::RemovedExpired, format!("removed {} ago, past retention", age(*deleted_at))),
+            (None, Some(deleted_at)) => (VacuumCategory::RemovedRetained, format!("removed {} ago, within retention", age(*deleted_at))),
+            (None, None) if modified < cutoff => (VacuumCategory::UnreferencedExpired, format!("never referenced, modified {} ago", age(modified))),
+            (None, None) => (VacuumCategory::UnreferencedRecent, format!("never referenced, modified {} ago; possibly an in-flight write", age(modified))),
+        };
+        match category {
+            VacuumCategory::RemovedExpired => report.removed_expired.add(bytes),
+            VacuumCategory::RemovedRetained => report.removed_retained.add(bytes),
+            VacuumCategory::UnreferencedExpired => report.unreferenced_expired.add(bytes),
+            VacuumCategory::UnreferencedRecent => report.unreferenced_recent.add(bytes),
+            VacuumCategory::CdcExpired => report.cdc_expired.add(bytes),
+            VacuumCategory::CdcRetained => report.cdc_retained.add(bytes),
+        }
+        let needed = needed_by(rel);
+        if let Some(v) = needed {
+            reason.push_str(&format!("; still read by version {}", v));
+        }
+        let pinned_ids = pinned_by(rel);
+        if !pinned_ids.is_empty() {
+            report.pinned.add(bytes);
+            reason.push_str(&format!("; pinned by {}", pinned_ids.join(",")));
+        }
+        if category.deletable() {
+            match needed {
+                Some(_) => report.breaks_time_travel.add(bytes),
+                None => { report.deletable_files += 1; report.deletable_bytes += bytes; }
+            }
+        }
+        sink(VacuumCandidate { path: rel.to_string(), size: bytes, last_modified_ms: modified, category, reason, needed_by: needed, pinned_by: pinned_ids })?;
+    }
+    report.safe = report.removed_retained.files == 0 && report.unreferenced_recent.files == 0 && report.breaks_time_travel.files == 0 && report.pinned.files == 0;
+    Ok(report)
+}
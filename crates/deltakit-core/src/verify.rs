@@ -0,0 +1,123 @@
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use object_store::path::Path as ObjPath;
+use object_store::DynObjectStore;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{list_active_files, AddFileLite, DeltaTableHandle, ExclusionList, TableRoot};
+
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const DEFAULT_VERIFY_CONCURRENCY: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileProblem {
+    Missing,
+    SizeMismatch,
+    Unreadable,
+    /// deep mode: no `PAR1` magic at both ends, or a footer length that does not fit the file
+    BadFooter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileIssue {
+    pub path: String,
+    pub problem: FileProblem,
+    /// `size` from the log
+    pub expected_size: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual_size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// also read each file's first and last bytes and check the Parquet magic and footer length
+    pub deep: bool,
+    pub exclude: ExclusionList,
+    pub concurrency: Option<usize>,
+}
+
+/// Physical check of every active file of a version; `ok` is false when any file has an issue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileVerification {
+    pub table: String,
+    pub version: i64,
+    pub ok: bool,
+    pub deep: bool,
+    pub files_checked: usize,
+    pub bytes_checked: i64,
+    pub missing: usize,
+    pub size_mismatches: usize,
+    pub unreadable: usize,
+    pub bad_footers: usize,
+    /// sorted by path
+    pub issues: Vec<FileIssue>,
+}
+
+pub async fn verify_files(h: &DeltaTableHandle, version: i64, opts: &VerifyOptions) -> Result<FileVerification> {
+    let (files, _) = opts.exclude.apply(list_active_files(h, Some(version)).await?);
+    let root = TableRoot::new(&h.uri)?;
+    let store = root.store().await?;
+    let checks = files.iter().map(|f| {
+        let (root, store) = (&root, store.clone());
+        async move {
+            match root.resolve(store, &f.path).await {
+                Ok((store, loc)) => check_file(store, &loc, f, opts.deep).await,
+                Err(e) => Some(issue(f, FileProblem::Unreadable, None, Some(e.to_string()))),
+            }
+        }
+    });
+    let mut issues: Vec<FileIssue> = stream::iter(checks)
+        .buffer_unordered(opts.concurrency.unwrap_or(DEFAULT_VERIFY_CONCURRENCY).max(1))
+        .filter_map(|i| async move { i })
+        .collect()
+        .await;
+    issues.sort_by(|a, b| a.path.cmp(&b.path));
+    let count = |p: FileProblem| issues.iter().filter(|i| i.problem == p).count();
+    Ok(FileVerification {
+        table: h.uri.clone(),
+        version,
+        ok: issues.is_empty(),
+        deep: opts.deep,
+        files_checked: files.len(),
+        bytes_checked: files.iter().map(|f| f.size).sum(),
+        missing: count(FileProblem::Missing),
+        size_mismatches: count(FileProblem::SizeMismatch),
+        unreadable: count(FileProblem::Unreadable),
+        bad_footers: count(FileProblem::BadFooter),
+        issues,
+    })
+}
+
+fn issue(f: &AddFileLite, problem: FileProblem, actual_size: Option<i64>, error: Option<String>) -> FileIssue {
+    FileIssue { path: f.path.clone(), problem, expected_size: f.size, actual_size, error }
+}
+
+async fn check_file(store: Arc<DynObjectStore>, loc: &ObjPath, f: &AddFileLite, deep: bool) -> Option<FileIssue> {
+    let meta = match storage::head_if_exists(store.clone(), loc).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return Some(issue(f, FileProblem::Missing, None, None)),
+        Err(e) => return Some(issue(f, FileProblem::Unreadable, None, Some(format!("{:#}", e)))),
+    };
+    let size = meta.size;
+    if size as i64 != f.size { return Some(issue(f, FileProblem::SizeMismatch, Some(size as i64), None)); }
+    if !deep { return None; }
+    // PAR1 <data> <footer> <footer length: u32 LE> PAR1
+    if size < 12 { return Some(issue(f, FileProblem::BadFooter, Some(size as i64), Some(format!("{} bytes is too small for Parquet", size)))); }
+    let (head, tail) = match (storage::head_range(store.clone(), loc, 0..4).await, storage::head_range(store, loc, size - 8..size).await) {
+        (Ok(h), Ok(t)) => (h, t),
+        (Err(e), _) | (_, Err(e)) => return Some(issue(f, FileProblem::Unreadable, Some(size as i64), Some(format!("{:#}", e)))),
+    };
+    let footer_len = u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]) as usize;
+    let error = if &head[..] != PARQUET_MAGIC || &tail[4..] != PARQUET_MAGIC {
+        Some("missing PAR1 magic".to_string())
+    } else if footer_len > size - 12 {
+        Some(format!("footer length {} exceeds the file", footer_len))
+    } else {
+        None
+    };
+    error.map(|e| issue(f, FileProblem::BadFooter, Some(size as i64), Some(e)))
+}
@@ -40,6 +40,10 @@ fn remove_action(path: &str) -> String {
     format!("{{\"remove\":{{\"path\":\"{}\",\"deletionTimestamp\":0,\"dataChange\":true}}}}", path)
 }
 
+fn commit_info(timestamp_ms: i64) -> String {
+    format!("{{\"commitInfo\":{{\"timestamp\":{},\"operation\":\"WRITE\"}}}}", timestamp_ms)
+}
+
 fn touch_file(dir: &PathBuf, rel: &str) {
     let p = dir.join(rel);
     if let Some(parent) = p.parent() { fs::create_dir_all(parent).unwrap(); }
@@ -99,3 +103,943 @@ async fn test_mvp_end_to_end_local() {
 }
 
 
+
+#[tokio::test]
+async fn test_predicate_prunes_on_partitions_and_stats() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let with_stats = |path: &str, dt: &str, min: i64, max: i64| format!(
+        "{{\"add\":{{\"path\":\"{}\",\"size\":10,\"partitionValues\":{{\"dt\":\"{}\"}},\"modificationTime\":0,\"dataChange\":true,\"stats\":\"{{\\\"numRecords\\\":4,\\\"minValues\\\":{{\\\"id\\\":{}}},\\\"maxValues\\\":{{\\\"id\\\":{}}},\\\"nullCount\\\":{{\\\"id\\\":0}}}}\"}}}}",
+        path, dt, min, max
+    );
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        with_stats("dt=2024-01-01/a.parquet", "2024-01-01", 0, 9),
+        with_stats("dt=2024-01-02/b.parquet", "2024-01-02", 10, 19),
+        with_stats("dt=2024-01-02/c.parquet", "2024-01-02", 20, 29),
+    ]);
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let files = core::list_active_files(&h, Some(0)).await.unwrap();
+    assert_eq!(files[0].stats.as_ref().unwrap().num_records, Some(4));
+
+    let kept = |expr: &str| {
+        let p = core::Predicate::parse(expr).unwrap();
+        files.iter().filter(|f| p.may_match(f)).map(|f| f.path.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(kept("dt >= '2024-01-02'").len(), 2);
+    assert_eq!(kept("dt = '2024-01-02' AND id < 15"), vec!["dt=2024-01-02/b.parquet".to_string()]);
+    assert_eq!(kept("id IN (5, 25)").len(), 2);
+    assert_eq!(kept("NOT (id >= 10) OR dt IN ('2024-01-03')"), vec!["dt=2024-01-01/a.parquet".to_string()]);
+    assert_eq!(kept("id IS NULL").len(), 0);
+    assert_eq!(kept("other_col = 'x'").len(), 3);
+    assert!(core::Predicate::parse("dt >= ").is_err());
+    assert!(core::Predicate::parse("dt IN ('a'").is_err());
+}
+
+#[tokio::test]
+async fn test_exclusion_list_skips_quarantined_files() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join("table");
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=2024-01-01/a.parquet", 10, "dt", "2024-01-01", 1),
+        add_action("dt=2024-01-01/bad-1.parquet", 10, "dt", "2024-01-01", 1),
+        add_action("dt=2024-01-02/a.parquet", 10, "dt", "2024-01-02", 1),
+        add_action("dt=2024-01-03/a.parquet", 10, "dt", "2024-01-03", 1),
+    ]);
+    let list_path = temp.path().join("quarantine.txt");
+    fs::write(&list_path, "# known-bad files\n**/bad-*.parquet  # truncated footer, INC-1234\ndt=2024-01-03/\tlabel leak\n").unwrap();
+    let list = core::ExclusionList::load(&list_path.to_string_lossy()).await.unwrap();
+    assert_eq!(list.entries.len(), 2);
+    assert!(core::ExclusionList::parse("dt=2024-01-01/a.parquet\n", None).is_err());
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let manifest = core::generate_manifest_excluding(&h, 0, core::ManifestFormat::FileList, &list).await.unwrap();
+    let kept: Vec<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(kept.len(), 2);
+    assert!(!kept.iter().any(|p| p.contains("bad-") || p.starts_with("dt=2024-01-03")));
+    let mut skipped: Vec<(&str, &str)> = manifest.excluded.iter().map(|e| (e.path.as_str(), e.reason.as_str())).collect();
+    skipped.sort_unstable();
+    assert_eq!(skipped, vec![("dt=2024-01-01/bad-1.parquet", "truncated footer, INC-1234"), ("dt=2024-01-03/a.parquet", "label leak")]);
+}
+
+#[tokio::test]
+async fn test_vacuum_classifies_orphans_by_retention() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let day: i64 = 24 * 3600 * 1000;
+    let now = chrono::Utc::now().timestamp_millis();
+    // log retention of a day keeps old versions out of reach, so only deletedFileRetentionDuration matters here
+    let meta = metadata_action(&["dt"]).replace("\"configuration\":{}", "\"configuration\":{\"delta.deletedFileRetentionDuration\":\"interval 2 days\",\"delta.logRetentionDuration\":\"interval 1 day\"}");
+    let remove_at = |path: &str, ts: i64| format!("{{\"remove\":{{\"path\":\"{}\",\"deletionTimestamp\":{},\"dataChange\":true}}}}", path, ts);
+    write_delta_log(&dir, 0, &[
+        commit_info(now - 6 * day),
+        protocol_action(),
+        meta,
+        add_action("dt=2024-01-01/old.parquet", 4, "dt", "2024-01-01", 1),
+        add_action("dt=2024-01-01/new.parquet", 4, "dt", "2024-01-01", 1),
+        add_action("dt=2024-01-01/live.parquet", 4, "dt", "2024-01-01", 1),
+    ]);
+    write_delta_log(&dir, 1, &[commit_info(now - 5 * day), remove_at("dt=2024-01-01/old.parquet", now - 5 * day), remove_at("dt=2024-01-01/new.parquet", now - day / 2)]);
+    fs::create_dir_all(dir.join("dt=2024-01-01")).unwrap();
+    for f in ["old", "new", "live"] { fs::write(dir.join(format!("dt=2024-01-01/{}.parquet", f)), b"abcd").unwrap(); }
+    fs::write(dir.join("stray.parquet"), b"abcdefgh").unwrap();
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let vac = core::vacuum_report(&h, &core::VacuumOptions::default()).await.unwrap();
+    assert_eq!((vac.retention_ms, vac.retention_source.as_str()), (2 * day, "table"));
+    assert_eq!(vac.orphans, 3);
+    assert_eq!((vac.removed_expired.files, vac.removed_expired.bytes), (1, 4));
+    assert_eq!(vac.removed_retained.files, 1);
+    assert_eq!((vac.unreferenced_recent.files, vac.unreferenced_recent.bytes), (1, 8));
+    assert_eq!(vac.deletable_files, 1);
+    assert!(!vac.safe);
+
+    // a week later everything orphaned has aged out
+    let later = core::vacuum_report(&h, &core::VacuumOptions { now_ms: Some(now + 7 * day), ..Default::default() }).await.unwrap();
+    assert_eq!((later.removed_expired.files, later.unreferenced_expired.files), (2, 1));
+    assert_eq!(later.deletable_bytes, 16);
+    assert!(later.safe);
+
+    let mut seen = Vec::new();
+    let report = core::vacuum_candidates(&h, &core::VacuumOptions::default(), |c| { seen.push(c); Ok(()) }).await.unwrap();
+    assert_eq!(seen.len(), report.orphans);
+    let stray = seen.iter().find(|c| c.path == "stray.parquet").unwrap();
+    assert_eq!((stray.category, stray.size), (core::VacuumCategory::UnreferencedRecent, 8));
+    assert!(stray.reason.contains("in-flight"));
+    let plan = core::DeletionPlan::new("/t", &later, &seen.iter().cloned().map(|mut c| { c.category = core::VacuumCategory::RemovedExpired; c }).collect::<Vec<_>>(), 2);
+    assert_eq!((plan.files, plan.batches.len()), (3, 2));
+    assert_eq!(plan.batches[1].paths, vec!["stray.parquet".to_string()]);
+}
+
+#[tokio::test]
+async fn test_vacuum_keeps_files_reachable_by_time_travel() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let day: i64 = 24 * 3600 * 1000;
+    let now = chrono::Utc::now().timestamp_millis();
+    let remove_at = |path: &str, ts: i64| format!("{{\"remove\":{{\"path\":\"{}\",\"deletionTimestamp\":{},\"dataChange\":true}}}}", path, ts);
+    write_delta_log(&dir, 0, &[
+        commit_info(now - 40 * day),
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=2024-01-01/a.parquet", 4, "dt", "2024-01-01", 1),
+        add_action("dt=2024-01-01/b.parquet", 4, "dt", "2024-01-01", 1),
+    ]);
+    write_delta_log(&dir, 1, &[commit_info(now - 20 * day), remove_at("dt=2024-01-01/a.parquet", now - 20 * day)]);
+    write_delta_log(&dir, 2, &[commit_info(now - 10 * day), remove_at("dt=2024-01-01/b.parquet", now - 10 * day)]);
+    touch_file(&dir, "_delta_log/00000000000000000001.checkpoint.parquet");
+    for f in ["a", "b"] { touch_file(&dir, &format!("dt=2024-01-01/{}.parquet", f)); }
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let opts = core::VacuumOptions { retention_ms: Some(7 * day), ..Default::default() };
+    let mut seen = Vec::new();
+    let vac = core::vacuum_candidates(&h, &opts, |c| { seen.push(c); Ok(()) }).await.unwrap();
+    // v0 is older than the 30-day default log retention; v1 and v2 are still reachable
+    assert_eq!((vac.earliest_reachable_version, vac.latest_version, vac.oldest_checkpoint), (1, 2, Some(1)));
+    assert_eq!(vac.removed_expired.files, 2);
+    assert_eq!((vac.deletable_files, vac.breaks_time_travel.files), (1, 1));
+    let b = seen.iter().find(|c| c.path.ends_with("b.parquet")).unwrap();
+    assert_eq!(b.needed_by, Some(1));
+    assert!(!b.deletable());
+    assert!(!vac.safe);
+
+    let pinned = core::vacuum_report(&h, &core::VacuumOptions { protect_versions: vec![0], ..opts.clone() }).await.unwrap();
+    assert_eq!((pinned.deletable_files, pinned.breaks_time_travel.files), (0, 2));
+    assert!(pinned.unreachable_protected.is_empty());
+    assert!(core::vacuum_report(&h, &core::VacuumOptions { protect_versions: vec![9], ..opts }).await.is_err());
+}
+
+#[tokio::test]
+async fn test_vacuum_after_log_cleanup_reads_the_checkpoint() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let day: i64 = 24 * 3600 * 1000;
+    let now = chrono::Utc::now().timestamp_millis();
+    let old = now - 60 * day;
+    write_delta_log(&dir, 0, &[
+        commit_info(old),
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=1/live.parquet", 4, "dt", "1", 1),
+        add_action("dt=1/gone.parquet", 4, "dt", "1", 1),
+    ]);
+    write_delta_log(&dir, 1, &[commit_info(old), format!("{{\"remove\":{{\"path\":\"dt=1/gone.parquet\",\"deletionTimestamp\":{},\"dataChange\":true}}}}", old)]);
+    write_checkpoint(&dir, 1, &[("delta.deletedFileRetentionDuration", "interval 90 days")], &["dt=1/live.parquet"], &[("dt=1/gone.parquet", old)]);
+    write_delta_log(&dir, 2, &[commit_info(now - day), add_action("dt=2/new.parquet", 4, "dt", "2", 1)]);
+    for f in ["dt=1/live.parquet", "dt=1/gone.parquet", "dt=2/new.parquet", "dt=2/stray.parquet"] { touch_file(&dir, f); }
+    fs::remove_file(dir.join("_delta_log/00000000000000000000.json")).unwrap();
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let mut seen = Vec::new();
+    let vac = core::vacuum_candidates(&h, &core::VacuumOptions::default(), |c| { seen.push(c); Ok(()) }).await.unwrap();
+    let paths: Vec<&str> = seen.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(paths, vec!["dt=1/gone.parquet", "dt=2/stray.parquet"]);
+    // the checkpoint's tombstone and retention apply: 60 days is within the table's 90
+    assert_eq!((vac.retention_source.as_str(), vac.removed_retained.files), ("table", 1));
+    assert_eq!(vac.referenced_files, 2);
+
+    // without a checkpoint to start from, nothing is classified
+    fs::remove_file(dir.join("_delta_log/00000000000000000001.checkpoint.parquet")).unwrap();
+    assert!(core::vacuum_report(&h, &core::VacuumOptions::default()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_pins_protect_files_from_vacuum() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let day: i64 = 24 * 3600 * 1000;
+    let now = chrono::Utc::now().timestamp_millis();
+    let meta = metadata_action(&["dt"]).replace("\"configuration\":{}", "\"configuration\":{\"delta.logRetentionDuration\":\"interval 1 day\"}");
+    let remove_at = |path: &str, ts: i64| format!("{{\"remove\":{{\"path\":\"{}\",\"deletionTimestamp\":{},\"dataChange\":true}}}}", path, ts);
+    write_delta_log(&dir, 0, &[commit_info(now - 30 * day), protocol_action(), meta, add_action("dt=2024-01-01/a.parquet", 4, "dt", "2024-01-01", 1)]);
+    write_delta_log(&dir, 1, &[commit_info(now - 20 * day), remove_at("dt=2024-01-01/a.parquet", now - 20 * day)]);
+    touch_file(&dir, "dt=2024-01-01/a.parquet");
+
+    let uri = dir.to_string_lossy().to_string();
+    let registry = core::pins::default_pin_registry(&uri);
+    let h = core::load_table(&uri).await.unwrap();
+    assert_eq!(core::vacuum_report(&h, &core::VacuumOptions::default()).await.unwrap().deletable_files, 1);
+
+    let pin = core::pins::add_pin(&registry, &uri, 0, "llm-pretrain", Some(now + 14 * day)).await.unwrap();
+    core::pins::add_pin(&registry, &uri, 0, "old-eval", Some(now - day)).await.unwrap();
+    assert!(core::pins::add_pin(&registry, &uri, 5, "nobody", None).await.is_err());
+    assert_eq!(core::pins::list_pins(&registry, &uri).await.unwrap().len(), 2);
+
+    let mut seen = Vec::new();
+    let vac = core::vacuum_candidates(&h, &core::VacuumOptions::default(), |c| { seen.push(c); Ok(()) }).await.unwrap();
+    // the sidecar registry itself is never a vacuum candidate
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0].pinned_by, vec![pin.id.clone()]);
+    assert_eq!((vac.pins.len(), vac.expired_pins, vac.pinned.files, vac.deletable_files), (1, 1, 1, 0));
+    assert!(!vac.safe);
+
+    let removed = core::pins::remove_pins(&registry, &uri, 0, Some("llm-pretrain")).await.unwrap();
+    assert_eq!(removed, vec![pin]);
+    assert!(core::vacuum_report(&h, &core::VacuumOptions::default()).await.unwrap().safe);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_pin_adds_are_all_kept() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    write_delta_log(&dir, 0, &[protocol_action(), metadata_action(&["dt"]), add_action("dt=1/a.parquet", 4, "dt", "1", 1)]);
+    let uri = dir.to_string_lossy().to_string();
+    let registry = core::pins::default_pin_registry(&uri);
+
+    let owners: Vec<String> = (0..8).map(|i| format!("job-{}", i)).collect();
+    let adds = owners.iter().map(|o| {
+        let (registry, uri, o) = (registry.clone(), uri.clone(), o.clone());
+        tokio::spawn(async move { core::pins::add_pin(&registry, &uri, 0, &o, None).await })
+    });
+    for r in futures::future::join_all(adds).await { r.unwrap().unwrap(); }
+    let mut listed: Vec<String> = core::pins::list_pins(&registry, &uri).await.unwrap().into_iter().map(|p| p.owner).collect();
+    listed.sort();
+    assert_eq!(listed, owners);
+
+    let removed = core::pins::remove_pins(&registry, &uri, 0, Some("job-3")).await.unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(core::pins::list_pins(&registry, &uri).await.unwrap().len(), 7);
+    assert_eq!(core::pins::remove_pins(&registry, &uri, 0, None).await.unwrap().len(), 7);
+    assert!(core::pins::list_pins(&registry, &uri).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_vacuum_follows_hidden_file_rules() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let day: i64 = 24 * 3600 * 1000;
+    let now = chrono::Utc::now().timestamp_millis();
+    // example deletion vector from the Delta protocol
+    let with_dv = serde_json::json!({"add": {
+        "path": "_part=1/a.parquet", "size": 4, "partitionValues": {"_part": "1"}, "modificationTime": 0, "dataChange": true,
+        "deletionVector": {"storageType": "u", "pathOrInlineDv": "ab^-aqEH.-t@S}K{vb[*k^", "offset": 4, "sizeInBytes": 40, "cardinality": 6}
+    }}).to_string();
+    let cdc = |path: &str| serde_json::json!({"cdc": {"path": path, "partitionValues": {}, "size": 4, "dataChange": false}}).to_string();
+    write_delta_log(&dir, 0, &[commit_info(now - 40 * day), protocol_action(), metadata_action(&["_part"]), with_dv, cdc("_change_data/cdc-old.parquet")]);
+    write_delta_log(&dir, 1, &[commit_info(now - day), cdc("_change_data/cdc-new.parquet")]);
+    for f in [
+        "_part=1/a.parquet",
+        "_part=1/stray.parquet",
+        "ab/deletion_vector_d2c639aa-8816-431a-aaf6-d3fe2512ff61.bin",
+        "_change_data/cdc-old.parquet",
+        "_change_data/cdc-new.parquet",
+        "_part=1/.a.parquet.crc",
+        "_committed_1234",
+        "_symlink_format_manifest/manifest",
+        "_tmp/x.parquet",
+    ] {
+        touch_file(&dir, f);
+    }
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let mut seen = Vec::new();
+    let vac = core::vacuum_candidates(&h, &core::VacuumOptions::default(), |c| { seen.push(c); Ok(()) }).await.unwrap();
+    assert_eq!(vac.hidden_files, 4);
+    assert_eq!(vac.referenced_files, 2);
+    let mut paths: Vec<(&str, core::VacuumCategory)> = seen.iter().map(|c| (c.path.as_str(), c.category)).collect();
+    paths.sort_by(|a, b| a.0.cmp(b.0));
+    assert_eq!(paths, vec![
+        ("_change_data/cdc-new.parquet", core::VacuumCategory::CdcRetained),
+        ("_change_data/cdc-old.parquet", core::VacuumCategory::CdcExpired),
+        ("_part=1/stray.parquet", core::VacuumCategory::UnreferencedRecent),
+    ]);
+    assert_eq!(vac.cdc_expired.files, 1);
+}
+
+#[tokio::test]
+async fn test_vacuum_keeps_file_whose_deletion_vector_was_replaced() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let day: i64 = 24 * 3600 * 1000;
+    let now = chrono::Utc::now().timestamp_millis();
+    let dv = |prefix: &str| serde_json::json!({"storageType": "u", "pathOrInlineDv": format!("{}-aqEH.-t@S}}K{{vb[*k^", prefix), "offset": 1, "sizeInBytes": 40, "cardinality": 6});
+    let add = |prefix: &str| serde_json::json!({"add": {
+        "path": "dt=1/a.parquet", "size": 4, "partitionValues": {"dt": "1"}, "modificationTime": 0, "dataChange": true, "deletionVector": dv(prefix)
+    }}).to_string();
+    let remove = |prefix: &str| serde_json::json!({"remove": {
+        "path": "dt=1/a.parquet", "deletionTimestamp": now - 20 * day, "dataChange": true, "deletionVector": dv(prefix)
+    }}).to_string();
+    write_delta_log(&dir, 0, &[commit_info(now - 40 * day), protocol_action(), metadata_action(&["dt"]), add("ab^")]);
+    // the DV update lists the new add before the remove of the old one
+    write_delta_log(&dir, 1, &[commit_info(now - 20 * day), add("cd^"), remove("ab^")]);
+    let old_dv = "ab/deletion_vector_d2c639aa-8816-431a-aaf6-d3fe2512ff61.bin";
+    let new_dv = "cd/deletion_vector_d2c639aa-8816-431a-aaf6-d3fe2512ff61.bin";
+    for f in ["dt=1/a.parquet", old_dv, new_dv] { touch_file(&dir, f); }
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let mut seen = Vec::new();
+    let vac = core::vacuum_candidates(&h, &core::VacuumOptions::default(), |c| { seen.push(c); Ok(()) }).await.unwrap();
+    assert_eq!(vac.referenced_files, 2);
+    let paths: Vec<(&str, core::VacuumCategory)> = seen.iter().map(|c| (c.path.as_str(), c.category)).collect();
+    assert_eq!(paths, vec![(old_dv, core::VacuumCategory::RemovedExpired)]);
+    assert_eq!(vac.deletable_files, 1);
+}
+
+#[tokio::test]
+async fn test_partitioned_vacuum_listing_matches_flat() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let two_cols = |path: &str, dt: &str, c: &str| format!(
+        "{{\"add\":{{\"path\":\"{}\",\"size\":0,\"partitionValues\":{{\"dt\":\"{}\",\"country\":\"{}\"}},\"modificationTime\":0,\"dataChange\":true}}}}",
+        path, dt, c
+    );
+    let mut v0 = vec![protocol_action(), metadata_action(&["dt", "country"])];
+    for dt in ["2024-01-01", "2024-01-02", "2024-01-03"] {
+        for c in ["de", "us"] {
+            let path = format!("dt={}/country={}/part-0.parquet", dt, c);
+            v0.push(two_cols(&path, dt, c));
+            touch_file(&dir, &path);
+            touch_file(&dir, &format!("dt={}/country={}/orphan.parquet", dt, c));
+        }
+    }
+    write_delta_log(&dir, 0, &v0);
+    touch_file(&dir, "stray-at-root.parquet");
+    touch_file(&dir, "dt=2024-01-01/stray-mid-level.parquet");
+    touch_file(&dir, "dt=2024-01-02/_tmp/spill/a.parquet");
+    touch_file(&dir, ".cache/b.parquet");
+
+    let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+    let run = |concurrency: usize| {
+        let h = h.clone();
+        async move {
+            let counted = Arc::new(AtomicUsize::new(0));
+            let c = counted.clone();
+            let progress = storage::ListProgress(Arc::new(move |n| { c.fetch_max(n, Ordering::Relaxed); }));
+            let opts = core::VacuumOptions { now_ms: Some(0), concurrency: Some(concurrency), progress: Some(progress), ..Default::default() };
+            let mut seen = Vec::new();
+            let report = core::vacuum_candidates(&h, &opts, |c| { seen.push(serde_json::to_string(&c).unwrap()); Ok(()) }).await.unwrap();
+            assert_eq!(report.hidden_files, 2);
+            (serde_json::to_string(&report).unwrap(), seen, counted.load(Ordering::Relaxed))
+        }
+    };
+    let (flat, flat_seen, flat_count) = run(1).await;
+    let (parallel, parallel_seen, parallel_count) = run(8).await;
+    assert_eq!(flat_seen.len(), 8);
+    assert_eq!(flat, parallel);
+    assert_eq!(flat_seen, parallel_seen);
+    // 14 data files and the 2 hidden ones; _delta_log is never listed
+    assert_eq!((flat_count, parallel_count), (16, 16));
+}
+
+#[tokio::test]
+async fn test_diff_detail_partitions_and_rewrites() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=2024-01-01/a.parquet", 100, "dt", "2024-01-01", 10),
+        add_action("dt=2024-01-01/b.parquet", 100, "dt", "2024-01-01", 10),
+    ]);
+    // v1: a new day of data
+    write_delta_log(&dir, 1, &[add_action("dt=2024-01-02/c.parquet", 300, "dt", "2024-01-02", 30)]);
+    // v2: OPTIMIZE compacts a+b into d
+    let optimized = add_action("dt=2024-01-01/d.parquet", 180, "dt", "2024-01-01", 20).replace("\"dataChange\":true", "\"dataChange\":false");
+    write_delta_log(&dir, 2, &[
+        remove_action("dt=2024-01-01/a.parquet").replace("\"dataChange\":true", "\"dataChange\":false"),
+        remove_action("dt=2024-01-01/b.parquet").replace("\"dataChange\":true", "\"dataChange\":false"),
+        optimized,
+    ]);
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let plain = core::diff_versions(&h, 0, 2).await.unwrap();
+    assert_eq!((plain.files_added, plain.bytes_added, plain.rows_added), (1, 300, 30));
+    assert_eq!(plain.files_removed, 0);
+    assert_eq!((plain.rewrite_files_added, plain.rewrite_files_removed), (1, 2));
+    assert_eq!((plain.rewrite_bytes_added, plain.rewrite_bytes_removed), (180, 200));
+    assert!(plain.added.is_empty() && plain.partitions.is_empty());
+
+    let opts = core::DiffOptions { detail: true, by: vec!["dt".to_string()] };
+    let d = core::diff_versions_with(&h, 0, 2, &opts).await.unwrap();
+    let added: Vec<(&str, bool)> = d.added.iter().map(|f| (f.path.as_str(), f.data_change)).collect();
+    assert_eq!(added, vec![("dt=2024-01-01/d.parquet", false), ("dt=2024-01-02/c.parquet", true)]);
+    assert_eq!(d.removed.len(), 2);
+    assert!(d.removed.iter().all(|f| !f.data_change));
+    assert_eq!(d.partitions.len(), 2);
+    let day1 = &d.partitions[0];
+    assert_eq!(day1.partition.get("dt").cloned().flatten().as_deref(), Some("2024-01-01"));
+    assert_eq!((day1.files_added, day1.files_removed, day1.rewrite_files_added, day1.rewrite_files_removed), (0, 0, 1, 2));
+    let day2 = &d.partitions[1];
+    assert_eq!((day2.files_added, day2.bytes_added, day2.rows_added), (1, 300, 30));
+
+    let bad = core::DiffOptions { by: vec!["country".to_string()], ..Default::default() };
+    assert!(core::diff_versions_with(&h, 0, 2, &bad).await.is_err());
+}
+
+#[tokio::test]
+async fn test_diff_commits_reports_transient_files() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    write_delta_log(&dir, 0, &[protocol_action(), metadata_action(&["dt"]), add_action("dt=1/a.parquet", 100, "dt", "1", 10)]);
+    write_delta_log(&dir, 1, &[
+        "{\"commitInfo\":{\"timestamp\":1000,\"operation\":\"WRITE\",\"operationMetrics\":{\"numFiles\":\"1\",\"numOutputRows\":\"5\"}}}".to_string(),
+        add_action("dt=1/tmp.parquet", 50, "dt", "1", 5),
+    ]);
+    write_delta_log(&dir, 2, &[
+        "{\"commitInfo\":{\"timestamp\":2000,\"operation\":\"DELETE\"}}".to_string(),
+        remove_action("dt=1/tmp.parquet"),
+    ]);
+    write_delta_log(&dir, 3, &[commit_info(3000), add_action("dt=1/b.parquet", 70, "dt", "1", 7)]);
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    // the snapshot diff never sees tmp.parquet
+    let snap = core::diff_versions(&h, 0, 3).await.unwrap();
+    assert_eq!((snap.files_added, snap.files_removed), (1, 0));
+
+    let opts = core::DiffOptions { detail: true, ..Default::default() };
+    let r = core::diff_commits(&h, 0, 3, &opts).await.unwrap();
+    assert_eq!(r.commits.iter().map(|c| c.version).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(r.commits[0].operation.as_deref(), Some("WRITE"));
+    assert_eq!(r.commits[0].operation_metrics.get("numOutputRows"), Some(&serde_json::json!("5")));
+    assert_eq!((r.commits[0].files_added, r.commits[0].bytes_added), (1, 50));
+    // the remove carries no size; it is taken from the earlier add
+    assert_eq!((r.commits[1].files_removed, r.commits[1].bytes_removed), (1, 50));
+    assert_eq!(r.commits[2].added, vec!["dt=1/b.parquet".to_string()]);
+    assert_eq!(r.transient.len(), 1);
+    assert_eq!((r.transient[0].path.as_str(), r.transient[0].added_in, r.transient[0].removed_in), ("dt=1/tmp.parquet", 1, 2));
+
+    fs::remove_file(dir.join("_delta_log").join(format!("{:020}.json", 2))).unwrap();
+    assert!(core::diff_commits(&h, 0, 3, &opts).await.is_err());
+}
+
+#[tokio::test]
+async fn test_diff_commits_deletion_vector_update_is_not_transient() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let with_dv = |action: String, id: &str| action.replace("\"dataChange\":true", &format!("\"dataChange\":true,\"deletionVector\":{{\"storageType\":\"i\",\"pathOrInlineDv\":\"{}\",\"sizeInBytes\":8,\"cardinality\":1}}", id));
+    write_delta_log(&dir, 0, &[protocol_action(), metadata_action(&["dt"]), add_action("dt=1/a.parquet", 100, "dt", "1", 10)]);
+    write_delta_log(&dir, 1, &[commit_info(1000), add_action("dt=1/b.parquet", 50, "dt", "1", 5)]);
+    // DELETE with deletion vectors: the new add precedes the remove of the old version of each file
+    write_delta_log(&dir, 2, &[
+        commit_info(2000),
+        with_dv(add_action("dt=1/a.parquet", 100, "dt", "1", 10), "new-a"),
+        remove_action("dt=1/a.parquet"),
+        with_dv(add_action("dt=1/b.parquet", 50, "dt", "1", 5), "new-b"),
+        remove_action("dt=1/b.parquet"),
+    ]);
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+    assert!(core::diff_commits(&h, 0, 2, &core::DiffOptions::default()).await.unwrap().transient.is_empty());
+
+    write_delta_log(&dir, 3, &[commit_info(3000), with_dv(remove_action("dt=1/b.parquet"), "new-b")]);
+    let r = core::diff_commits(&h, 0, 3, &core::DiffOptions::default()).await.unwrap();
+    let transient: Vec<(&str, i64, i64)> = r.transient.iter().map(|t| (t.path.as_str(), t.added_in, t.removed_in)).collect();
+    assert_eq!(transient, vec![("dt=1/b.parquet", 1, 3)]);
+
+    // a was live at 0; its deletion-vector update and later removal do not make it transient
+    write_delta_log(&dir, 4, &[commit_info(4000), with_dv(remove_action("dt=1/a.parquet"), "new-a")]);
+    let r = core::diff_commits(&h, 0, 4, &core::DiffOptions::default()).await.unwrap();
+    let transient: Vec<(&str, i64, i64)> = r.transient.iter().map(|t| (t.path.as_str(), t.added_in, t.removed_in)).collect();
+    assert_eq!(transient, vec![("dt=1/b.parquet", 1, 3)]);
+    // from 1 on, b was live as well
+    assert!(core::diff_commits(&h, 1, 4, &core::DiffOptions::default()).await.unwrap().transient.is_empty());
+}
+
+#[tokio::test]
+async fn test_diff_versions_counts_deletion_vector_rows() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let with_dv = |action: String, id: &str, cardinality: u64| action.replace("\"dataChange\":true", &format!("\"dataChange\":true,\"deletionVector\":{{\"storageType\":\"i\",\"pathOrInlineDv\":\"{}\",\"sizeInBytes\":8,\"cardinality\":{}}}", id, cardinality));
+    write_delta_log(&dir, 0, &[protocol_action(), metadata_action(&["dt"]), add_action("dt=1/a.parquet", 100, "dt", "1", 10), add_action("dt=2/b.parquet", 50, "dt", "2", 5)]);
+    // DELETE of 3 rows of a, written as a deletion vector
+    write_delta_log(&dir, 1, &[commit_info(1000), with_dv(add_action("dt=1/a.parquet", 100, "dt", "1", 10), "dv-1", 3), remove_action("dt=1/a.parquet")]);
+    // 2 more rows of a, then a goes away with its 5 live rows
+    write_delta_log(&dir, 2, &[commit_info(2000), remove_action("dt=1/a.parquet"), with_dv(add_action("dt=1/a.parquet", 100, "dt", "1", 10), "dv-2", 5)]);
+    write_delta_log(&dir, 3, &[commit_info(3000), remove_action("dt=1/a.parquet")]);
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+    let opts = core::DiffOptions { detail: true, by: vec!["dt".into()] };
+
+    let r = core::diff_versions_with(&h, 0, 1, &opts).await.unwrap();
+    assert_eq!((r.files_added, r.files_removed, r.deletion_vector_updates), (0, 0, 1));
+    assert_eq!((r.rows_added, r.rows_removed, r.row_delta), (0, 3, -3));
+    assert_eq!((r.partitions.len(), r.partitions[0].rows_removed), (1, 3));
+
+    let r = core::diff_versions_with(&h, 0, 2, &opts).await.unwrap();
+    assert_eq!((r.deletion_vector_updates, r.rows_removed), (1, 5));
+
+    let r = core::diff_versions_with(&h, 1, 3, &opts).await.unwrap();
+    assert_eq!((r.files_removed, r.deletion_vector_updates, r.rows_removed), (1, 0, 7));
+    assert_eq!(r.removed[0].rows, Some(7));
+}
+
+fn write_change_data(dir: &PathBuf, rel: &str, types: &[&str]) -> i64 {
+    use deltalake::arrow::array::{ArrayRef, Int64Array, StringArray};
+    use deltalake::arrow::record_batch::RecordBatch;
+    use deltalake::parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let ids: ArrayRef = Arc::new(Int64Array::from((0..types.len() as i64).collect::<Vec<_>>()));
+    let kinds: ArrayRef = Arc::new(StringArray::from(types.to_vec()));
+    let batch = RecordBatch::try_from_iter([("id", ids), ("_change_type", kinds)]).unwrap();
+    let p = dir.join(rel);
+    fs::create_dir_all(p.parent().unwrap()).unwrap();
+    let mut w = ArrowWriter::try_new(fs::File::create(&p).unwrap(), batch.schema(), None).unwrap();
+    w.write(&batch).unwrap();
+    w.close().unwrap();
+    fs::metadata(&p).unwrap().len() as i64
+}
+
+#[tokio::test]
+async fn test_diff_counts_rows_and_change_data() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let meta = metadata_action(&["dt"]).replace("\"configuration\":{}", "\"configuration\":{\"delta.enableChangeDataFeed\":\"true\"}");
+    write_delta_log(&dir, 0, &[protocol_action(), meta, add_action("dt=1/a.parquet", 100, "dt", "1", 10)]);
+    // v1: append, no cdc files; its rows count as inserts
+    write_delta_log(&dir, 1, &[add_action("dt=2/b.parquet", 50, "dt", "2", 5)]);
+    // v2: update of a, recorded in the change data feed
+    let size = write_change_data(&dir, "_change_data/cdc-00002.parquet", &["update_preimage", "update_preimage", "update_postimage", "update_postimage", "delete"]);
+    write_delta_log(&dir, 2, &[
+        remove_action("dt=1/a.parquet"),
+        add_action("dt=1/a2.parquet", 90, "dt", "1", 9),
+        format!("{{\"cdc\":{{\"path\":\"_change_data/cdc-00002.parquet\",\"partitionValues\":{{}},\"size\":{},\"dataChange\":false}}}}", size),
+    ]);
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let opts = core::DiffOptions { by: vec!["dt".to_string()], ..Default::default() };
+    let d = core::diff_versions_with(&h, 0, 2, &opts).await.unwrap();
+    assert_eq!((d.rows_added, d.rows_removed, d.row_delta, d.files_without_rows), (14, 10, 4, 0));
+    let deltas: Vec<i64> = d.partitions.iter().map(|p| p.row_delta).collect();
+    assert_eq!(deltas, vec![-1, 5]);
+    let cd = d.change_data.expect("change data feed is on");
+    assert_eq!((cd.insert, cd.update_preimage, cd.update_postimage, cd.delete), (5, 2, 2, 1));
+    assert_eq!((cd.cdc_files, cd.derived_commits), (1, 1));
+
+    // without the feed there is no change data summary
+    let plain = core::diff_versions(&h, 0, 0).await.unwrap();
+    assert!(plain.change_data.is_none());
+}
+
+/// Checkpoint holding only the protocol and metaData actions, one per row as Delta writes them.
+// one row each for protocol and metaData, then one per add and per (remove, deletionTimestamp)
+fn write_checkpoint(dir: &PathBuf, version: u64, configuration: &[(&str, &str)], adds: &[&str], removes: &[(&str, i64)]) {
+    use deltalake::arrow::array::{ArrayRef, Int32Array, Int64Array, ListBuilder, MapBuilder, StringArray, StringBuilder, StructArray};
+    use deltalake::arrow::buffer::NullBuffer;
+    use deltalake::arrow::datatypes::{DataType, Field};
+    use deltalake::arrow::record_batch::RecordBatch;
+    use deltalake::parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let n = 2 + adds.len() + removes.len();
+    let rows = |at: std::ops::Range<usize>| NullBuffer::from((0..n).map(|i| at.contains(&i)).collect::<Vec<_>>());
+    let ints = |v: i32| -> ArrayRef { Arc::new(Int32Array::from((0..n).map(|i| if i == 0 { v } else { 0 }).collect::<Vec<_>>())) };
+    let protocol = StructArray::try_new(
+        vec![Field::new("minReaderVersion", DataType::Int32, true), Field::new("minWriterVersion", DataType::Int32, true)].into(),
+        vec![ints(1), ints(2)],
+        Some(rows(0..1)),
+    ).unwrap();
+    let mut parts = ListBuilder::new(StringBuilder::new());
+    let mut conf = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for i in 0..n {
+        if i == 1 {
+            parts.values().append_value("dt");
+            for (k, v) in configuration {
+                conf.keys().append_value(k);
+                conf.values().append_value(v);
+            }
+        }
+        parts.append(i == 1);
+        conf.append(i == 1).unwrap();
+    }
+    let (parts, conf): (ArrayRef, ArrayRef) = (Arc::new(parts.finish()), Arc::new(conf.finish()));
+    let ids: Vec<&str> = (0..n).map(|i| if i == 1 { "00000000-0000-0000-0000-000000000000" } else { "" }).collect();
+    let metadata = StructArray::try_new(
+        vec![
+            Field::new("id", DataType::Utf8, true),
+            Field::new("partitionColumns", parts.data_type().clone(), true),
+            Field::new("configuration", conf.data_type().clone(), true),
+        ].into(),
+        vec![Arc::new(StringArray::from(ids)), parts, conf],
+        Some(rows(1..2)),
+    ).unwrap();
+    let file_paths = |at: usize, paths: Vec<&str>| -> ArrayRef {
+        Arc::new(StringArray::from((0..n).map(|i| if i >= at && i - at < paths.len() { paths[i - at] } else { "" }).collect::<Vec<_>>()))
+    };
+    let add = StructArray::try_new(
+        vec![Field::new("path", DataType::Utf8, true), Field::new("size", DataType::Int64, true)].into(),
+        vec![file_paths(2, adds.to_vec()), Arc::new(Int64Array::from(vec![4; n]))],
+        Some(rows(2..2 + adds.len())),
+    ).unwrap();
+    let at = 2 + adds.len();
+    let deleted: Vec<i64> = (0..n).map(|i| if i >= at { removes[i - at].1 } else { 0 }).collect();
+    let remove = StructArray::try_new(
+        vec![Field::new("path", DataType::Utf8, true), Field::new("deletionTimestamp", DataType::Int64, true)].into(),
+        vec![file_paths(at, removes.iter().map(|(p, _)| *p).collect()), Arc::new(Int64Array::from(deleted))],
+        Some(rows(at..n)),
+    ).unwrap();
+    let batch = RecordBatch::try_from_iter([
+        ("protocol", Arc::new(protocol) as ArrayRef),
+        ("metaData", Arc::new(metadata) as ArrayRef),
+        ("add", Arc::new(add) as ArrayRef),
+        ("remove", Arc::new(remove) as ArrayRef),
+    ]).unwrap();
+    let p = dir.join("_delta_log").join(format!("{:020}.checkpoint.parquet", version));
+    let mut w = ArrowWriter::try_new(fs::File::create(&p).unwrap(), batch.schema(), None).unwrap();
+    w.write(&batch).unwrap();
+    w.close().unwrap();
+}
+
+#[tokio::test]
+async fn test_diff_change_data_after_log_cleanup() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let meta = metadata_action(&["dt"]).replace("\"configuration\":{}", "\"configuration\":{\"delta.enableChangeDataFeed\":\"true\"}");
+    write_delta_log(&dir, 0, &[protocol_action(), meta, add_action("dt=1/a.parquet", 100, "dt", "1", 10)]);
+    write_delta_log(&dir, 1, &[add_action("dt=2/b.parquet", 50, "dt", "2", 5)]);
+    write_delta_log(&dir, 2, &[add_action("dt=2/c.parquet", 70, "dt", "2", 7)]);
+    // the metaData enabling the feed now only survives in the checkpoint
+    write_checkpoint(&dir, 1, &[("delta.enableChangeDataFeed", "true")], &[], &[]);
+    fs::remove_file(dir.join("_delta_log").join(format!("{:020}.json", 0))).unwrap();
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let cd = core::diff_versions(&h, 1, 2).await.unwrap().change_data.expect("change data feed is on");
+    assert_eq!((cd.insert, cd.derived_commits, cd.cdc_files), (7, 1, 0));
+}
+
+#[tokio::test]
+async fn test_compare_snapshots_across_tables() {
+    let temp = tempfile::tempdir().unwrap();
+    let (dir_a, dir_b) = (temp.path().join("a"), temp.path().join("b"));
+    for dir in [&dir_a, &dir_b] {
+        write_delta_log(dir, 0, &[
+            protocol_action(),
+            metadata_action(&["dt"]),
+            add_action("dt=1/a.parquet", 100, "dt", "1", 10),
+            add_action("dt=1/b.parquet", 200, "dt", "1", 20),
+        ]);
+    }
+    let ha = core::load_table(dir_a.to_str().unwrap()).await.unwrap();
+    let hb = core::load_table(dir_b.to_str().unwrap()).await.unwrap();
+    let same = core::compare_snapshots(&ha, Some(0), &hb, None).await.unwrap();
+    assert!(same.equivalent);
+    assert_eq!((same.matching_files, same.b.version), (2, 0));
+
+    // b drifts: a file goes missing, one is rewritten with other stats, one appears, and a property is set
+    write_delta_log(&dir_b, 1, &[
+        remove_action("dt=1/a.parquet"),
+        remove_action("dt=1/b.parquet"),
+        add_action("dt=1/b.parquet", 200, "dt", "1", 21),
+        add_action("dt=1/c.parquet", 50, "dt", "1", 5),
+        metadata_action(&["dt"]).replace("\"configuration\":{}", "\"configuration\":{\"delta.appendOnly\":\"true\"}"),
+    ]);
+    let drift = core::compare_snapshots(&ha, Some(0), &hb, Some(1)).await.unwrap();
+    assert!(!drift.equivalent);
+    assert_eq!(drift.missing_in_b.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["dt=1/a.parquet"]);
+    assert_eq!(drift.missing_in_a.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["dt=1/c.parquet"]);
+    assert_eq!(drift.stats_mismatches.len(), 1);
+    assert_eq!(drift.stats_mismatches[0].fields, vec!["num_records".to_string()]);
+    assert!(drift.schema_equal && drift.protocol_differences.is_empty());
+    assert_eq!(drift.metadata_differences.iter().map(|d| d.field.as_str()).collect::<Vec<_>>(), vec!["configuration.delta.appendOnly"]);
+}
+
+#[tokio::test]
+async fn test_symlink_and_filelist_manifests() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join("table");
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=2/b.parquet", 20, "dt", "2", 2),
+        add_action("dt=1/z.parquet", 10, "dt", "1", 1),
+        add_action("dt=1/a.parquet", 10, "dt", "1", 1),
+        add_action("dt=__HIVE_DEFAULT_PARTITION__/n.parquet", 5, "dt", "x", 1).replace("{\"dt\":\"x\"}", "{\"dt\":null}"),
+    ]);
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+    let root = url::Url::from_file_path(&dir).unwrap().to_string();
+
+    let hive = core::generate_manifest(&h, 0, core::ManifestFormat::Hive).await.unwrap();
+    let paths: Vec<&str> = hive.objects.iter().map(|o| o.path.as_str()).collect();
+    assert_eq!(paths, vec!["dt=1/manifest", "dt=2/manifest", "dt=__HIVE_DEFAULT_PARTITION__/manifest"]);
+    assert_eq!(hive.objects[0].body, format!("{0}/dt=1/a.parquet\n{0}/dt=1/z.parquet\n", root));
+
+    let out = temp.path().join("symlink");
+    let written = core::write_manifest(&hive, out.to_str().unwrap()).await.unwrap();
+    assert_eq!(written.len(), 3);
+    assert_eq!(fs::read_to_string(out.join("dt=2/manifest")).unwrap(), format!("{}/dt=2/b.parquet\n", root));
+
+    let list = core::generate_manifest(&h, 0, core::ManifestFormat::FileList).await.unwrap();
+    assert_eq!(list.objects.len(), 1);
+    let file = temp.path().join("files.txt");
+    core::write_manifest(&list, file.to_str().unwrap()).await.unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap().lines().count(), 4);
+
+    let into_log = dir.join("_delta_log").join("manifest");
+    assert!(core::write_manifest(&list, into_log.to_str().unwrap()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_encoded_and_absolute_add_paths() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join("my table");
+    let root = url::Url::from_file_path(&dir).unwrap().to_string();
+    // on disk the partition directory is Hive-escaped ("50%25 off"); the log URI-encodes that once more
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=50%2525%20off/a.parquet", 10, "dt", "50% off", 1),
+        add_action(&format!("{}/dt=50%2525%20off/b.parquet", root), 20, "dt", "50% off", 2),
+        add_action("s3://elsewhere/shallow/c.parquet", 30, "dt", "50% off", 3),
+    ]);
+    touch_file(&dir, "dt=50%25 off/a.parquet");
+    touch_file(&dir, "dt=50%25 off/b.parquet");
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let paths: Vec<String> = core::list_active_files(&h, Some(0)).await.unwrap().into_iter().map(|f| f.path).collect();
+    assert_eq!(paths, vec!["dt=50%25 off/a.parquet", "dt=50%25 off/b.parquet", "s3://elsewhere/shallow/c.parquet"]);
+
+    // a remove written with a different encoding still matches
+    write_delta_log(&dir, 1, &[remove_action("dt=50%2525 off/a.parquet")]);
+    assert_eq!(core::list_active_files(&h, Some(1)).await.unwrap().len(), 2);
+
+    let vac = core::vacuum_dry_run(&h, 0).await.unwrap();
+    assert_eq!((vac.existing_files, vac.orphans, vac.removed_expired.files), (2, 1, 1));
+
+    let hive = core::generate_manifest(&h, 0, core::ManifestFormat::Hive).await.unwrap();
+    assert_eq!(hive.objects[0].path, "dt=50%25 off/manifest");
+    let lines: Vec<&str> = hive.objects[0].body.lines().collect();
+    assert_eq!(lines, vec![
+        format!("{}/dt=50%2525%20off/a.parquet", root).as_str(),
+        format!("{}/dt=50%2525%20off/b.parquet", root).as_str(),
+        "s3://elsewhere/shallow/c.parquet",
+    ]);
+
+    let table_root = core::TableRoot::new(dir.to_str().unwrap()).unwrap();
+    assert_eq!(table_root.canonical("dt=a+b/x.parquet"), "dt=a+b/x.parquet");
+    assert!(core::TableRoot::is_external(&table_root.canonical("s3://elsewhere/c.parquet")));
+}
+
+#[tokio::test]
+async fn test_lockfile_round_trip_and_drift() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join("table");
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=1/a.parquet", 3, "dt", "1", 1),
+        add_action("dt=2/b.parquet", 3, "dt", "2", 1),
+        add_action("dt=2/c.parquet", 3, "dt", "2", 1),
+    ]);
+    for f in ["dt=1/a.parquet", "dt=2/b.parquet", "dt=2/c.parquet"] {
+        touch_file(&dir, f);
+        fs::write(dir.join(f), b"abc").unwrap();
+    }
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let opts = core::LockOptions {
+        filter: Some(core::Predicate::parse("dt = '2'").unwrap()),
+        exclude: core::ExclusionList::parse("dt=2/c.parquet  # bad rows\n", None).unwrap(),
+        etags: true,
+        ..Default::default()
+    };
+    let lock = core::create_lock(&h, 0, &opts).await.unwrap();
+    assert_eq!(lock.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["dt=2/b.parquet"]);
+    assert!(lock.files[0].etag.is_some());
+    assert_eq!(lock.integrity_hash, core::compute_integrity_hash(&h, Some(0)).await.unwrap());
+    assert_eq!(lock.filters.filter.as_deref(), Some("dt = '2'"));
+
+    let path = temp.path().join("data.lock");
+    core::write_lock(&lock, path.to_str().unwrap()).await.unwrap();
+    let read = core::read_lock(path.to_str().unwrap()).await.unwrap();
+    let clean = core::verify_lock(&read, None).await.unwrap();
+    assert!(clean.ok, "{:?}", clean);
+    assert_eq!(clean.integrity_hash_matches, Some(true));
+
+    fs::write(dir.join("dt=2/b.parquet"), b"abcd").unwrap();
+    let drift = core::verify_lock(&read, None).await.unwrap();
+    assert!(!drift.ok);
+    assert_eq!(drift.size_changed.len(), 1);
+    assert_eq!(drift.etag_changed.len(), 1);
+    fs::remove_file(dir.join("dt=2/b.parquet")).unwrap();
+    assert_eq!(core::verify_lock(&read, None).await.unwrap().missing, vec!["dt=2/b.parquet".to_string()]);
+}
+
+#[tokio::test]
+async fn test_verify_files_reports_missing_resized_and_corrupt() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join("table");
+    let parquet = |body: &[u8]| -> Vec<u8> {
+        let mut v = b"PAR1".to_vec();
+        v.extend_from_slice(body);
+        v.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        v.extend_from_slice(b"PAR1");
+        v
+    };
+    let good = parquet(b"footer");
+    let mut corrupt = good.clone();
+    corrupt.truncate(good.len() - 1);
+    corrupt.push(b'X');
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action("dt=1/good.parquet", good.len() as i64, "dt", "1", 1),
+        add_action("dt=1/corrupt.parquet", corrupt.len() as i64, "dt", "1", 1),
+        add_action("dt=1/short.parquet", 100, "dt", "1", 1),
+        add_action("dt=1/gone.parquet", 10, "dt", "1", 1),
+    ]);
+    touch_file(&dir, "dt=1/good.parquet");
+    fs::write(dir.join("dt=1/good.parquet"), &good).unwrap();
+    fs::write(dir.join("dt=1/corrupt.parquet"), &corrupt).unwrap();
+    fs::write(dir.join("dt=1/short.parquet"), &good).unwrap();
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let shallow = core::verify_files(&h, 0, &core::VerifyOptions::default()).await.unwrap();
+    assert!(!shallow.ok);
+    assert_eq!((shallow.files_checked, shallow.missing, shallow.size_mismatches, shallow.bad_footers), (4, 1, 1, 0));
+    assert_eq!(shallow.issues[0].path, "dt=1/gone.parquet");
+
+    let deep = core::verify_files(&h, 0, &core::VerifyOptions { deep: true, ..Default::default() }).await.unwrap();
+    assert_eq!(deep.bad_footers, 1);
+    let bad: Vec<(&str, core::FileProblem)> = deep.issues.iter().map(|i| (i.path.as_str(), i.problem)).collect();
+    assert_eq!(bad, vec![
+        ("dt=1/corrupt.parquet", core::FileProblem::BadFooter),
+        ("dt=1/gone.parquet", core::FileProblem::Missing),
+        ("dt=1/short.parquet", core::FileProblem::SizeMismatch),
+    ]);
+
+    let skip = core::ExclusionList::parse("dt=1/gone.parquet  # lost\ndt=1/short.parquet  # truncated\ndt=1/corrupt.parquet  # bad\n", None).unwrap();
+    assert!(core::verify_files(&h, 0, &core::VerifyOptions { deep: true, exclude: skip, ..Default::default() }).await.unwrap().ok);
+}
+
+#[tokio::test]
+async fn test_row_group_counts_read_footers() {
+    use deltalake::arrow::array::{ArrayRef, Int64Array};
+    use deltalake::arrow::record_batch::RecordBatch;
+    use deltalake::parquet::arrow::ArrowWriter;
+    use deltalake::parquet::file::properties::WriterProperties;
+    use std::sync::Arc;
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().to_path_buf();
+    let write = |rel: &str, rows: i64, per_group: usize| {
+        let ids: ArrayRef = Arc::new(Int64Array::from((0..rows).collect::<Vec<_>>()));
+        let batch = RecordBatch::try_from_iter([("id", ids)]).unwrap();
+        let props = WriterProperties::builder().set_max_row_group_size(per_group).build();
+        let mut w = ArrowWriter::try_new(fs::File::create(dir.join(rel)).unwrap(), batch.schema(), Some(props)).unwrap();
+        w.write(&batch).unwrap();
+        w.close().unwrap();
+    };
+    write("a.parquet", 5, 2);
+    write("b.parquet", 4, 10);
+    fs::write(dir.join("junk.parquet"), b"not parquet at all").unwrap();
+
+    let uri = dir.to_string_lossy().to_string();
+    let counts = core::row_group_counts(&uri, &["a.parquet".to_string(), "b.parquet".to_string()], None).await.unwrap();
+    assert_eq!(counts.into_iter().collect::<Vec<_>>(), vec![("a.parquet".to_string(), 3), ("b.parquet".to_string(), 1)]);
+    let err = core::row_group_counts(&uri, &["junk.parquet".to_string()], None).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("junk.parquet"));
+}
+
+#[tokio::test]
+async fn test_content_hash_merkle_root_resume_and_lock() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join("table");
+    let files = ["dt=1/a.parquet", "dt=1/b.parquet", "dt=2/c.parquet"];
+    write_delta_log(&dir, 0, &[
+        protocol_action(),
+        metadata_action(&["dt"]),
+        add_action(files[0], 3, "dt", "1", 1),
+        add_action(files[1], 3, "dt", "1", 1),
+        add_action(files[2], 3, "dt", "2", 1),
+    ]);
+    for (f, body) in files.iter().zip([b"abc", b"def", b"ghi"]) {
+        touch_file(&dir, f);
+        fs::write(dir.join(f), body).unwrap();
+    }
+    let h = core::load_table(dir.to_str().unwrap()).await.unwrap();
+
+    let full = core::content_hash(&h, 0, &core::ContentHashOptions::default()).await.unwrap();
+    assert_eq!((full.files_total, full.files_hashed, full.bytes_hashed), (3, 3, 9));
+    assert_eq!(full.files[0].blake3, blake3::hash(b"abc").to_hex().to_string());
+    assert_eq!(full.merkle_root, core::merkle_root(&full.files));
+
+    // a resume state left by an interrupted run is reused, and hashing one file at a time saves it as it goes
+    let state = temp.path().join("hash-state.json");
+    let partial = serde_json::json!({"table": h.uri, "version": 0, "files": [full.files[0]]});
+    fs::write(&state, partial.to_string()).unwrap();
+    let opts = core::ContentHashOptions { resume: Some(state.to_str().unwrap().to_string()), checkpoint_every: Some(1), ..Default::default() };
+    let resumed = core::content_hash(&h, 0, &opts).await.unwrap();
+    assert_eq!(resumed.merkle_root, full.merkle_root);
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(&state).unwrap()).unwrap();
+    assert_eq!(saved["files"].as_array().unwrap().len(), 3);
+
+    let sampled = core::content_hash(&h, 0, &core::ContentHashOptions { sample: Some(0.5), sample_seed: 7, ..Default::default() }).await.unwrap();
+    let again = core::content_hash(&h, 0, &core::ContentHashOptions { sample: Some(0.5), sample_seed: 7, ..Default::default() }).await.unwrap();
+    assert_eq!(sampled.files, again.files);
+    assert!(sampled.files_hashed <= 3 && sampled.files_total == 3);
+
+    // same size, different bytes: invisible to the log and to HEAD, caught by the hashes
+    let lock = core::create_lock(&h, 0, &core::LockOptions { content: true, ..Default::default() }).await.unwrap();
+    assert_eq!(lock.content_root.as_deref(), Some(full.merkle_root.as_str()));
+    let path = temp.path().join("data.lock");
+    core::write_lock(&lock, path.to_str().unwrap()).await.unwrap();
+    assert_eq!(core::read_content_manifest(path.to_str().unwrap()).await.unwrap().merkle_root, full.merkle_root);
+    assert!(core::verify_lock_content(&lock, None).await.unwrap().identical);
+
+    fs::write(dir.join(files[1]), b"xyz").unwrap();
+    assert!(core::verify_lock(&lock, None).await.unwrap().ok);
+    let drift = core::verify_lock_content(&lock, None).await.unwrap();
+    assert_eq!(drift.changed, vec![files[1].to_string()]);
+    let after = core::content_hash(&h, 0, &core::ContentHashOptions::default()).await.unwrap();
+    let cmp = core::compare_content(&full, &after);
+    assert!(!cmp.identical);
+    assert_eq!((cmp.files_compared, cmp.changed.len()), (3, 1));
+    // a resized and a vanished file are drift, not an error that hides the other files
+    fs::write(dir.join(files[1]), b"wxyz").unwrap();
+    fs::remove_file(dir.join(files[2])).unwrap();
+    let drift = core::verify_lock_content(&lock, None).await.unwrap();
+    assert_eq!((drift.files_compared, drift.changed.clone(), drift.only_in_a.clone()), (2, vec![files[1].to_string()], vec![files[2].to_string()]));
+    assert!(!drift.identical);
+    // content-hash still fails, but keeps what it could hash for a resumed run
+    let state = temp.path().join("hash-state-2.json");
+    let opts = core::ContentHashOptions { resume: Some(state.to_str().unwrap().to_string()), checkpoint_every: Some(1), ..Default::default() };
+    let err = core::content_hash(&h, 0, &opts).await.unwrap_err().to_string();
+    assert!(err.contains("2 of 3 files") && err.contains(files[1]) && err.contains(files[2]), "{}", err);
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(&state).unwrap()).unwrap();
+    assert_eq!(saved["files"].as_array().unwrap().len(), 1);
+    assert!(core::create_lock(&h, 0, &core::LockOptions { content: true, ..Default::default() }).await.is_err());
+}
@@ -11,10 +11,18 @@ struct PyShardFile { #[pyo3(get)] path: String, #[pyo3(get)] bytes: i64, #[pyo3(
 #[derive(Clone)]
 struct PyShard { #[pyo3(get)] id: u32, #[pyo3(get)] bytes: i64, #[pyo3(get)] rows: u64, #[pyo3(get)] files: Vec<PyShardFile> }
 
+#[pyclass]
+#[derive(Clone)]
+struct PySplit { #[pyo3(get)] name: String, #[pyo3(get)] ratio: f64, #[pyo3(get)] shards: Vec<PyShard> }
+
+fn to_py_shards(v: Vec<sp::Shard>) -> Vec<PyShard> {
+    v.into_iter().map(|s| PyShard { id: s.id, bytes: s.bytes, rows: s.rows, files: s.files.into_iter().map(|f| PyShardFile { path: f.path, bytes: f.bytes, rows: f.approx_rows }).collect() }).collect()
+}
+
 #[pyfunction]
 fn shard_manifest(py: Python<'_>, uri: String, version: i64, shards: u32, balance: Option<String>, by: Option<Vec<String>>, sticky_by: Option<Vec<String>>, row_group_aware: Option<bool>) -> PyResult<Vec<PyShard>> {
     let mode = match balance.as_deref() { Some("rows") => sp::BalanceMode::Rows, _ => sp::BalanceMode::Bytes };
-    let opts = sp::ShardOptions { by: by.unwrap_or_default(), sticky_by: sticky_by.unwrap_or_default(), max_files_per_shard: None, balance: mode, row_group_aware: row_group_aware.unwrap_or(false) };
+    let opts = sp::ShardOptions { by: by.unwrap_or_default(), sticky_by: sticky_by.unwrap_or_default(), max_files_per_shard: None, balance: mode, row_group_aware: row_group_aware.unwrap_or(false), ..Default::default() };
     py.allow_threads(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         let res: Result<Vec<sp::Shard>> = rt.block_on(async move {
@@ -22,15 +30,75 @@ fn shard_manifest(py: Python<'_>, uri: String, version: i64, shards: u32, balanc
             sp::plan_shards(&h, version, shards, opts).await
         });
         match res {
-            Ok(v) => Ok(v.into_iter().map(|s| PyShard { id: s.id, bytes: s.bytes, rows: s.rows, files: s.files.into_iter().map(|f| PyShardFile { path: f.path, bytes: f.bytes, rows: f.approx_rows }).collect() }).collect()),
+            Ok(v) => Ok(to_py_shards(v)),
+            Err(e) => Err(pyo3::exceptions::PyRuntimeError::new_err(e.to_string())),
+        }
+    })
+}
+
+#[pyfunction]
+fn split_manifest(py: Python<'_>, uri: String, version: i64, shards: u32, splits: String, balance: Option<String>, by: Option<Vec<String>>, sticky_by: Option<Vec<String>>) -> PyResult<Vec<PySplit>> {
+    let mode = match balance.as_deref() { Some("rows") => sp::BalanceMode::Rows, _ => sp::BalanceMode::Bytes };
+    let opts = sp::ShardOptions { by: by.unwrap_or_default(), sticky_by: sticky_by.unwrap_or_default(), balance: mode, ..Default::default() };
+    py.allow_threads(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let res: Result<Vec<sp::SplitPlan>> = rt.block_on(async move {
+            let splits = sp::parse_splits(&splits)?;
+            let h = core::load_table(&uri).await?;
+            sp::plan_splits(&h, version, shards, &splits, opts).await
+        });
+        match res {
+            Ok(v) => Ok(v.into_iter().map(|s| PySplit { name: s.name, ratio: s.ratio, shards: to_py_shards(s.shards) }).collect()),
             Err(e) => Err(pyo3::exceptions::PyRuntimeError::new_err(e.to_string())),
         }
     })
 }
 
+#[pyclass]
+#[derive(Clone)]
+struct PyResumeFile { #[pyo3(get)] path: String, #[pyo3(get)] bytes: i64, #[pyo3(get)] row_group: u32, #[pyo3(get)] row: u64, #[pyo3(get)] from_rank: u32 }
+
+#[pyclass]
+#[derive(Clone)]
+struct PyResumeShard { #[pyo3(get)] rank: u32, #[pyo3(get)] bytes: i64, #[pyo3(get)] files: Vec<PyResumeFile>, #[pyo3(get)] inherited_from: Vec<u32> }
+
+#[pyclass(name = "ProgressLedger")]
+struct PyProgressLedger { inner: sp::progress::ProgressLedger }
+
+fn py_err(e: anyhow::Error) -> PyErr { pyo3::exceptions::PyRuntimeError::new_err(e.to_string()) }
+
+#[pymethods]
+impl PyProgressLedger {
+    #[new]
+    fn new(path: String, plan_path: String) -> PyResult<Self> {
+        let plan = sp::PlanFile::read(std::path::Path::new(&plan_path)).map_err(py_err)?;
+        let inner = sp::progress::ProgressLedger::open(std::path::Path::new(&path), plan.shards()).map_err(py_err)?;
+        Ok(PyProgressLedger { inner })
+    }
+
+    #[getter]
+    fn fingerprint(&self) -> String { self.inner.fingerprint().to_string() }
+
+    fn record(&mut self, rank: u32, path: String, row_group: Option<u32>, row: Option<u64>, complete: Option<bool>) -> PyResult<()> {
+        self.inner.record(rank, &path, row_group.unwrap_or(0), row.unwrap_or(0), complete.unwrap_or(false)).map_err(py_err)
+    }
+
+    fn remaining(&self, failed: Option<Vec<u32>>) -> PyResult<Vec<PyResumeShard>> {
+        let plan = self.inner.remaining(&failed.unwrap_or_default()).map_err(py_err)?;
+        Ok(plan.shards.into_iter().map(|s| PyResumeShard {
+            rank: s.rank,
+            bytes: s.bytes,
+            files: s.files.into_iter().map(|f| PyResumeFile { path: f.path, bytes: f.bytes, row_group: f.row_group, row: f.row, from_rank: f.from_rank }).collect(),
+            inherited_from: s.inherited_from,
+        }).collect())
+    }
+}
+
 #[pymodule]
 fn deltakit_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(shard_manifest, m)?)?;
+    m.add_function(wrap_pyfunction!(split_manifest, m)?)?;
+    m.add_class::<PyProgressLedger>()?;
     Ok(())
 }
 
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{BalanceMode, Shard, ShardFile};
+
+/// Hard placement rule honoured by the planner on top of `by`/`sticky_by`.
+///
+/// Text form (used by the CLI and in error messages):
+/// `pin(region=eu,0-7)`, `separate(dt=2024-01-01,dt=2024-01-02)`, `max_share(country,0.2)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Constraint {
+    /// files with `column=value` may only land on `shards`
+    Pin { column: String, value: String, shards: Vec<u32> },
+    /// the listed partitions never share a shard
+    Separate { members: Vec<(String, String)> },
+    /// no shard holds more than `fraction` of the balanced shard size (total / shards, in bytes or
+    /// rows) of any one value of `column`. This is an absolute cap per shard, not a share of the
+    /// shard's own load: on a shard lighter than the balanced size the value may make up more.
+    MaxShare { column: String, fraction: f64 },
+}
+
+impl Constraint {
+    pub fn parse(spec: &str) -> Result<Constraint> {
+        let spec = spec.trim();
+        let bad = || anyhow!("invalid constraint '{}', expected pin(col=value,shards), separate(col=a,col=b) or max_share(col,fraction)", spec);
+        let (kind, rest) = spec.split_once('(').ok_or_else(bad)?;
+        let args: Vec<&str> = rest.strip_suffix(')').ok_or_else(bad)?.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()).collect();
+        let pair = |a: &str| -> Result<(String, String)> {
+            let (c, v) = a.split_once('=').ok_or_else(bad)?;
+            Ok((c.trim().to_string(), v.trim().to_string()))
+        };
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "pin" => {
+                let (column, value) = pair(args.first().ok_or_else(bad)?)?;
+                let mut shards = Vec::new();
+                for a in &args[1..] {
+                    match a.split_once('-') {
+                        Some((lo, hi)) => {
+                            let (lo, hi): (u32, u32) = (lo.trim().parse().map_err(|_| bad())?, hi.trim().parse().map_err(|_| bad())?);
+                            if hi < lo { return Err(bad()); }
+                            shards.extend(lo..=hi);
+                        }
+                        None => shards.push(a.parse().map_err(|_| bad())?),
+                    }
+                }
+                if shards.is_empty() { return Err(bad()); }
+                shards.sort_unstable();
+                shards.dedup();
+                Ok(Constraint::Pin { column, value, shards })
+            }
+            "separate" => {
+                let members = args.iter().map(|a| pair(a)).collect::<Result<Vec<_>>>()?;
+                if members.len() < 2 { return Err(bad()); }
+                Ok(Constraint::Separate { members })
+            }
+            "max_share" => {
+                if args.len() != 2 { return Err(bad()); }
+                let fraction: f64 = args[1].parse().map_err(|_| bad())?;
+                if fraction.is_nan() || fraction <= 0.0 || fraction > 1.0 { return Err(anyhow!("constraint '{}': fraction must be within (0, 1]", spec)); }
+                Ok(Constraint::MaxShare { column: args[0].to_string(), fraction })
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+fn fmt_ids(ids: &[u32]) -> String {
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while i < ids.len() {
+        let mut j = i;
+        while j + 1 < ids.len() && ids[j + 1] == ids[j] + 1 { j += 1; }
+        parts.push(if j > i { format!("{}-{}", ids[i], ids[j]) } else { ids[i].to_string() });
+        i = j + 1;
+    }
+    parts.join(",")
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::Pin { column, value, shards } => write!(f, "pin({}={},{})", column, value, fmt_ids(shards)),
+            Constraint::Separate { members } => {
+                let m: Vec<String> = members.iter().map(|(c, v)| format!("{}={}", c, v)).collect();
+                write!(f, "separate({})", m.join(","))
+            }
+            Constraint::MaxShare { column, fraction } => write!(f, "max_share({},{})", column, fraction),
+        }
+    }
+}
+
+fn value_of<'a>(f: &'a ShardFile, column: &str) -> Option<&'a str> {
+    f.partition.get(column).and_then(|o| o.as_deref())
+}
+
+fn load_of(f: &ShardFile, mode: &BalanceMode) -> f64 {
+    match mode { BalanceMode::Bytes => f.bytes.max(0) as f64, BalanceMode::Rows => f.approx_rows as f64 }
+}
+
+/// Tracks what each shard already holds so every placement can be checked against the rules.
+pub(crate) struct ConstraintState<'a> {
+    rules: &'a [Constraint],
+    mode: BalanceMode,
+    k: usize,
+    // [rule][shard] -> members of a separate() rule present on the shard
+    separate: Vec<Vec<HashSet<usize>>>,
+    // [rule][shard] -> value -> load, for max_share() rules
+    share: Vec<Vec<HashMap<String, f64>>>,
+    // absolute per-shard load allowed per value for max_share() rules: fraction * balanced target size
+    caps: Vec<f64>,
+}
+
+impl<'a> ConstraintState<'a> {
+    pub(crate) fn new<'f, I>(rules: &'a [Constraint], mode: BalanceMode, out: &'f [Shard], incoming: I) -> Result<Self>
+    where
+        I: Iterator<Item = &'f ShardFile> + Clone,
+    {
+        let k = out.len();
+        for r in rules {
+            if let Constraint::Pin { shards, .. } = r {
+                if let Some(bad) = shards.iter().find(|s| **s as usize >= k) {
+                    return Err(anyhow!("constraint {} refers to shard {} but the plan has {} shards", r, bad, k));
+                }
+            }
+        }
+        let total: f64 = out.iter().flat_map(|s| s.files.iter()).chain(incoming.clone()).map(|f| load_of(f, &mode)).sum();
+        let target = total / k.max(1) as f64;
+        let mut st = ConstraintState {
+            rules,
+            mode,
+            k,
+            separate: vec![vec![HashSet::new(); k]; rules.len()],
+            share: vec![vec![HashMap::new(); k]; rules.len()],
+            caps: rules.iter().map(|r| match r { Constraint::MaxShare { fraction, .. } => fraction * target, _ => 0.0 }).collect(),
+        };
+        for r in rules {
+            if let Constraint::MaxShare { column, fraction } = r {
+                // every shard can hold at most fraction*target of a value, so the value's total must fit;
+                // that is necessary, not sufficient: placement is greedy and `allowed` errors if a file finds no room
+                let mut per_value: BTreeMap<&str, f64> = BTreeMap::new();
+                for f in out.iter().flat_map(|s| s.files.iter()).chain(incoming.clone()) {
+                    if let Some(v) = value_of(f, column) { *per_value.entry(v).or_default() += load_of(f, &st.mode); }
+                }
+                if let Some((v, load)) = per_value.into_iter().find(|(_, l)| *l > fraction * total * (1.0 + 1e-9)) {
+                    return Err(anyhow!("constraint {} is infeasible: {}={} alone is {:.1}% of the plan", r, column, v, 100.0 * load / total.max(1.0)));
+                }
+            }
+        }
+        for (idx, s) in out.iter().enumerate() {
+            for f in &s.files { st.record(f, idx); }
+        }
+        Ok(st)
+    }
+
+    /// Shards `f` may go to; errors naming every rule that narrowed the choice when none is left.
+    pub(crate) fn allowed(&self, f: &ShardFile) -> Result<Vec<bool>> {
+        let mut ok = vec![true; self.k];
+        let mut applied: Vec<&Constraint> = Vec::new();
+        for (i, r) in self.rules.iter().enumerate() {
+            let before = ok.iter().filter(|b| **b).count();
+            match r {
+                Constraint::Pin { column, value, shards } => {
+                    if value_of(f, column) != Some(value.as_str()) { continue; }
+                    for (s, slot) in ok.iter_mut().enumerate() {
+                        if !shards.contains(&(s as u32)) { *slot = false; }
+                    }
+                }
+                Constraint::Separate { members } => {
+                    let Some(me) = members.iter().position(|(c, v)| value_of(f, c) == Some(v.as_str())) else { continue };
+                    for (s, slot) in ok.iter_mut().enumerate() {
+                        if self.separate[i][s].iter().any(|m| *m != me) { *slot = false; }
+                    }
+                    // a member may only spread over its fair share of shards, leaving room for the others
+                    let held: Vec<usize> = (0..self.k).filter(|s| self.separate[i][*s].contains(&me)).collect();
+                    if held.len() >= (self.k / members.len()).max(1) {
+                        for (s, slot) in ok.iter_mut().enumerate() {
+                            if !held.contains(&s) { *slot = false; }
+                        }
+                    }
+                }
+                Constraint::MaxShare { column, .. } => {
+                    let Some(v) = value_of(f, column) else { continue };
+                    let w = load_of(f, &self.mode);
+                    for (s, slot) in ok.iter_mut().enumerate() {
+                        let cur = self.share[i][s].get(v).copied().unwrap_or(0.0);
+                        if cur + w > self.caps[i] * (1.0 + 1e-9) { *slot = false; }
+                    }
+                }
+            }
+            if ok.iter().filter(|b| **b).count() < before { applied.push(r); }
+        }
+        if !ok.iter().any(|b| *b) {
+            let names: Vec<String> = applied.iter().map(|r| r.to_string()).collect();
+            return Err(anyhow!("no shard can take {}: conflicting constraints {}", f.path, names.join(", ")));
+        }
+        Ok(ok)
+    }
+
+    pub(crate) fn record(&mut self, f: &ShardFile, shard: usize) {
+        for (i, r) in self.rules.iter().enumerate() {
+            match r {
+                Constraint::Pin { .. } => {}
+                Constraint::Separate { members } => {
+                    if let Some(me) = members.iter().position(|(c, v)| value_of(f, c) == Some(v.as_str())) {
+                        self.separate[i][shard].insert(me);
+                    }
+                }
+                Constraint::MaxShare { column, .. } => {
+                    if let Some(v) = value_of(f, column) {
+                        *self.share[i][shard].entry(v.to_string()).or_default() += load_of(f, &self.mode);
+                    }
+                }
+            }
+        }
+    }
+}
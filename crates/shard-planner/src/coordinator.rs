@@ -0,0 +1,478 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{plan_fingerprint, Shard};
+
+/// One unit of work handed to a worker: a whole file, or one of its row groups.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WorkItem {
+    pub shard: u32,
+    pub path: String,
+    /// the file's bytes, or an even share of them per row group
+    pub bytes: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_group: Option<u32>,
+}
+
+impl WorkItem {
+    fn key(&self) -> (u32, String, Option<u32>) { (self.shard, self.path.clone(), self.row_group) }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lease {
+    pub lease_id: u64,
+    pub worker: String,
+    pub item: WorkItem,
+    /// true when the item came from another shard's queue
+    pub stolen: bool,
+    pub expires_in_ms: u64,
+}
+
+/// Answer to a lease request.
+#[derive(Debug, Clone)]
+pub enum LeaseReply {
+    Granted(Lease),
+    /// nothing is pending, but leased items may still come back; ask again after this long
+    Wait(Duration),
+    /// every item is done
+    Drained,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardProgress {
+    pub shard: u32,
+    pub pending: usize,
+    pub leased: usize,
+    pub done: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoordinatorStatus {
+    pub fingerprint: String,
+    pub total: usize,
+    pub pending: usize,
+    pub leased: usize,
+    pub done: usize,
+    pub requeued: u64,
+    pub shards: Vec<ShardProgress>,
+}
+
+struct ActiveLease {
+    worker: String,
+    item: WorkItem,
+    expires_at: Instant,
+}
+
+/// Lease bookkeeping for a shard plan. Workers drain their own shard first and then steal from
+/// the back of the longest other queue; expired leases go back to the front of their shard.
+///
+/// Only completions are journaled: on restart, acked items are skipped and anything that was
+/// leased but not acked is simply pending again.
+pub struct Coordinator {
+    fingerprint: String,
+    ttl: Duration,
+    by_row_group: bool,
+    queues: BTreeMap<u32, VecDeque<WorkItem>>,
+    leases: HashMap<u64, ActiveLease>,
+    done: HashMap<u32, usize>,
+    total: usize,
+    next_lease: u64,
+    requeued: u64,
+    journal: Option<std::fs::File>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalEntry {
+    Open {
+        fingerprint: String,
+        total: usize,
+        #[serde(default)]
+        by_row_group: bool,
+    },
+    Ack {
+        shard: u32,
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        row_group: Option<u32>,
+        worker: String,
+    },
+}
+
+// requests are small JSON objects
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Upper bound on `LeaseReply::Wait`: an ack or release can free the queue before any lease expires.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+impl Coordinator {
+    pub fn new(plan: &[Shard], ttl: Duration) -> Self {
+        Coordinator::build(plan, ttl, None)
+    }
+
+    /// Like `new`, but leases every row group of a file on its own. `row_groups` maps paths to
+    /// their row-group count (see `deltakit_core::row_group_counts`); unlisted files stay whole.
+    pub fn by_row_group(plan: &[Shard], ttl: Duration, row_groups: &BTreeMap<String, u32>) -> Self {
+        Coordinator::build(plan, ttl, Some(row_groups))
+    }
+
+    fn build(plan: &[Shard], ttl: Duration, row_groups: Option<&BTreeMap<String, u32>>) -> Self {
+        let mut queues = BTreeMap::new();
+        let mut total = 0;
+        for s in plan {
+            let mut q = VecDeque::new();
+            for f in &s.files {
+                match row_groups.and_then(|rg| rg.get(&f.path)).copied().filter(|n| *n > 0) {
+                    Some(n) => q.extend((0..n).map(|rg| {
+                        let share = f.bytes / n as i64 + if rg == 0 { f.bytes % n as i64 } else { 0 };
+                        WorkItem { shard: s.id, path: f.path.clone(), bytes: share, row_group: Some(rg) }
+                    })),
+                    None => q.push_back(WorkItem { shard: s.id, path: f.path.clone(), bytes: f.bytes, row_group: None }),
+                }
+            }
+            total += q.len();
+            queues.insert(s.id, q);
+        }
+        Coordinator {
+            fingerprint: plan_fingerprint(plan),
+            ttl,
+            by_row_group: row_groups.is_some(),
+            queues,
+            leases: HashMap::new(),
+            done: HashMap::new(),
+            total,
+            next_lease: 1,
+            requeued: 0,
+            journal: None,
+        }
+    }
+
+    /// Like `new`, but replays and then appends to `journal`. Refuses a journal written for another plan.
+    pub fn with_journal(plan: &[Shard], ttl: Duration, journal: &Path) -> Result<Self> {
+        Coordinator::new(plan, ttl).journaled(journal)
+    }
+
+    /// Replays and then appends to `journal`. Refuses a journal written for another plan, or one
+    /// that leased whole files when this coordinator leases row groups (and vice versa).
+    pub fn journaled(self, journal: &Path) -> Result<Self> {
+        let mut c = self;
+        let mut acked: HashSet<(u32, String, Option<u32>)> = HashSet::new();
+        let mut opened = false;
+        if journal.exists() {
+            let file = std::fs::File::open(journal).with_context(|| format!("opening journal {}", journal.display()))?;
+            for line in std::io::BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() { continue; }
+                // a torn last line from a crash is ignored
+                let Ok(entry) = serde_json::from_str::<JournalEntry>(&line) else { continue };
+                match entry {
+                    JournalEntry::Open { fingerprint, by_row_group, .. } => {
+                        if fingerprint != c.fingerprint {
+                            return Err(anyhow!("journal {} belongs to plan {}, not {}", journal.display(), fingerprint, c.fingerprint));
+                        }
+                        if by_row_group != c.by_row_group {
+                            let unit = |rg: bool| if rg { "row groups" } else { "whole files" };
+                            return Err(anyhow!("journal {} leased {}, not {}", journal.display(), unit(by_row_group), unit(c.by_row_group)));
+                        }
+                        opened = true;
+                    }
+                    JournalEntry::Ack { shard, path, row_group, .. } => { acked.insert((shard, path, row_group)); }
+                }
+            }
+        }
+        for (shard, q) in c.queues.iter_mut() {
+            let before = q.len();
+            q.retain(|it| !acked.contains(&it.key()));
+            *c.done.entry(*shard).or_default() += before - q.len();
+        }
+        c.journal = Some(std::fs::OpenOptions::new().create(true).append(true).open(journal)?);
+        if !opened {
+            c.append(&JournalEntry::Open { fingerprint: c.fingerprint.clone(), total: c.total, by_row_group: c.by_row_group })?;
+        }
+        Ok(c)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_journal_file(&mut self, f: std::fs::File) { self.journal = Some(f); }
+
+    fn append(&mut self, e: &JournalEntry) -> Result<()> {
+        if let Some(f) = self.journal.as_mut() {
+            writeln!(f, "{}", serde_json::to_string(e)?)?;
+            f.sync_data()?;
+        }
+        Ok(())
+    }
+
+    pub fn fingerprint(&self) -> &str { &self.fingerprint }
+
+    /// Re-queues expired leases; returns how many.
+    pub fn reap(&mut self, now: Instant) -> usize {
+        let expired: Vec<u64> = self.leases.iter().filter(|(_, l)| l.expires_at <= now).map(|(id, _)| *id).collect();
+        for id in &expired {
+            if let Some(l) = self.leases.remove(id) {
+                self.queues.entry(l.item.shard).or_default().push_front(l.item);
+            }
+        }
+        self.requeued += expired.len() as u64;
+        expired.len()
+    }
+
+    /// Next item for `worker`, preferring its own `shard`. Once nothing is pending, workers are
+    /// told to wait while leases are outstanding (an expired one is re-queued) and only then that
+    /// the plan is drained.
+    pub fn lease(&mut self, worker: &str, shard: Option<u32>, now: Instant) -> LeaseReply {
+        self.reap(now);
+        let own = shard.and_then(|s| self.queues.get_mut(&s)).and_then(|q| q.pop_front());
+        let (item, stolen) = match own {
+            Some(it) => (it, false),
+            None => {
+                let victim = self.queues.iter().filter(|(_, q)| !q.is_empty()).max_by_key(|(id, q)| (q.len(), std::cmp::Reverse(**id))).map(|(id, _)| *id);
+                match victim.and_then(|v| self.queues.get_mut(&v)).and_then(|q| q.pop_back()) {
+                    Some(it) => (it, shard.is_some()),
+                    None => {
+                        return match self.leases.values().map(|l| l.expires_at).min() {
+                            Some(next) => LeaseReply::Wait(next.saturating_duration_since(now).min(MAX_RETRY_AFTER)),
+                            None => LeaseReply::Drained,
+                        };
+                    }
+                }
+            }
+        };
+        let lease_id = self.next_lease;
+        self.next_lease += 1;
+        self.leases.insert(lease_id, ActiveLease { worker: worker.to_string(), item: item.clone(), expires_at: now + self.ttl });
+        LeaseReply::Granted(Lease { lease_id, worker: worker.to_string(), item, stolen, expires_in_ms: self.ttl.as_millis() as u64 })
+    }
+
+    /// Extends every lease held by `worker`; returns how many.
+    pub fn heartbeat(&mut self, worker: &str, now: Instant) -> usize {
+        self.reap(now);
+        let mut n = 0;
+        for l in self.leases.values_mut().filter(|l| l.worker == worker) {
+            l.expires_at = now + self.ttl;
+            n += 1;
+        }
+        n
+    }
+
+    /// Completes a lease. The journal is written first, so a failed write leaves the lease in place.
+    pub fn ack(&mut self, lease_id: u64, now: Instant) -> Result<WorkItem> {
+        self.reap(now);
+        let l = self.leases.get(&lease_id).ok_or_else(|| anyhow!("unknown or expired lease {}", lease_id))?;
+        let entry = JournalEntry::Ack { shard: l.item.shard, path: l.item.path.clone(), row_group: l.item.row_group, worker: l.worker.clone() };
+        self.append(&entry)?;
+        let l = self.leases.remove(&lease_id).expect("lease checked above");
+        *self.done.entry(l.item.shard).or_default() += 1;
+        Ok(l.item)
+    }
+
+    /// Gives an item back without completing it.
+    pub fn release(&mut self, lease_id: u64, now: Instant) -> Result<WorkItem> {
+        self.reap(now);
+        let l = self.leases.remove(&lease_id).ok_or_else(|| anyhow!("unknown or expired lease {}", lease_id))?;
+        self.queues.entry(l.item.shard).or_default().push_front(l.item.clone());
+        self.requeued += 1;
+        Ok(l.item)
+    }
+
+    pub fn status(&mut self, now: Instant) -> CoordinatorStatus {
+        self.reap(now);
+        let shards: Vec<ShardProgress> = self
+            .queues
+            .iter()
+            .map(|(id, q)| ShardProgress {
+                shard: *id,
+                pending: q.len(),
+                leased: self.leases.values().filter(|l| l.item.shard == *id).count(),
+                done: self.done.get(id).copied().unwrap_or(0),
+            })
+            .collect();
+        CoordinatorStatus {
+            fingerprint: self.fingerprint.clone(),
+            total: self.total,
+            pending: shards.iter().map(|s| s.pending).sum(),
+            leased: self.leases.len(),
+            done: shards.iter().map(|s| s.done).sum(),
+            requeued: self.requeued,
+            shards,
+        }
+    }
+}
+
+// --- HTTP: one JSON request per connection
+//   POST /lease     {worker, shard?}   -> Lease, 503 {retry_after_ms} while only leased items are left, 204 when drained
+//   POST /heartbeat {worker}           -> {extended}
+//   POST /ack       {lease_id}         -> WorkItem
+//   POST /release   {lease_id}         -> WorkItem
+//   GET  /status                       -> CoordinatorStatus
+
+fn handle(c: &Mutex<Coordinator>, method: &str, path: &str, body: &[u8]) -> (u16, Value) {
+    let req: Value = if body.is_empty() { json!({}) } else {
+        match serde_json::from_slice(body) { Ok(v) => v, Err(e) => return (400, json!({ "error": e.to_string() })) }
+    };
+    let now = Instant::now();
+    let mut c = match c.lock() { Ok(g) => g, Err(p) => p.into_inner() };
+    let worker = req.get("worker").and_then(|v| v.as_str());
+    let lease_id = req.get("lease_id").and_then(|v| v.as_u64());
+    let res: Result<(u16, Value)> = match (method, path, worker, lease_id) {
+        ("GET", "/status", _, _) => Ok((200, json!(c.status(now)))),
+        ("POST", "/lease", Some(w), _) => {
+            let shard = req.get("shard").and_then(|v| v.as_u64()).map(|s| s as u32);
+            Ok(match c.lease(w, shard, now) {
+                LeaseReply::Granted(l) => (200, json!(l)),
+                LeaseReply::Wait(d) => (503, json!({ "retry_after_ms": d.as_millis() as u64 })),
+                LeaseReply::Drained => (204, Value::Null),
+            })
+        }
+        ("POST", "/heartbeat", Some(w), _) => Ok((200, json!({ "extended": c.heartbeat(w, now) }))),
+        ("POST", "/ack", _, Some(id)) => c.ack(id, now).map(|it| (200, json!(it))),
+        ("POST", "/release", _, Some(id)) => c.release(id, now).map(|it| (200, json!(it))),
+        ("POST", "/lease" | "/heartbeat", None, _) => Ok((400, json!({ "error": "missing worker" }))),
+        ("POST", "/ack" | "/release", _, None) => Ok((400, json!({ "error": "missing lease_id" }))),
+        _ => Ok((404, json!({ "error": format!("no route for {} {}", method, path) }))),
+    };
+    res.unwrap_or_else(|e| (409, json!({ "error": e.to_string() })))
+}
+
+async fn read_request(sock: &mut TcpStream) -> Result<(String, String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = sock.read(&mut chunk).await?;
+        if n == 0 { return Err(anyhow!("connection closed mid-request")); }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(p) = buf.windows(4).position(|w| w == b"\r\n\r\n") { break p + 4; }
+        if buf.len() > 64 * 1024 { return Err(anyhow!("request header too large")); }
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut first = lines.next().unwrap_or("").split_whitespace();
+    let method = first.next().unwrap_or("").to_string();
+    let path = first.next().unwrap_or("").to_string();
+    let len = lines
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if len > MAX_BODY_BYTES { return Err(anyhow!("request body of {} bytes is too large", len)); }
+    let mut body = buf[header_end..].to_vec();
+    while body.len() < len {
+        let n = sock.read(&mut chunk).await?;
+        if n == 0 { break; }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(len);
+    Ok((method, path, body))
+}
+
+async fn respond(sock: &mut TcpStream, code: u16, body: &Value) -> Result<()> {
+    let reason = match code { 200 => "OK", 204 => "No Content", 400 => "Bad Request", 404 => "Not Found", 409 => "Conflict", 503 => "Service Unavailable", _ => "Error" };
+    let payload = if code == 204 { String::new() } else { serde_json::to_string(body)? };
+    // Retry-After is whole seconds; the body carries the exact wait
+    let retry = match body.get("retry_after_ms").and_then(|v| v.as_u64()) { Some(ms) => format!("Retry-After: {}\r\n", ms.div_ceil(1000)), None => String::new() };
+    let head = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n", code, reason, payload.len(), retry);
+    sock.write_all(head.as_bytes()).await?;
+    sock.write_all(payload.as_bytes()).await?;
+    sock.shutdown().await?;
+    Ok(())
+}
+
+/// Serves `coordinator` on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, coordinator: Arc<Mutex<Coordinator>>) -> Result<()> {
+    loop {
+        let (mut sock, _) = listener.accept().await?;
+        let c = coordinator.clone();
+        tokio::spawn(async move {
+            let res = match read_request(&mut sock).await {
+                Ok((method, path, body)) => {
+                    // acks fsync the journal under the coordinator lock, so keep them off the async workers
+                    match tokio::task::spawn_blocking(move || handle(&c, &method, &path, &body)).await {
+                        Ok((code, v)) => respond(&mut sock, code, &v).await,
+                        Err(e) => respond(&mut sock, 500, &json!({ "error": e.to_string() })).await,
+                    }
+                }
+                Err(e) => respond(&mut sock, 400, &json!({ "error": e.to_string() })).await,
+            };
+            if let Err(e) = res { tracing::debug!(error = %e, "coordinator connection failed"); }
+        });
+    }
+}
+
+/// Minimal client for workers (and tests) talking to `serve`.
+#[derive(Debug, Clone)]
+pub struct CoordinatorClient {
+    pub addr: SocketAddr,
+    pub worker: String,
+}
+
+impl CoordinatorClient {
+    pub fn new(addr: SocketAddr, worker: &str) -> Self { CoordinatorClient { addr, worker: worker.to_string() } }
+
+    async fn call(&self, method: &str, path: &str, body: Option<Value>) -> Result<(u16, Value)> {
+        let mut sock = TcpStream::connect(self.addr).await?;
+        let payload = body.map(|b| b.to_string()).unwrap_or_default();
+        let req = format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", method, path, self.addr, payload.len(), payload);
+        sock.write_all(req.as_bytes()).await?;
+        let mut raw = Vec::new();
+        sock.read_to_end(&mut raw).await?;
+        let split = raw.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(|| anyhow!("malformed response"))?;
+        let status_line = String::from_utf8_lossy(&raw[..split]).lines().next().unwrap_or("").to_string();
+        let code: u16 = status_line.split_whitespace().nth(1).and_then(|c| c.parse().ok()).ok_or_else(|| anyhow!("malformed status line '{}'", status_line))?;
+        let body = &raw[split + 4..];
+        let v = if body.is_empty() { Value::Null } else { serde_json::from_slice(body)? };
+        if code >= 400 && code != 503 {
+            return Err(anyhow!("{} {} failed ({}): {}", method, path, code, v.get("error").and_then(|e| e.as_str()).unwrap_or("")));
+        }
+        Ok((code, v))
+    }
+
+    /// One lease request, answered as the coordinator did.
+    pub async fn poll_lease(&self, shard: Option<u32>) -> Result<LeaseReply> {
+        let (code, v) = self.call("POST", "/lease", Some(json!({ "worker": self.worker, "shard": shard }))).await?;
+        Ok(match code {
+            204 => LeaseReply::Drained,
+            503 => LeaseReply::Wait(Duration::from_millis(v.get("retry_after_ms").and_then(|ms| ms.as_u64()).unwrap_or(1000))),
+            _ => LeaseReply::Granted(serde_json::from_value(v)?),
+        })
+    }
+
+    /// Next item, waiting as told while other workers still hold leases; `None` once drained.
+    pub async fn lease(&self, shard: Option<u32>) -> Result<Option<Lease>> {
+        loop {
+            match self.poll_lease(shard).await? {
+                LeaseReply::Granted(l) => return Ok(Some(l)),
+                LeaseReply::Wait(d) => tokio::time::sleep(d).await,
+                LeaseReply::Drained => return Ok(None),
+            }
+        }
+    }
+
+    pub async fn heartbeat(&self) -> Result<usize> {
+        let (_, v) = self.call("POST", "/heartbeat", Some(json!({ "worker": self.worker }))).await?;
+        Ok(v.get("extended").and_then(|n| n.as_u64()).unwrap_or(0) as usize)
+    }
+
+    pub async fn ack(&self, lease_id: u64) -> Result<WorkItem> {
+        let (_, v) = self.call("POST", "/ack", Some(json!({ "lease_id": lease_id }))).await?;
+        Ok(serde_json::from_value(v)?)
+    }
+
+    pub async fn release(&self, lease_id: u64) -> Result<WorkItem> {
+        let (_, v) = self.call("POST", "/release", Some(json!({ "lease_id": lease_id }))).await?;
+        Ok(serde_json::from_value(v)?)
+    }
+
+    pub async fn status(&self) -> Result<CoordinatorStatus> {
+        let (_, v) = self.call("GET", "/status", None).await?;
+        Ok(serde_json::from_value(v)?)
+    }
+}
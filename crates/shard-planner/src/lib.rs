@@ -1,9 +1,16 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use blake3::Hasher;
 use deltakit_core as core;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashSet};
+
+pub mod constraints;
+pub mod coordinator;
+pub mod progress;
+pub mod usage;
+pub use constraints::Constraint;
+use constraints::ConstraintState;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BalanceMode { Bytes, Rows }
@@ -17,6 +24,204 @@ pub struct ShardOptions {
     pub max_files_per_shard: Option<usize>,
     pub balance: BalanceMode,
     pub row_group_aware: bool,
+    /// files that provably hold no matching row are pruned before sharding
+    #[serde(default)]
+    pub filter: Option<core::Predicate>,
+    /// fraction of files to keep, chosen by seeded stable hash
+    #[serde(default)]
+    pub sample: Option<f64>,
+    #[serde(default)]
+    pub sample_seed: u64,
+    /// sample each group of these partition columns separately so every group keeps its proportion
+    #[serde(default)]
+    pub stratify_by: Vec<String>,
+    /// hard placement rules (pins, separation, per-value share caps)
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    /// consumption order of the files within each shard
+    #[serde(default)]
+    pub order: OrderPolicy,
+    /// quarantined files that never enter a plan
+    #[serde(default, skip_serializing_if = "core::ExclusionList::is_empty")]
+    pub exclude: core::ExclusionList,
+}
+
+/// Intra-shard iteration order. Every policy is a total order (ties fall back to the path),
+/// so re-planning the same files yields the same sequence.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OrderPolicy {
+    /// largest first, as assigned
+    #[default]
+    Assignment,
+    /// by a partition column; nulls go last either way
+    Column { column: String, descending: bool },
+    /// round-robin across the groups formed by these partition columns
+    Interleave { by: Vec<String> },
+    /// seeded shuffle keyed on the path, independent of the other files present
+    Shuffle { seed: u64 },
+}
+
+impl OrderPolicy {
+    /// Parses `dt`, `dt:asc`, `dt:desc`, `interleave:lang,region` or `shuffle:42`.
+    pub fn parse(spec: &str) -> Result<OrderPolicy> {
+        let spec = spec.trim();
+        let (head, arg) = match spec.split_once(':') { Some((h, a)) => (h.trim(), Some(a.trim())), None => (spec, None) };
+        match (head.to_ascii_lowercase().as_str(), arg) {
+            ("", _) => Err(anyhow!("empty order policy")),
+            ("assignment", None) => Ok(OrderPolicy::Assignment),
+            ("interleave", Some(cols)) => {
+                let by: Vec<String> = cols.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect();
+                if by.is_empty() { return Err(anyhow!("interleave needs at least one column")); }
+                Ok(OrderPolicy::Interleave { by })
+            }
+            ("shuffle", Some(seed)) => Ok(OrderPolicy::Shuffle { seed: seed.parse().map_err(|_| anyhow!("invalid shuffle seed '{}'", seed))? }),
+            (_, None) => Ok(OrderPolicy::Column { column: head.to_string(), descending: false }),
+            (_, Some(dir)) => match dir.to_ascii_lowercase().as_str() {
+                "asc" => Ok(OrderPolicy::Column { column: head.to_string(), descending: false }),
+                "desc" => Ok(OrderPolicy::Column { column: head.to_string(), descending: true }),
+                _ => Err(anyhow!("invalid order '{}', expected <column>[:asc|:desc], interleave:<cols> or shuffle:<seed>", spec)),
+            },
+        }
+    }
+}
+
+/// Numbers first in numeric order, then other values lexically, then nulls. A total order, so the
+/// result does not depend on which other values are present.
+fn cmp_partition_values(a: Option<&str>, b: Option<&str>) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(x), Some(y)) => match (x.parse::<f64>().ok(), y.parse::<f64>().ok()) {
+            (Some(p), Some(q)) => p.total_cmp(&q).then_with(|| x.cmp(y)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => x.cmp(y),
+        },
+    }
+}
+
+fn ordered(mut files: Vec<ShardFile>, policy: &OrderPolicy) -> Vec<ShardFile> {
+    match policy {
+        OrderPolicy::Assignment => files,
+        OrderPolicy::Column { column, descending } => {
+            files.sort_by(|a, b| {
+                let (x, y) = (a.partition.get(column).and_then(|o| o.as_deref()), b.partition.get(column).and_then(|o| o.as_deref()));
+                let ord = match (x, y, descending) {
+                    (Some(_), Some(_), true) => cmp_partition_values(y, x),
+                    _ => cmp_partition_values(x, y),
+                };
+                ord.then_with(|| a.path.cmp(&b.path))
+            });
+            files
+        }
+        OrderPolicy::Interleave { by } => {
+            let mut groups: BTreeMap<Vec<(String, String)>, std::collections::VecDeque<ShardFile>> = BTreeMap::new();
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            for f in files { groups.entry(group_key(by, &f.partition)).or_default().push_back(f); }
+            let mut out = Vec::new();
+            while !groups.is_empty() {
+                groups.retain(|_, q| {
+                    if let Some(f) = q.pop_front() { out.push(f); }
+                    !q.is_empty()
+                });
+            }
+            out
+        }
+        OrderPolicy::Shuffle { seed } => {
+            files.sort_by(|a, b| sample_key(*seed, &a.path).total_cmp(&sample_key(*seed, &b.path)).then_with(|| a.path.cmp(&b.path)));
+            files
+        }
+    }
+}
+
+fn apply_order(out: &mut [Shard], policy: &OrderPolicy) {
+    for s in out.iter_mut() { s.files = ordered(std::mem::take(&mut s.files), policy); }
+}
+
+/// How the input file set was narrowed; recorded so a plan can be reproduced.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Selection {
+    pub filter: Option<String>,
+    pub sample: Option<f64>,
+    pub sample_seed: u64,
+    pub stratify_by: Vec<String>,
+    pub files_considered: usize,
+    pub files_pruned: usize,
+    pub files_sampled_out: usize,
+    /// quarantined files skipped before filtering and sampling
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<core::ExcludedFile>,
+    /// files kept by the sample in each `stratify_by` stratum
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strata: Vec<StratumCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StratumCount {
+    pub partition: BTreeMap<String, String>,
+    /// files in the stratum after filtering
+    pub files: usize,
+    pub sampled: usize,
+}
+
+impl Selection {
+    fn add_strata(&mut self, strata: Vec<StratumCount>) {
+        for c in strata {
+            match self.strata.iter_mut().find(|s| s.partition == c.partition) {
+                Some(s) => {
+                    s.files += c.files;
+                    s.sampled += c.sampled;
+                }
+                None => self.strata.push(c),
+            }
+        }
+        self.strata.sort_by(|a, b| a.partition.cmp(&b.partition));
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardPlan {
+    pub version: i64,
+    pub selection: Selection,
+    #[serde(default)]
+    pub order: OrderPolicy,
+    pub shards: Vec<Shard>,
+}
+
+/// A saved plan: either a plain shard list or a `ShardPlan` with its recorded settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PlanFile { Plan(ShardPlan), Shards(Vec<Shard>) }
+
+impl PlanFile {
+    pub fn read(path: &std::path::Path) -> Result<PlanFile> {
+        let raw = std::fs::read(path).map_err(|e| anyhow!("reading plan {}: {}", path.display(), e))?;
+        Ok(serde_json::from_slice(&raw)?)
+    }
+
+    pub fn shards(&self) -> &[Shard] {
+        match self { PlanFile::Plan(p) => &p.shards, PlanFile::Shards(v) => v }
+    }
+
+    pub fn into_shards(self) -> Vec<Shard> {
+        match self { PlanFile::Plan(p) => p.shards, PlanFile::Shards(v) => v }
+    }
+}
+
+/// Stable identity of a plan: the ordered (shard, path) pairs.
+pub fn plan_fingerprint(plan: &[Shard]) -> String {
+    let mut h = Hasher::new();
+    for s in plan {
+        for f in &s.files {
+            h.update(&s.id.to_le_bytes());
+            h.update(f.path.as_bytes());
+            h.update(b"\n");
+        }
+    }
+    h.finalize().to_hex().to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +230,9 @@ pub struct ShardFile {
     pub bytes: i64,
     pub approx_rows: u64,
     pub partition: BTreeMap<String, Option<String>>,
+    /// name of the source table in multi-table plans
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,42 +255,105 @@ fn stable_hash(parts: &[(String, String)]) -> u64 {
     u64::from_le_bytes(x.as_bytes()[0..8].try_into().unwrap())
 }
 
-pub async fn plan_shards(
-    h: &core::DeltaTableHandle,
-    version: i64,
-    shards: u32,
-    opts: ShardOptions,
-) -> Result<Vec<Shard>> {
-    let files = core::list_active_files(h, Some(version)).await?;
+fn group_key(keys: &[String], partition: &BTreeMap<String, Option<String>>) -> Vec<(String, String)> {
+    keys.iter()
+        .map(|k| (k.clone(), partition.get(k).and_then(|o| o.clone()).unwrap_or_else(|| "__UNKNOWN__".to_string())))
+        .collect()
+}
 
+fn to_shard_files(files: Vec<core::AddFileLite>) -> Vec<ShardFile> {
     let mut items: Vec<ShardFile> = Vec::with_capacity(files.len());
     for f in files {
-        let approx_rows = 0u64; // core fast_rowcount per-file unexposed
+        let approx_rows = f.stats.as_ref().and_then(|s| s.num_records).unwrap_or(0);
         items.push(ShardFile {
             path: f.path,
             bytes: f.size,
             approx_rows,
             partition: f.partition_values,
+            source: None,
         });
     }
+    items
+}
+
+fn sample_key(seed: u64, path: &str) -> f64 {
+    unit_interval(&[("seed".to_string(), seed.to_string()), ("path".to_string(), path.to_string())])
+}
 
-    // group by co-location keys (opts.by) & create buckets
-    let mut groups: HashMap<Vec<(String, String)>, Vec<ShardFile>> = HashMap::new();
+/// Applies `opts.filter` and `opts.sample` to the active file set.
+pub fn select_files(files: Vec<core::AddFileLite>, opts: &ShardOptions) -> Result<(Vec<core::AddFileLite>, Selection)> {
+    if opts.sample.is_none() && !opts.stratify_by.is_empty() { return Err(anyhow!("stratify_by only applies to a sample; give a sample fraction")); }
+    let (files, excluded) = opts.exclude.apply(files);
+    let mut sel = Selection {
+        filter: opts.filter.as_ref().map(|p| p.to_string()),
+        sample: opts.sample,
+        sample_seed: opts.sample_seed,
+        stratify_by: opts.stratify_by.clone(),
+        files_considered: files.len(),
+        excluded,
+        ..Default::default()
+    };
+    let kept: Vec<core::AddFileLite> = match &opts.filter {
+        Some(p) => files.into_iter().filter(|f| p.may_match(f)).collect(),
+        None => files,
+    };
+    sel.files_pruned = sel.files_considered - kept.len();
+
+    let frac = match opts.sample {
+        None => return Ok((kept, sel)),
+        Some(f) if !(0.0..=1.0).contains(&f) => return Err(anyhow!("sample fraction must be within [0, 1], got {}", f)),
+        Some(f) => f,
+    };
+    let before = kept.len();
+    let sampled: Vec<core::AddFileLite> = if opts.stratify_by.is_empty() {
+        // threshold on the hash: a file's membership never changes as the table grows
+        kept.into_iter().filter(|f| sample_key(opts.sample_seed, &f.path) < frac).collect()
+    } else {
+        // same per-file threshold as above, salted with the stratum so strata are drawn independently;
+        // a file stays in or out as its stratum grows, and a small stratum may keep none
+        let mut strata: BTreeMap<Vec<(String, String)>, Vec<core::AddFileLite>> = BTreeMap::new();
+        for f in kept { strata.entry(group_key(&opts.stratify_by, &f.partition_values)).or_default().push(f); }
+        let mut out = Vec::new();
+        let mut counts = Vec::with_capacity(strata.len());
+        for (key, group) in strata {
+            let files = group.len();
+            let mut salt = key.clone();
+            salt.push(("seed".to_string(), opts.sample_seed.to_string()));
+            let taken: Vec<core::AddFileLite> = group
+                .into_iter()
+                .filter(|f| {
+                    let mut parts = salt.clone();
+                    parts.push(("path".to_string(), f.path.clone()));
+                    unit_interval(&parts) < frac
+                })
+                .collect();
+            counts.push(StratumCount { partition: key.into_iter().collect(), files, sampled: taken.len() });
+            out.extend(taken);
+        }
+        sel.add_strata(counts);
+        out
+    };
+    sel.files_sampled_out = before - sampled.len();
+    Ok((sampled, sel))
+}
+
+// appends items to `out` without moving anything already assigned; returns the files
+// `max_files_per_shard` left without a shard
+fn assign_files(out: &mut [Shard], items: Vec<ShardFile>, opts: &ShardOptions) -> Result<Vec<ShardFile>> {
+    let mut rules = ConstraintState::new(&opts.constraints, opts.balance.clone(), out, items.iter())?;
+    assign_with_rules(out, items, opts, &mut rules)
+}
+
+// `rules` may already hold placements made outside `out` on the same shard ids
+fn assign_with_rules(out: &mut [Shard], items: Vec<ShardFile>, opts: &ShardOptions, rules: &mut ConstraintState) -> Result<Vec<ShardFile>> {
+    // group by co-location keys (opts.by) & create buckets; BTreeMap keeps assignment order stable
+    let mut groups: BTreeMap<Vec<(String, String)>, Vec<ShardFile>> = BTreeMap::new();
     for it in items.into_iter() {
-        let key: Vec<(String, String)> = opts
-            .by
-            .iter()
-            .map(|k| (k.clone(), it.partition.get(k).and_then(|o| o.clone()).unwrap_or_else(|| "__UNKNOWN__".to_string())))
-            .collect();
-        groups.entry(key).or_default().push(it);
+        groups.entry(group_key(&opts.by, &it.partition)).or_default().push(it);
     }
 
-    // prep K shards
-    let k = shards.max(1);
-    let mut out: Vec<Shard> = (0..k)
-        .map(|i| Shard { id: i, bytes: 0, rows: 0, files: Vec::new() })
-        .collect();
-
+    let k = out.len();
+    let mut unplaced = Vec::new();
     // each group assigned to shards using stable hashing over sticky_by keys then greedy balance
     for (group_key, mut files) in groups.into_iter() {
         // stable seed from sticky_by subset
@@ -97,26 +368,423 @@ pub async fn plan_shards(
         };
         let base_idx = (stable_hash(&sticky_pairs) % (k as u64)) as usize;
 
-        files.sort_by_key(|f| match opts.balance { BalanceMode::Bytes => -(f.bytes as i64), BalanceMode::Rows => -(f.approx_rows as i64) });
+        files.sort_by(|a, b| match opts.balance { BalanceMode::Bytes => b.bytes.cmp(&a.bytes), BalanceMode::Rows => b.approx_rows.cmp(&a.approx_rows) }.then_with(|| a.path.cmp(&b.path)));
 
         for f in files.into_iter() {
+            let allowed = rules.allowed(&f)?;
             let (target_idx, _) = (0..k)
-                .map(|offset| ( (base_idx + offset as usize) % (k as usize) , &out[(base_idx + offset as usize) % (k as usize)]))
+                .map(|offset| ((base_idx + offset) % k, &out[(base_idx + offset) % k]))
+                .filter(|(i, _)| allowed[*i])
                 .min_by_key(|(_, s)| match opts.balance { BalanceMode::Bytes => s.bytes, BalanceMode::Rows => s.rows as i64 })
                 .unwrap();
 
             if let Some(maxf) = opts.max_files_per_shard {
-                if out[target_idx].files.len() >= maxf { continue; }
+                if out[target_idx].files.len() >= maxf {
+                    unplaced.push(f);
+                    continue;
+                }
             }
+            rules.record(&f, target_idx);
             out[target_idx].bytes += f.bytes.max(0);
             out[target_idx].rows += f.approx_rows;
             out[target_idx].files.push(f);
         }
     }
+    Ok(unplaced)
+}
+
+pub async fn plan_shards(
+    h: &core::DeltaTableHandle,
+    version: i64,
+    shards: u32,
+    opts: ShardOptions,
+) -> Result<Vec<Shard>> {
+    Ok(plan_shards_with_selection(h, version, shards, opts).await?.shards)
+}
+
+/// Same as `plan_shards`, but also returns the filter/sample settings and counts behind the plan.
+pub async fn plan_shards_with_selection(
+    h: &core::DeltaTableHandle,
+    version: i64,
+    shards: u32,
+    opts: ShardOptions,
+) -> Result<ShardPlan> {
+    let files = core::list_active_files(h, Some(version)).await?;
+    let (files, selection) = select_files(files, &opts)?;
+    let items = to_shard_files(files);
+
+    // prep K shards
+    let k = shards.max(1);
+    let mut out: Vec<Shard> = (0..k)
+        .map(|i| Shard { id: i, bytes: 0, rows: 0, files: Vec::new() })
+        .collect();
+    assign_files(&mut out, items, &opts)?;
+    apply_order(&mut out, &opts.order);
+
+    Ok(ShardPlan { version, selection, order: opts.order, shards: out })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitSpec {
+    pub name: String,
+    pub ratio: f64,
+    /// overrides the plan-wide shard count for this split
+    pub shards: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitPlan {
+    pub name: String,
+    pub ratio: f64,
+    pub files: usize,
+    pub bytes: i64,
+    /// how the input of all splits was narrowed; the same for every split
+    pub selection: Selection,
+    #[serde(default)]
+    pub order: OrderPolicy,
+    pub shards: Vec<Shard>,
+}
+
+/// Parses `train=0.98,val=0.01,test=0.01`; a split may carry its own shard count as `val=0.01:4`.
+pub fn parse_splits(spec: &str) -> Result<Vec<SplitSpec>> {
+    let mut out = Vec::new();
+    for part in spec.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        let (name, rest) = part.split_once('=').ok_or_else(|| anyhow!("invalid split '{}', expected name=ratio", part))?;
+        let (ratio, shards) = match rest.split_once(':') {
+            Some((r, k)) => (r, Some(k.trim().parse::<u32>().map_err(|_| anyhow!("invalid shard count in split '{}'", part))?)),
+            None => (rest, None),
+        };
+        let ratio = ratio.trim().parse::<f64>().map_err(|_| anyhow!("invalid ratio in split '{}'", part))?;
+        out.push(SplitSpec { name: name.trim().to_string(), ratio, shards });
+    }
+    Ok(out)
+}
+
+fn validate_splits(splits: &[SplitSpec]) -> Result<()> {
+    if splits.is_empty() { return Err(anyhow!("no splits given")); }
+    let mut names = HashSet::new();
+    for s in splits {
+        if s.name.is_empty() { return Err(anyhow!("split name must not be empty")); }
+        if !names.insert(s.name.as_str()) { return Err(anyhow!("duplicate split '{}'", s.name)); }
+        if !s.ratio.is_finite() || s.ratio <= 0.0 { return Err(anyhow!("split '{}' ratio must be > 0", s.name)); }
+    }
+    let total: f64 = splits.iter().map(|s| s.ratio).sum();
+    if (total - 1.0).abs() > 1e-6 { return Err(anyhow!("split ratios sum to {}, expected 1.0", total)); }
+    Ok(())
+}
+
+// position of a key in [0, 1); depends only on the key so membership never changes as the table grows
+fn unit_interval(parts: &[(String, String)]) -> f64 {
+    (stable_hash(parts) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn split_index(splits: &[SplitSpec], x: f64) -> usize {
+    let mut acc = 0.0;
+    for (i, s) in splits.iter().enumerate() {
+        acc += s.ratio;
+        if x < acc { return i; }
+    }
+    splits.len() - 1
+}
+
+/// Assigns files (or whole `by` groups) to named splits by stable hash, then shards each split independently.
+pub async fn plan_splits(
+    h: &core::DeltaTableHandle,
+    version: i64,
+    shards: u32,
+    splits: &[SplitSpec],
+    opts: ShardOptions,
+) -> Result<Vec<SplitPlan>> {
+    validate_splits(splits)?;
+    let files = core::list_active_files(h, Some(version)).await?;
+    let (files, selection) = select_files(files, &opts)?;
+    let mut buckets: Vec<Vec<ShardFile>> = vec![Vec::new(); splits.len()];
+    for f in to_shard_files(files) {
+        let key = if opts.by.is_empty() { vec![("path".to_string(), f.path.clone())] } else { group_key(&opts.by, &f.partition) };
+        buckets[split_index(splits, unit_interval(&key))].push(f);
+    }
 
+    let mut out = Vec::with_capacity(splits.len());
+    for (spec, items) in splits.iter().zip(buckets) {
+        let k = spec.shards.unwrap_or(shards).max(1);
+        let mut plan: Vec<Shard> = (0..k)
+            .map(|i| Shard { id: i, bytes: 0, rows: 0, files: Vec::new() })
+            .collect();
+        let files = items.len();
+        let bytes = items.iter().map(|f| f.bytes.max(0)).sum();
+        assign_files(&mut plan, items, &opts)?;
+        apply_order(&mut plan, &opts.order);
+        out.push(SplitPlan { name: spec.name.clone(), ratio: spec.ratio, files, bytes, selection: selection.clone(), order: opts.order.clone(), shards: plan });
+    }
     Ok(out)
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixtureSource {
+    pub name: String,
+    pub table: core::DeltaTableHandle,
+    pub version: i64,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceStats {
+    pub name: String,
+    pub uri: String,
+    pub version: i64,
+    pub weight: f64,
+    pub available_files: usize,
+    pub available_bytes: i64,
+    pub selected_files: usize,
+    pub selected_bytes: i64,
+    pub selected_rows: u64,
+    /// realised share of the plan in the balance unit
+    pub share: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceShare {
+    pub files: usize,
+    pub bytes: i64,
+    pub rows: u64,
+    pub share: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardMixture {
+    pub shard: u32,
+    pub sources: BTreeMap<String, SourceShare>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixturePlan {
+    /// settings shared by all sources; counts and exclusions summed over them
+    pub selection: Selection,
+    #[serde(default)]
+    pub order: OrderPolicy,
+    pub sources: Vec<SourceStats>,
+    pub shards: Vec<Shard>,
+    pub mixture: Vec<ShardMixture>,
+}
+
+fn weight_of(f: &ShardFile, mode: &BalanceMode) -> f64 {
+    match mode { BalanceMode::Bytes => f.bytes.max(0) as f64, BalanceMode::Rows => f.approx_rows as f64 }
+}
+
+/// Plans K shards over several tables so every shard matches the target mixture. The scarcest source
+/// (relative to its weight) is taken whole; the others are subsampled by stable path hash to fit.
+pub async fn plan_mixture(sources: &[MixtureSource], shards: u32, opts: ShardOptions) -> Result<MixturePlan> {
+    if sources.is_empty() { return Err(anyhow!("no sources given")); }
+    let mut names = HashSet::new();
+    for s in sources {
+        if !names.insert(s.name.as_str()) { return Err(anyhow!("duplicate source '{}'", s.name)); }
+        if !s.weight.is_finite() || s.weight <= 0.0 { return Err(anyhow!("source '{}' weight must be > 0", s.name)); }
+    }
+    let total_weight: f64 = sources.iter().map(|s| s.weight).sum();
+
+    let mut pools = Vec::with_capacity(sources.len());
+    let mut selection = Selection { filter: opts.filter.as_ref().map(|p| p.to_string()), sample: opts.sample, sample_seed: opts.sample_seed, stratify_by: opts.stratify_by.clone(), ..Default::default() };
+    for s in sources {
+        let files = core::list_active_files(&s.table, Some(s.version)).await?;
+        let (files, sel) = select_files(files, &opts)?;
+        selection.files_considered += sel.files_considered;
+        selection.files_pruned += sel.files_pruned;
+        selection.files_sampled_out += sel.files_sampled_out;
+        selection.excluded.extend(sel.excluded);
+        selection.add_strata(sel.strata);
+        let mut items = to_shard_files(files);
+        for it in items.iter_mut() { it.source = Some(s.name.clone()); }
+        let avail: f64 = items.iter().map(|f| weight_of(f, &opts.balance)).sum();
+        if avail <= 0.0 {
+            let unit = match opts.balance { BalanceMode::Bytes => "bytes", BalanceMode::Rows => "rows" };
+            return Err(anyhow!("source '{}' has no {} at version {}", s.name, unit, s.version));
+        }
+        pools.push((items, avail));
+    }
+
+    // total plan size the mixture can sustain without repeating data
+    let budget = sources
+        .iter()
+        .zip(pools.iter())
+        .map(|(s, (_, avail))| avail / (s.weight / total_weight))
+        .fold(f64::INFINITY, f64::min);
+
+    let k = shards.max(1);
+    let mut out: Vec<Shard> = (0..k)
+        .map(|i| Shard { id: i, bytes: 0, rows: 0, files: Vec::new() })
+        .collect();
+    let mut picks = Vec::with_capacity(sources.len());
+    for (s, (mut items, _)) in sources.iter().zip(pools) {
+        let available_files = items.len();
+        let available_bytes: i64 = items.iter().map(|f| f.bytes.max(0)).sum();
+        let quota = budget * s.weight / total_weight;
+        items.sort_by_key(|f| (stable_hash(&[("path".to_string(), f.path.clone())]), f.path.clone()));
+        let mut taken = 0.0;
+        let mut selected = Vec::new();
+        for f in items {
+            if taken >= quota * (1.0 - 1e-9) { break; }
+            taken += weight_of(&f, &opts.balance);
+            selected.push(f);
+        }
+        picks.push((available_files, available_bytes, selected));
+    }
+
+    // constraints hold for the merged shards, so one rule state sees every source's placements
+    let mut rules = ConstraintState::new(&opts.constraints, opts.balance.clone(), &out, picks.iter().flat_map(|(_, _, sel)| sel.iter()))?;
+    let mut stats = Vec::with_capacity(sources.len());
+    for (s, (available_files, available_bytes, selected)) in sources.iter().zip(picks) {
+        // balance each source on its own so every shard gets its slice of the mixture;
+        // max_files_per_shard therefore applies per source
+        let mut per_source: Vec<Shard> = (0..k)
+            .map(|i| Shard { id: i, bytes: 0, rows: 0, files: Vec::new() })
+            .collect();
+        assign_with_rules(&mut per_source, selected, &opts, &mut rules)?;
+        let mut selected_files = 0;
+        let mut selected_bytes = 0;
+        let mut selected_rows = 0;
+        for (dst, src) in out.iter_mut().zip(per_source) {
+            selected_files += src.files.len();
+            selected_bytes += src.bytes;
+            selected_rows += src.rows;
+            dst.bytes += src.bytes;
+            dst.rows += src.rows;
+            dst.files.extend(src.files);
+        }
+        stats.push(SourceStats {
+            name: s.name.clone(),
+            uri: s.table.uri.clone(),
+            version: s.version,
+            weight: s.weight / total_weight,
+            available_files,
+            available_bytes,
+            selected_files,
+            selected_bytes,
+            selected_rows,
+            share: 0.0,
+        });
+    }
+
+    apply_order(&mut out, &opts.order);
+
+    let unit_total: f64 = stats.iter().map(|s| match opts.balance { BalanceMode::Bytes => s.selected_bytes as f64, BalanceMode::Rows => s.selected_rows as f64 }).sum();
+    for s in stats.iter_mut() {
+        let v = match opts.balance { BalanceMode::Bytes => s.selected_bytes as f64, BalanceMode::Rows => s.selected_rows as f64 };
+        s.share = if unit_total > 0.0 { v / unit_total } else { 0.0 };
+    }
+
+    let mixture = out
+        .iter()
+        .map(|sh| {
+            let mut by_source: BTreeMap<String, SourceShare> = BTreeMap::new();
+            for f in &sh.files {
+                let e = by_source.entry(f.source.clone().unwrap_or_default()).or_insert(SourceShare { files: 0, bytes: 0, rows: 0, share: 0.0 });
+                e.files += 1;
+                e.bytes += f.bytes.max(0);
+                e.rows += f.approx_rows;
+            }
+            let shard_total = match opts.balance { BalanceMode::Bytes => sh.bytes as f64, BalanceMode::Rows => sh.rows as f64 };
+            for e in by_source.values_mut() {
+                let v = match opts.balance { BalanceMode::Bytes => e.bytes as f64, BalanceMode::Rows => e.rows as f64 };
+                e.share = if shard_total > 0.0 { v / shard_total } else { 0.0 };
+            }
+            ShardMixture { shard: sh.id, sources: by_source }
+        })
+        .collect();
+
+    Ok(MixturePlan { selection, order: opts.order, sources: stats, shards: out, mixture })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemovedFile {
+    pub shard: u32,
+    pub path: String,
+    pub bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TailPlan {
+    pub from: i64,
+    pub to: i64,
+    /// per-shard additions only
+    pub added: Vec<Shard>,
+    /// files of the base plan that are no longer active at `to`; dropped from `plan`, never reassigned
+    pub removed: Vec<RemovedFile>,
+    /// cumulative plan at `to`
+    pub plan: Vec<Shard>,
+    /// quarantined files skipped, including ones dropped from the base plan
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<core::ExcludedFile>,
+    /// selected files `max_files_per_shard` kept out of every shard; the next tail offers them again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unplaced: Vec<ShardFile>,
+}
+
+/// Extends a plan made at `from` with the files appended up to `to`. Existing assignments never move.
+pub async fn plan_tail(
+    h: &core::DeltaTableHandle,
+    base: &[Shard],
+    from: i64,
+    to: i64,
+    opts: ShardOptions,
+) -> Result<TailPlan> {
+    if to < from { return Err(anyhow!("to must be >= from")); }
+    if base.is_empty() { return Err(anyhow!("base plan has no shards")); }
+    let files_from = core::list_active_files(h, Some(from)).await?;
+    let files_to = core::list_active_files(h, Some(to)).await?;
+    // files the base plan holds or the selection left out at `from` are settled; anything else
+    // active at `from` (e.g. kept out by max_files_per_shard) is still waiting for a shard
+    let in_base: HashSet<&str> = base.iter().flat_map(|s| s.files.iter().map(|f| f.path.as_str())).collect();
+    let mut known: HashSet<String> = files_from.iter().map(|f| f.path.clone()).collect();
+    for f in select_files(files_from, &opts)?.0 {
+        if !in_base.contains(f.path.as_str()) { known.remove(&f.path); }
+    }
+    let active: HashSet<String> = files_to.iter().map(|f| f.path.clone()).collect();
+
+    let mut plan: Vec<Shard> = base.to_vec();
+    let mut removed = Vec::new();
+    let mut excluded = Vec::new();
+    for s in plan.iter_mut() {
+        let (keep, gone): (Vec<ShardFile>, Vec<ShardFile>) = std::mem::take(&mut s.files).into_iter().partition(|f| active.contains(&f.path));
+        for f in gone {
+            s.bytes = s.bytes.saturating_sub(f.bytes.max(0)).max(0);
+            // the base plan is user input; totals that disagree with its files must not underflow
+            s.rows = s.rows.saturating_sub(f.approx_rows);
+            removed.push(RemovedFile { shard: s.id, path: f.path, bytes: f.bytes });
+        }
+        // files quarantined since the base plan was made leave it as well
+        let (keep, bad): (Vec<ShardFile>, Vec<ShardFile>) = keep.into_iter().partition(|f| opts.exclude.matching(&f.path).is_none());
+        for f in bad {
+            s.bytes = s.bytes.saturating_sub(f.bytes.max(0)).max(0);
+            s.rows = s.rows.saturating_sub(f.approx_rows);
+            let e = opts.exclude.matching(&f.path).expect("partitioned on a match");
+            excluded.push(core::ExcludedFile { path: f.path.clone(), size: f.bytes, pattern: e.pattern.clone(), reason: e.reason.clone() });
+        }
+        s.files = keep;
+    }
+
+    let new_files: Vec<core::AddFileLite> = files_to.into_iter().filter(|f| !known.contains(&f.path)).collect();
+    let (new_files, selection) = select_files(new_files, &opts)?;
+    excluded.extend(selection.excluded);
+    let before: Vec<usize> = plan.iter().map(|s| s.files.len()).collect();
+    let unplaced = assign_files(&mut plan, to_shard_files(new_files), &opts)?;
+    // only the appended files are ordered; whatever precedes them may already be consumed
+    for (s, n) in plan.iter_mut().zip(before.iter()) {
+        let tail = s.files.split_off(*n);
+        s.files.extend(ordered(tail, &opts.order));
+    }
+
+    let added = plan
+        .iter()
+        .zip(before)
+        .map(|(s, n)| {
+            let files = s.files[n..].to_vec();
+            Shard { id: s.id, bytes: files.iter().map(|f| f.bytes.max(0)).sum(), rows: files.iter().map(|f| f.approx_rows).sum(), files }
+        })
+        .collect();
+
+    Ok(TailPlan { from, to, added, removed, plan, excluded, unplaced })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,12 +842,585 @@ mod tests {
         let ver = core::current_version(&h).await.unwrap();
         assert_eq!(ver, 1);
 
-        let opts = ShardOptions { by: vec!["dt".into()], sticky_by: vec!["dt".into()], max_files_per_shard: None, balance: BalanceMode::Bytes, row_group_aware: false };
+        let opts = ShardOptions { by: vec!["dt".into()], sticky_by: vec!["dt".into()], max_files_per_shard: None, balance: BalanceMode::Bytes, row_group_aware: false, ..Default::default() };
         let shards = plan_shards(&h, ver, 2, opts).await.unwrap();
         assert_eq!(shards.len(), 2);
         let total_files: usize = shards.iter().map(|s| s.files.len()).sum();
         assert!(total_files >= 2);
     }
-}
 
+    #[tokio::test]
+    async fn test_tail_plan_keeps_assignments() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        write_delta_log(&dir, 0, &[
+            protocol_action(),
+            metadata_action(&["dt"]),
+            add_action("dt=2024-01-01/a.parquet", 100, "dt", "2024-01-01", 10),
+            add_action("dt=2024-01-01/b.parquet", 80, "dt", "2024-01-01", 8),
+        ]);
+        write_delta_log(&dir, 1, &[
+            remove_action("dt=2024-01-01/a.parquet"),
+            add_action("dt=2024-01-02/c.parquet", 50, "dt", "2024-01-02", 5),
+            add_action("dt=2024-01-02/d.parquet", 40, "dt", "2024-01-02", 4),
+        ]);
+
+        let uri = dir.to_string_lossy().to_string();
+        let h = core::load_table(&uri).await.unwrap();
+        let opts = ShardOptions { by: vec!["dt".into()], ..Default::default() };
+        let base = plan_shards(&h, 0, 3, opts.clone()).await.unwrap();
+        let tail = plan_tail(&h, &base, 0, 1, opts.clone()).await.unwrap();
+
+        assert_eq!(tail.removed.len(), 1);
+        assert_eq!(tail.removed[0].path, "dt=2024-01-01/a.parquet");
+        let added: usize = tail.added.iter().map(|s| s.files.len()).sum();
+        assert_eq!(added, 2);
+        // b stays where the base plan put it
+        let owner = |plan: &[Shard], p: &str| plan.iter().find(|s| s.files.iter().any(|f| f.path == p)).map(|s| s.id);
+        assert_eq!(owner(&base, "dt=2024-01-01/b.parquet"), owner(&tail.plan, "dt=2024-01-01/b.parquet"));
+        assert!(owner(&tail.plan, "dt=2024-01-01/a.parquet").is_none());
+        let total: usize = tail.plan.iter().map(|s| s.files.len()).sum();
+        assert_eq!(total, 3);
+
+        // a hand-edited plan whose totals undercount its files must not underflow
+        let mut skewed = base.clone();
+        for s in skewed.iter_mut() { s.rows = 0; }
+        let tail = plan_tail(&h, &skewed, 0, 1, opts.clone()).await.unwrap();
+        assert_eq!(tail.removed.len(), 1);
+        for s in skewed.iter_mut() { s.bytes = 0; }
+        let tail = plan_tail(&h, &skewed, 0, 1, opts).await.unwrap();
+        assert!(tail.plan.iter().all(|s| s.bytes >= 0));
+    }
+
+    #[tokio::test]
+    async fn test_tail_plan_offers_files_the_cap_left_out() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        write_delta_log(&dir, 0, &[
+            protocol_action(),
+            metadata_action(&["dt"]),
+            add_action("dt=1/a.parquet", 100, "dt", "1", 10),
+            add_action("dt=1/b.parquet", 80, "dt", "1", 8),
+        ]);
+        write_delta_log(&dir, 1, &[add_action("dt=2/c.parquet", 50, "dt", "2", 5)]);
+        let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+        let capped = ShardOptions { max_files_per_shard: Some(1), ..Default::default() };
+        let base = plan_shards(&h, 0, 1, capped.clone()).await.unwrap();
+        assert_eq!(base[0].files.len(), 1);
+        let left_out = if base[0].files[0].path == "dt=1/a.parquet" { "dt=1/b.parquet" } else { "dt=1/a.parquet" };
 
+        let tail = plan_tail(&h, &base, 0, 1, capped).await.unwrap();
+        let mut unplaced: Vec<&str> = tail.unplaced.iter().map(|f| f.path.as_str()).collect();
+        unplaced.sort();
+        let mut expected = vec![left_out, "dt=2/c.parquet"];
+        expected.sort();
+        assert_eq!(unplaced, expected);
+        assert!(tail.added.iter().all(|s| s.files.is_empty()));
+
+        let roomy = ShardOptions { max_files_per_shard: Some(3), ..Default::default() };
+        let tail = plan_tail(&h, &base, 0, 1, roomy).await.unwrap();
+        assert!(tail.unplaced.is_empty());
+        let mut added: Vec<&str> = tail.added[0].files.iter().map(|f| f.path.as_str()).collect();
+        added.sort();
+        assert_eq!(added, expected);
+    }
+
+    #[tokio::test]
+    async fn test_split_plan_is_stable() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let adds: Vec<String> = (0..40).map(|i| add_action(&format!("dt=2024-01-01/f{}.parquet", i), 10 + i, "dt", "2024-01-01", 1)).collect();
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        v0.extend(adds[..20].iter().cloned());
+        write_delta_log(&dir, 0, &v0);
+        write_delta_log(&dir, 1, &adds[20..]);
+
+        let uri = dir.to_string_lossy().to_string();
+        let h = core::load_table(&uri).await.unwrap();
+        let splits = parse_splits("train=0.8,val=0.1:1,test=0.1").unwrap();
+        assert_eq!(splits[1].shards, Some(1));
+        assert!(parse_splits("train=0.8,val=0.1").map(|s| validate_splits(&s)).unwrap().is_err());
+
+        let at = |plan: &[SplitPlan], p: &str| plan.iter().find(|s| s.shards.iter().any(|sh| sh.files.iter().any(|f| f.path == p))).map(|s| s.name.clone());
+        let p0 = plan_splits(&h, 0, 2, &splits, ShardOptions::default()).await.unwrap();
+        let p1 = plan_splits(&h, 1, 2, &splits, ShardOptions::default()).await.unwrap();
+        assert_eq!(p1.iter().map(|s| s.files).sum::<usize>(), 40);
+        assert_eq!(p1[1].shards.len(), 1);
+        for i in 0..20 {
+            let p = format!("dt=2024-01-01/f{}.parquet", i);
+            assert_eq!(at(&p0, &p), at(&p1, &p));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mixture_plan_matches_weights() {
+        let web_dir = tempfile::tempdir().unwrap();
+        let books_dir = tempfile::tempdir().unwrap();
+        let mut web = vec![protocol_action(), metadata_action(&["dt"])];
+        web.extend((0..50).map(|i| add_action(&format!("dt=2024-01-01/w{}.parquet", i), 100, "dt", "2024-01-01", 1)));
+        write_delta_log(&web_dir.path().to_path_buf(), 0, &web);
+        let mut books = vec![protocol_action(), metadata_action(&["dt"])];
+        books.extend((0..10).map(|i| add_action(&format!("dt=2024-01-01/b{}.parquet", i), 100, "dt", "2024-01-01", 1)));
+        write_delta_log(&books_dir.path().to_path_buf(), 0, &books);
+
+        let src = |name: &str, dir: &tempfile::TempDir, weight: f64| MixtureSource {
+            name: name.into(),
+            table: core::DeltaTableHandle { uri: dir.path().to_string_lossy().to_string(), version: None },
+            version: 0,
+            weight,
+        };
+        let sources = vec![src("web", &web_dir, 0.75), src("books", &books_dir, 0.25)];
+        let plan = plan_mixture(&sources, 2, ShardOptions::default()).await.unwrap();
+
+        // books (1000 B) binds at 25%, so web contributes 3000 B
+        assert_eq!(plan.sources[1].selected_bytes, 1000);
+        assert_eq!(plan.sources[0].selected_bytes, 3000);
+        assert_eq!(plan.selection.files_considered, 60);
+        assert!((plan.sources[0].share - 0.75).abs() < 1e-9);
+        for m in &plan.mixture {
+            assert!((m.sources["web"].share - 0.75).abs() < 0.05);
+        }
+        assert!(plan.shards.iter().flat_map(|s| s.files.iter()).all(|f| f.source.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_mixture_constraints_span_sources() {
+        let us_dir = tempfile::tempdir().unwrap();
+        let apac_dir = tempfile::tempdir().unwrap();
+        for (dir, region) in [(&us_dir, "us"), (&apac_dir, "apac")] {
+            let mut v0 = vec![protocol_action(), metadata_action(&["region"])];
+            v0.extend((0..6).map(|i| add_action(&format!("region={}/f{}.parquet", region, i), 100, "region", region, 1)));
+            write_delta_log(&dir.path().to_path_buf(), 0, &v0);
+        }
+        let src = |name: &str, dir: &tempfile::TempDir| MixtureSource {
+            name: name.into(),
+            table: core::DeltaTableHandle { uri: dir.path().to_string_lossy().to_string(), version: None },
+            version: 0,
+            weight: 0.5,
+        };
+        let opts = ShardOptions { constraints: vec![Constraint::parse("separate(region=us,region=apac)").unwrap()], ..Default::default() };
+        let plan = plan_mixture(&[src("us", &us_dir), src("apac", &apac_dir)], 2, opts).await.unwrap();
+        // each source alone would spread over both shards
+        for s in &plan.shards {
+            let regions: HashSet<_> = s.files.iter().map(|f| f.partition["region"].clone()).collect();
+            assert!(regions.len() <= 1, "shard {} mixes {:?}", s.id, regions);
+        }
+        assert_eq!(plan.shards.iter().map(|s| s.files.len()).sum::<usize>(), 12);
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_sample_are_recorded() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        for d in ["2025-12-31", "2026-01-01", "2026-01-02"] {
+            v0.extend((0..10).map(|i| add_action(&format!("dt={}/f{}.parquet", d, i), 10, "dt", d, 3)));
+        }
+        write_delta_log(&dir, 0, &v0);
+
+        let uri = dir.to_string_lossy().to_string();
+        let h = core::load_table(&uri).await.unwrap();
+        let opts = ShardOptions {
+            filter: Some(core::Predicate::parse("dt >= '2026-01-01'").unwrap()),
+            sample: Some(0.5),
+            sample_seed: 7,
+            stratify_by: vec!["dt".into()],
+            ..Default::default()
+        };
+        let plan = plan_shards_with_selection(&h, 0, 2, opts.clone()).await.unwrap();
+        assert_eq!(plan.selection.files_pruned, 10);
+        assert_eq!(plan.selection.filter.as_deref(), Some("dt >= '2026-01-01'"));
+        let files: Vec<&ShardFile> = plan.shards.iter().flat_map(|s| s.files.iter()).collect();
+        assert!(files.iter().all(|f| f.approx_rows == 3));
+        let strata = &plan.selection.strata;
+        assert_eq!(strata.iter().map(|c| c.partition["dt"].as_str()).collect::<Vec<_>>(), vec!["2026-01-01", "2026-01-02"]);
+        for c in strata {
+            assert_eq!(c.files, 10);
+            assert_eq!(c.sampled, files.iter().filter(|f| f.path.starts_with(&format!("dt={}/", c.partition["dt"]))).count());
+        }
+        assert_eq!(plan.selection.files_sampled_out, 20 - strata.iter().map(|c| c.sampled).sum::<usize>());
+        assert!(strata.iter().all(|c| c.sampled > 0 && c.sampled < 10));
+
+        // growing a stratum keeps every earlier decision
+        write_delta_log(&dir, 1, &(10..40).map(|i| add_action(&format!("dt=2026-01-01/f{}.parquet", i), 10, "dt", "2026-01-01", 3)).collect::<Vec<_>>());
+        let grown = plan_shards_with_selection(&h, 1, 2, opts.clone()).await.unwrap();
+        let picked = |p: &ShardPlan| p.shards.iter().flat_map(|s| s.files.iter().map(|f| f.path.clone())).collect::<HashSet<_>>();
+        let earlier: HashSet<String> = picked(&grown).into_iter().filter(|x| x.rsplit_once("/f").unwrap().1.len() <= "9.parquet".len()).collect();
+        assert_eq!(earlier, picked(&plan));
+        assert_eq!(grown.selection.strata[0].files, 40);
+
+        // a tiny fraction is not rounded up to one file per stratum
+        let tiny = plan_shards_with_selection(&h, 0, 2, ShardOptions { sample: Some(0.001), ..opts.clone() }).await.unwrap();
+        assert!(tiny.selection.strata.iter().all(|c| c.sampled == 0));
+
+        let again = plan_shards_with_selection(&h, 0, 2, opts.clone()).await.unwrap();
+        assert_eq!(serde_json::to_string(&plan).unwrap(), serde_json::to_string(&again).unwrap());
+
+        let splits = plan_splits(&h, 0, 2, &parse_splits("train=0.5,val=0.5").unwrap(), opts.clone()).await.unwrap();
+        assert!(splits.iter().all(|s| s.selection.sample_seed == 7 && s.selection.files_pruned == 10));
+
+        let unsampled = ShardOptions { sample: None, ..opts };
+        assert!(plan_shards_with_selection(&h, 0, 2, unsampled).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_constraints_are_honoured() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let mut v0 = vec![protocol_action(), metadata_action(&["region"])];
+        for r in ["eu", "us", "apac", "latam", "mea"] {
+            v0.extend((0..8).map(|i| add_action(&format!("region={}/f{}.parquet", r, i), 10, "region", r, 1)));
+        }
+        write_delta_log(&dir, 0, &v0);
+        let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+        let with = |specs: &[&str]| ShardOptions { constraints: specs.iter().map(|s| Constraint::parse(s).unwrap()).collect(), ..Default::default() };
+
+        let opts = with(&["pin(region=eu,0-1)", "separate(region=us,region=apac)", "max_share(region,0.5)"]);
+        let shards = plan_shards(&h, 0, 4, opts).await.unwrap();
+        let regions = |s: &Shard| s.files.iter().map(|f| f.partition["region"].clone().unwrap()).collect::<HashSet<_>>();
+        for s in &shards {
+            let r = regions(s);
+            if r.contains("eu") { assert!(s.id <= 1); }
+            assert!(!(r.contains("us") && r.contains("apac")));
+            let eu = s.files.iter().filter(|f| f.partition["region"].as_deref() == Some("eu")).count();
+            assert!(eu * 10 <= 50);
+        }
+        assert_eq!(shards.iter().map(|s| s.files.len()).sum::<usize>(), 40);
+
+        assert_eq!(Constraint::parse("pin(region=eu,0-3,5)").unwrap().to_string(), "pin(region=eu,0-3,5)");
+        let err = plan_shards(&h, 0, 4, with(&["pin(region=eu,9)"])).await.unwrap_err().to_string();
+        assert!(err.contains("pin(region=eu,9)"));
+        // eu (80 B) pinned to one shard, but capped at 30% of the 100 B shard target
+        let err = plan_shards(&h, 0, 4, with(&["pin(region=eu,0)", "max_share(region,0.3)"])).await.unwrap_err().to_string();
+        assert!(err.contains("pin(region=eu,0)") && err.contains("max_share(region,0.3)"), "{}", err);
+    }
+
+    #[test]
+    fn test_max_share_is_an_absolute_cap_on_unequal_shards() {
+        let file = |path: &str, col: &str, val: &str, bytes: i64| ShardFile {
+            path: path.into(),
+            bytes,
+            approx_rows: 1,
+            partition: BTreeMap::from([(col.to_string(), Some(val.to_string()))]),
+            source: None,
+        };
+        // shard 0 is already heavy, shard 1 empty: total 90 B, balanced size 45 B, cap 22.5 B of eu per shard
+        let out = vec![
+            Shard { id: 0, bytes: 60, rows: 1, files: vec![file("dt=1/big.parquet", "dt", "1", 60)] },
+            Shard { id: 1, bytes: 0, rows: 0, files: Vec::new() },
+        ];
+        let eu: Vec<ShardFile> = (0..3).map(|i| file(&format!("region=eu/f{}.parquet", i), "region", "eu", 10)).collect();
+        let rules = vec![Constraint::parse("max_share(region,0.5)").unwrap()];
+        let mut st = ConstraintState::new(&rules, BalanceMode::Bytes, &out, eu.iter()).unwrap();
+        // two eu files make up all of shard 1's load, yet stay under the absolute cap
+        for f in &eu[..2] {
+            assert_eq!(st.allowed(f).unwrap(), vec![true, true]);
+            st.record(f, 1);
+        }
+        assert_eq!(st.allowed(&eu[2]).unwrap(), vec![true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_intra_shard_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        for d in ["2024-01-03", "2024-01-01", "2024-01-02"] {
+            v0.extend((0..3).map(|i| add_action(&format!("dt={}/f{}.parquet", d, i), 10 * (i + 1), "dt", d, 1)));
+        }
+        write_delta_log(&dir, 0, &v0);
+        let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+        let plan = |order: &str| {
+            let opts = ShardOptions { order: OrderPolicy::parse(order).unwrap(), ..Default::default() };
+            let h = h.clone();
+            async move { plan_shards_with_selection(&h, 0, 1, opts).await.unwrap() }
+        };
+        let dts = |p: &ShardPlan| p.shards[0].files.iter().map(|f| f.partition["dt"].clone().unwrap()).collect::<Vec<_>>();
+
+        let asc = plan("dt").await;
+        assert!(dts(&asc).windows(2).all(|w| w[0] <= w[1]));
+        let desc = plan("dt:desc").await;
+        assert_eq!(dts(&desc)[0], "2024-01-03");
+        let rr = plan("interleave:dt").await;
+        assert_eq!(&dts(&rr)[..3], &["2024-01-01", "2024-01-02", "2024-01-03"]);
+        assert_eq!(rr.order, OrderPolicy::Interleave { by: vec!["dt".into()] });
+
+        let opts = ShardOptions { order: OrderPolicy::parse("dt:desc").unwrap(), ..Default::default() };
+        let splits = plan_splits(&h, 0, 1, &parse_splits("train=0.5,val=0.5").unwrap(), opts.clone()).await.unwrap();
+        assert!(splits.iter().all(|s| s.order == opts.order));
+        let src = MixtureSource { name: "all".into(), table: h.clone(), version: 0, weight: 1.0 };
+        let mixed = plan_mixture(&[src], 1, opts.clone()).await.unwrap();
+        assert_eq!(mixed.order, opts.order);
+        assert_eq!(mixed.shards[0].files[0].partition["dt"].as_deref(), Some("2024-01-03"));
+
+        let a = plan("shuffle:9").await;
+        let b = plan("shuffle:9").await;
+        assert_eq!(serde_json::to_string(&a).unwrap(), serde_json::to_string(&b).unwrap());
+        assert!(OrderPolicy::parse("dt:sideways").is_err());
+
+        // mixed numeric and text values sort the same whatever the input order
+        let file = |v: &str| ShardFile { path: format!("n={}/f.parquet", v), bytes: 1, approx_rows: 1, partition: [("n".to_string(), Some(v.to_string()))].into_iter().collect(), source: None };
+        let by_n = OrderPolicy::parse("n").unwrap();
+        for input in [["10x", "2", "10"], ["2", "10", "10x"], ["10", "10x", "2"]] {
+            let sorted = ordered(input.iter().map(|v| file(v)).collect(), &by_n);
+            let values: Vec<&str> = sorted.iter().map(|f| f.partition["n"].as_deref().unwrap()).collect();
+            assert_eq!(values, vec!["2", "10", "10x"]);
+        }
+    }
+
+    async fn coordinator_plan(dir: &PathBuf) -> Vec<Shard> {
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        v0.extend((0..6).map(|i| add_action(&format!("dt=2024-01-01/f{}.parquet", i), 10, "dt", "2024-01-01", 1)));
+        write_delta_log(dir, 0, &v0);
+        let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+        plan_shards(&h, 0, 2, ShardOptions::default()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_coordinator_waits_for_outstanding_leases() {
+        use coordinator::{Coordinator, LeaseReply};
+        use std::time::{Duration, Instant};
+
+        let temp = tempfile::tempdir().unwrap();
+        let plan = coordinator_plan(&temp.path().to_path_buf()).await;
+        let granted = |r: LeaseReply| match r { LeaseReply::Granted(l) => l, other => panic!("expected a lease, got {:?}", other) };
+        let t0 = Instant::now();
+        let mut c = Coordinator::new(&plan, Duration::from_secs(10));
+        // slow takes one item from shard 1 and stalls; fast drains shard 0, then steals the rest
+        let stalled = granted(c.lease("slow", Some(1), t0));
+        let mut stolen = 0;
+        for _ in 0..5 {
+            let l = granted(c.lease("fast", Some(0), t0));
+            if l.stolen { stolen += 1; }
+            c.ack(l.lease_id, t0).unwrap();
+        }
+        assert!(stolen >= 1);
+        // the queues are empty, but the stalled lease can still come back
+        assert!(matches!(c.lease("fast", Some(0), t0), LeaseReply::Wait(d) if d > Duration::ZERO && d <= Duration::from_secs(1)));
+
+        let later = t0 + Duration::from_secs(11);
+        let retry = granted(c.lease("fast", Some(0), later));
+        assert_eq!(retry.item, stalled.item);
+        assert!(c.ack(stalled.lease_id, later).is_err());
+        assert!(matches!(c.lease("fast", None, later), LeaseReply::Wait(_)));
+        c.ack(retry.lease_id, later).unwrap();
+        assert!(matches!(c.lease("fast", None, later), LeaseReply::Drained));
+        let st = c.status(later);
+        assert_eq!((st.done, st.requeued), (6, 1));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_coordinator_failed_ack_keeps_the_lease() {
+        use coordinator::{Coordinator, LeaseReply};
+        use std::time::{Duration, Instant};
+
+        let temp = tempfile::tempdir().unwrap();
+        let plan = coordinator_plan(&temp.path().to_path_buf()).await;
+        let now = Instant::now();
+        let mut c = Coordinator::new(&plan, Duration::from_secs(10));
+        let mut leases = Vec::new();
+        while let LeaseReply::Granted(l) = c.lease("w", None, now) { leases.push(l); }
+        for l in &leases[1..] { c.ack(l.lease_id, now).unwrap(); }
+        // every journal write fails
+        c.set_journal_file(std::fs::File::options().write(true).open("/dev/full").unwrap());
+        assert!(c.ack(leases[0].lease_id, now).is_err());
+        let st = c.status(now);
+        assert_eq!((st.done, st.leased), (5, 1));
+        assert!(matches!(c.lease("w", None, now), LeaseReply::Wait(_)));
+    }
+
+    #[tokio::test]
+    async fn test_coordinator_work_stealing_and_resume() {
+        use coordinator::{Coordinator, CoordinatorClient, LeaseReply};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let temp = tempfile::tempdir().unwrap();
+        let plan = coordinator_plan(&temp.path().to_path_buf()).await;
+        let journal = temp.path().join("serve.journal");
+        let start = || {
+            let c = Arc::new(Mutex::new(Coordinator::with_journal(&plan, Duration::from_secs(60), &journal).unwrap()));
+            async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                (addr, tokio::spawn(coordinator::serve(listener, c)))
+            }
+        };
+
+        let (addr, server) = start().await;
+        let fast = CoordinatorClient::new(addr, "fast");
+        let slow = CoordinatorClient::new(addr, "slow");
+        let stalled = slow.lease(Some(1)).await.unwrap().unwrap();
+        assert_eq!(stalled.item.shard, 1);
+        let mut stolen = 0;
+        for _ in 0..5 {
+            let l = fast.lease(Some(0)).await.unwrap().unwrap();
+            if l.stolen { stolen += 1; }
+            fast.ack(l.lease_id).await.unwrap();
+        }
+        assert!(stolen >= 1);
+        // nothing pending while slow holds its lease: told to wait, not that the plan is drained
+        assert!(matches!(fast.poll_lease(Some(0)).await.unwrap(), LeaseReply::Wait(_)));
+        let waiting = tokio::spawn({
+            let fast = fast.clone();
+            async move { fast.lease(Some(0)).await }
+        });
+        slow.release(stalled.lease_id).await.unwrap();
+        let handed_over = waiting.await.unwrap().unwrap().unwrap();
+        assert_eq!(handed_over.item, stalled.item);
+        let st = fast.status().await.unwrap();
+        assert_eq!((st.done, st.leased, st.requeued), (5, 1, 1));
+        server.abort();
+
+        // restart from the journal: the 5 acked files stay done, the unacked one is pending again
+        let (addr, server) = start().await;
+        let w = CoordinatorClient::new(addr, "w");
+        let mut rest = Vec::new();
+        while let Some(l) = w.lease(None).await.unwrap() {
+            assert_eq!(w.heartbeat().await.unwrap(), 1);
+            rest.push(w.ack(l.lease_id).await.unwrap().path);
+        }
+        assert_eq!(rest, vec![stalled.item.path.clone()]);
+        let st = w.status().await.unwrap();
+        assert_eq!((st.total, st.done, st.pending), (6, 6, 0));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_coordinator_row_group_leases() {
+        use coordinator::{Coordinator, LeaseReply};
+        use std::time::{Duration, Instant};
+
+        let temp = tempfile::tempdir().unwrap();
+        let plan = coordinator_plan(&temp.path().to_path_buf()).await;
+        let journal = temp.path().join("rg.journal");
+        let split = &plan.iter().find(|s| !s.files.is_empty()).unwrap().files[0];
+        let counts: BTreeMap<String, u32> = [(split.path.clone(), 3)].into_iter().collect();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+
+        let mut c = Coordinator::by_row_group(&plan, ttl, &counts).journaled(&journal).unwrap();
+        assert_eq!(c.status(now).total, 8);
+        let mut groups = Vec::new();
+        while let LeaseReply::Granted(l) = c.lease("w", None, now) {
+            if l.item.path == split.path {
+                groups.push(l.item.row_group.unwrap());
+                assert_eq!(l.item.bytes, if l.item.row_group == Some(0) { 4 } else { 3 });
+                // ack only the first row group of the split file
+                if groups.len() == 1 { c.ack(l.lease_id, now).unwrap(); }
+            } else {
+                assert_eq!(l.item.row_group, None);
+            }
+        }
+        groups.sort_unstable();
+        assert_eq!(groups, vec![0, 1, 2]);
+        drop(c);
+
+        let mut resumed = Coordinator::by_row_group(&plan, ttl, &counts).journaled(&journal).unwrap();
+        let st = resumed.status(now);
+        assert_eq!((st.done, st.pending), (1, 7));
+        assert!(Coordinator::with_journal(&plan, ttl, &journal).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_progress_ledger_resume() {
+        use progress::ProgressLedger;
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        v0.extend((0..9).map(|i| add_action(&format!("dt=2024-01-01/f{}.parquet", i), 10 + i, "dt", "2024-01-01", 1)));
+        write_delta_log(&dir, 0, &v0);
+        let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+        let plan = plan_shards(&h, 0, 3, ShardOptions::default()).await.unwrap();
+        let ledger_path = dir.join("progress.ledger");
+
+        {
+            let mut ledger = ProgressLedger::open(&ledger_path, &plan).unwrap();
+            for s in &plan {
+                ledger.record(s.id, &s.files[0].path, 0, 0, true).unwrap();
+            }
+            ledger.record(2, &plan[2].files[1].path, 3, 128, false).unwrap();
+            assert!(ledger.record(0, "nope.parquet", 0, 0, true).is_err());
+        }
+
+        let ledger = ProgressLedger::open(&ledger_path, &plan).unwrap();
+        let all = ledger.remaining(&[]).unwrap();
+        assert_eq!((all.completed_files, all.remaining_files), (3, 6));
+
+        // rank 2 dies: its two leftovers (one mid-file) move to ranks 0 and 1
+        let resume = ledger.remaining(&[2]).unwrap();
+        assert_eq!(resume.shards.len(), 2);
+        assert_eq!(resume.remaining_files, 6);
+        let moved: Vec<&progress::ResumeFile> = resume.shards.iter().flat_map(|s| s.files.iter()).filter(|f| f.from_rank == 2).collect();
+        assert_eq!(moved.len(), 2);
+        let partial = moved.iter().find(|f| f.path == plan[2].files[1].path).unwrap();
+        assert_eq!((partial.row_group, partial.row), (3, 128));
+        assert!(resume.shards.iter().all(|s| s.inherited_from == vec![2]));
+
+        let mut other = plan.clone();
+        other.swap(0, 1);
+        assert!(ProgressLedger::open(&ledger_path, &other).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_usage_ledger_runs_and_removed_files() {
+        use usage::{join_table, UsageLedger};
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("table");
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        v0.extend((0..4).map(|i| add_action(&format!("dt=2024-01-01/f{}.parquet", i), 10 + i, "dt", "2024-01-01", 1)));
+        write_delta_log(&dir, 0, &v0);
+        write_delta_log(&dir, 1, &[remove_action("dt=2024-01-01/f0.parquet")]);
+        let uri = dir.to_string_lossy().to_string();
+        let h = core::load_table(&uri).await.unwrap();
+        let plan = plan_shards(&h, 0, 2, ShardOptions::default()).await.unwrap();
+        let half = plan_shards(&h, 0, 1, ShardOptions::default()).await.unwrap();
+
+        let ledger = UsageLedger::open(&temp.path().join("usage").to_string_lossy()).await.unwrap();
+        let rec = ledger.record("run-a", &plan, Some(&uri), Some(0)).await.unwrap();
+        assert_eq!(rec.files, 4);
+        assert!(ledger.record("run-a", &plan, Some(&uri), Some(0)).await.is_err());
+        ledger.record("run-b", &half, Some(&uri), Some(0)).await.unwrap();
+        assert!(ledger.record("../x", &plan, None, None).await.is_err());
+
+        let runs = ledger.runs_for_file("dt=2024-01-01/f0.parquet").await.unwrap();
+        let mut ids: Vec<&str> = runs.iter().map(|r| r.run_id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec!["run-a", "run-b"]);
+        assert_eq!(ledger.runs_for_file(&format!("{}/dt=2024-01-01/f2.parquet", uri)).await.unwrap().len(), 2);
+
+        let files = join_table(ledger.files_for_run("run-a").await.unwrap(), &h, 1).await.unwrap();
+        assert_eq!(files.len(), 4);
+        let removed: Vec<&str> = files.iter().filter(|u| u.removed == Some(true)).map(|u| u.record.path.as_str()).collect();
+        assert_eq!(removed, vec!["dt=2024-01-01/f0.parquet"]);
+    }
+
+    #[tokio::test]
+    async fn test_exclusions_skip_plan_and_tail_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let mut v0 = vec![protocol_action(), metadata_action(&["dt"])];
+        v0.extend((0..6).map(|i| add_action(&format!("dt=2024-01-01/f{}.parquet", i), 10, "dt", "2024-01-01", 1)));
+        write_delta_log(&dir, 0, &v0);
+        write_delta_log(&dir, 1, &[add_action("dt=2024-01-02/f0.parquet", 10, "dt", "2024-01-02", 1), add_action("dt=2024-01-02/f1.parquet", 10, "dt", "2024-01-02", 1)]);
+        let h = core::load_table(&dir.to_string_lossy()).await.unwrap();
+
+        let exclude = core::ExclusionList::parse("dt=2024-01-01/f3.parquet  # corrupt page\n", None).unwrap();
+        let opts = ShardOptions { exclude: exclude.clone(), ..Default::default() };
+        let plan = plan_shards_with_selection(&h, 0, 2, opts).await.unwrap();
+        assert_eq!(plan.selection.files_considered, 5);
+        assert_eq!(plan.selection.excluded.len(), 1);
+        assert!(plan.shards.iter().flat_map(|s| s.files.iter()).all(|f| f.path != "dt=2024-01-01/f3.parquet"));
+
+        // quarantined after the base plan was made: dropped from it and from the tail
+        let base = plan_shards(&h, 0, 2, ShardOptions::default()).await.unwrap();
+        let later = core::ExclusionList::parse("dt=2024-01-01/f3.parquet  # corrupt page\ndt=2024-01-02/f1.parquet  # bad schema\n", None).unwrap();
+        let tail = plan_tail(&h, &base, 0, 1, ShardOptions { exclude: later, ..Default::default() }).await.unwrap();
+        let mut skipped: Vec<&str> = tail.excluded.iter().map(|e| e.path.as_str()).collect();
+        skipped.sort_unstable();
+        assert_eq!(skipped, vec!["dt=2024-01-01/f3.parquet", "dt=2024-01-02/f1.parquet"]);
+        assert_eq!(tail.plan.iter().map(|s| s.files.len()).sum::<usize>(), 6);
+        assert_eq!(tail.plan.iter().map(|s| s.bytes).sum::<i64>(), 60);
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{plan_fingerprint, Shard};
+
+/// How far a rank got into one file. `complete` wins over any later partial offset.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Offset {
+    pub row_group: u32,
+    pub row: u64,
+    pub complete: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeFile {
+    pub path: String,
+    pub bytes: i64,
+    /// where to pick up inside the file; (0, 0) when untouched
+    pub row_group: u32,
+    pub row: u64,
+    /// rank the file was originally planned on
+    pub from_rank: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeShard {
+    pub rank: u32,
+    pub bytes: i64,
+    pub files: Vec<ResumeFile>,
+    /// failed ranks whose remainder landed here
+    pub inherited_from: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumePlan {
+    pub fingerprint: String,
+    pub completed_files: usize,
+    pub remaining_files: usize,
+    pub shards: Vec<ResumeShard>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LedgerEntry {
+    Open { fingerprint: String },
+    Progress { rank: u32, path: String, row_group: u32, row: u64, complete: bool },
+}
+
+/// Append-only per-rank consumption record for one plan fingerprint.
+pub struct ProgressLedger {
+    path: PathBuf,
+    plan: Vec<Shard>,
+    fingerprint: String,
+    offsets: BTreeMap<u32, BTreeMap<String, Offset>>,
+    file: std::fs::File,
+}
+
+impl ProgressLedger {
+    /// Opens (or creates) the ledger at `path` for `plan`; refuses a ledger written for another plan.
+    pub fn open(path: &Path, plan: &[Shard]) -> Result<ProgressLedger> {
+        let fingerprint = plan_fingerprint(plan);
+        let mut offsets: BTreeMap<u32, BTreeMap<String, Offset>> = BTreeMap::new();
+        let mut opened = false;
+        if path.exists() {
+            let f = std::fs::File::open(path).with_context(|| format!("opening progress ledger {}", path.display()))?;
+            for line in std::io::BufReader::new(f).lines() {
+                let line = line?;
+                if line.trim().is_empty() { continue; }
+                // a torn last line from a crash is ignored
+                let Ok(entry) = serde_json::from_str::<LedgerEntry>(&line) else { continue };
+                match entry {
+                    LedgerEntry::Open { fingerprint: fp } => {
+                        if fp != fingerprint {
+                            return Err(anyhow!("progress ledger {} belongs to plan {}, not {}", path.display(), fp, fingerprint));
+                        }
+                        opened = true;
+                    }
+                    LedgerEntry::Progress { rank, path, row_group, row, complete } => {
+                        let slot = offsets.entry(rank).or_default().entry(path).or_insert(Offset { row_group: 0, row: 0, complete: false });
+                        if !slot.complete { *slot = Offset { row_group, row, complete }; }
+                    }
+                }
+            }
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let mut ledger = ProgressLedger { path: path.to_path_buf(), plan: plan.to_vec(), fingerprint, offsets, file };
+        if !opened {
+            let fp = ledger.fingerprint.clone();
+            ledger.append(&LedgerEntry::Open { fingerprint: fp })?;
+        }
+        Ok(ledger)
+    }
+
+    fn append(&mut self, e: &LedgerEntry) -> Result<()> {
+        writeln!(self.file, "{}", serde_json::to_string(e)?)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    pub fn fingerprint(&self) -> &str { &self.fingerprint }
+
+    /// Records that `rank` has consumed `path` up to (`row_group`, `row`), or entirely when `complete`.
+    pub fn record(&mut self, rank: u32, path: &str, row_group: u32, row: u64, complete: bool) -> Result<()> {
+        if !self.plan.iter().any(|s| s.files.iter().any(|f| f.path == path)) {
+            return Err(anyhow!("{} is not part of plan {}", path, self.fingerprint));
+        }
+        self.append(&LedgerEntry::Progress { rank, path: path.to_string(), row_group, row, complete })?;
+        let slot = self.offsets.entry(rank).or_default().entry(path.to_string()).or_insert(Offset { row_group: 0, row: 0, complete: false });
+        if !slot.complete { *slot = Offset { row_group, row, complete }; }
+        Ok(())
+    }
+
+    pub fn offset(&self, rank: u32, path: &str) -> Option<&Offset> {
+        self.offsets.get(&rank).and_then(|m| m.get(path))
+    }
+
+    /// Work left per rank. Files of `failed` ranks are handed, with their offsets, to the surviving
+    /// rank with the least remaining bytes (largest first, ties by path), so the result is deterministic.
+    pub fn remaining(&self, failed: &[u32]) -> Result<ResumePlan> {
+        let failed: BTreeSet<u32> = failed.iter().copied().collect();
+        if let Some(r) = failed.iter().find(|r| !self.plan.iter().any(|s| s.id == **r)) {
+            return Err(anyhow!("rank {} is not in the plan", r));
+        }
+        // a file counts as done if any rank finished it (e.g. after an earlier redistribution)
+        let completed = |path: &str| self.offsets.values().any(|m| m.get(path).is_some_and(|o| o.complete));
+        let mut completed_files = 0;
+        let mut survivors: Vec<ResumeShard> = Vec::new();
+        let mut orphaned: Vec<ResumeFile> = Vec::new();
+        for s in &self.plan {
+            let mut files = Vec::new();
+            for f in &s.files {
+                if completed(&f.path) { completed_files += 1; continue; }
+                // the file may have moved ranks on an earlier resume; pick up from the furthest offset
+                let (row_group, row) = self.offsets.values().filter_map(|m| m.get(&f.path)).map(|o| (o.row_group, o.row)).max().unwrap_or((0, 0));
+                files.push(ResumeFile { path: f.path.clone(), bytes: f.bytes, row_group, row, from_rank: s.id });
+            }
+            if failed.contains(&s.id) {
+                orphaned.extend(files);
+            } else {
+                survivors.push(ResumeShard { rank: s.id, bytes: files.iter().map(|f| f.bytes.max(0)).sum(), files, inherited_from: Vec::new() });
+            }
+        }
+        if survivors.is_empty() && !orphaned.is_empty() {
+            return Err(anyhow!("every rank failed; nothing to redistribute to"));
+        }
+        orphaned.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+        for f in orphaned {
+            let target = survivors.iter_mut().min_by_key(|s| (s.bytes, s.rank)).expect("survivors checked above");
+            target.bytes += f.bytes.max(0);
+            if !target.inherited_from.contains(&f.from_rank) { target.inherited_from.push(f.from_rank); }
+            target.files.push(f);
+        }
+        for s in survivors.iter_mut() { s.inherited_from.sort_unstable(); }
+        let remaining_files = survivors.iter().map(|s| s.files.len()).sum();
+        Ok(ResumePlan { fingerprint: self.fingerprint.clone(), completed_files, remaining_files, shards: survivors })
+    }
+}
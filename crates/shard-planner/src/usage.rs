@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use deltakit_core as core;
+use object_store::path::Path as ObjPath;
+use object_store::DynObjectStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::{plan_fingerprint, Shard};
+
+/// One file of one plan, consumed by one run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub run_id: String,
+    /// RFC 3339, UTC
+    pub recorded_at: String,
+    /// fingerprint of the plan the file came from
+    pub plan: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    pub shard: u32,
+    pub path: String,
+    pub bytes: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordSummary {
+    pub run_id: String,
+    pub plan: String,
+    pub object: String,
+    pub files: usize,
+    pub bytes: i64,
+}
+
+/// A usage record joined against a table version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileUse {
+    #[serde(flatten)]
+    pub record: UsageRecord,
+    /// true when the file is no longer active at the checked version; None when not checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<bool>,
+}
+
+/// Append-only record of which runs read which files, kept as NDJSON objects under
+/// `<ledger>/runs/<run_id>/<plan fingerprint>.ndjson` on any supported store.
+pub struct UsageLedger {
+    store: Arc<DynObjectStore>,
+    root: ObjPath,
+}
+
+fn check_run_id(run_id: &str) -> Result<()> {
+    if run_id.is_empty() || !run_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) || run_id.starts_with('.') {
+        return Err(anyhow!("invalid run id '{}': use letters, digits, '-', '_' and '.'", run_id));
+    }
+    Ok(())
+}
+
+impl UsageLedger {
+    pub async fn open(uri: &str) -> Result<UsageLedger> {
+        let parsed = storage::parse_uri(uri)?;
+        let store = storage::make_object_store(uri, &storage::StorageOptions::default()).await?;
+        Ok(UsageLedger { store, root: storage::object_path_from_url(&parsed.url) })
+    }
+
+    /// Records every file of `plan` as read by `run_id`. Recording the same plan twice for a run is refused.
+    pub async fn record(&self, run_id: &str, plan: &[Shard], table: Option<&str>, version: Option<i64>) -> Result<RecordSummary> {
+        check_run_id(run_id)?;
+        let fingerprint = plan_fingerprint(plan);
+        let recorded_at = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let mut body = String::new();
+        let (mut files, mut bytes) = (0usize, 0i64);
+        for s in plan {
+            for f in &s.files {
+                let rec = UsageRecord {
+                    run_id: run_id.to_string(),
+                    recorded_at: recorded_at.clone(),
+                    plan: fingerprint.clone(),
+                    table: table.map(|t| t.to_string()),
+                    version,
+                    shard: s.id,
+                    path: f.path.clone(),
+                    bytes: f.bytes,
+                    source: f.source.clone(),
+                };
+                body.push_str(&serde_json::to_string(&rec)?);
+                body.push('\n');
+                files += 1;
+                bytes += f.bytes.max(0);
+            }
+        }
+        let loc = self.root.child("runs").child(run_id).child(format!("{}.ndjson", fingerprint));
+        if !storage::put_if_absent(self.store.clone(), &loc, body.into()).await? {
+            return Err(anyhow!("run {} already recorded plan {}", run_id, fingerprint));
+        }
+        Ok(RecordSummary { run_id: run_id.to_string(), plan: fingerprint, object: loc.to_string(), files, bytes })
+    }
+
+    async fn read(&self, prefix: &ObjPath) -> Result<Vec<UsageRecord>> {
+        let mut out = Vec::new();
+        for meta in storage::list_recursively(self.store.clone(), prefix).await? {
+            if !meta.location.as_ref().ends_with(".ndjson") { continue; }
+            let data = storage::get_bytes(self.store.clone(), &meta.location).await?;
+            for line in std::str::from_utf8(&data)?.lines().filter(|l| !l.trim().is_empty()) {
+                out.push(serde_json::from_str::<UsageRecord>(line).map_err(|e| anyhow!("{}: {}", meta.location, e))?);
+            }
+        }
+        out.sort_by(|a, b| (&a.recorded_at, &a.run_id, a.shard, &a.path).cmp(&(&b.recorded_at, &b.run_id, b.shard, &b.path)));
+        Ok(out)
+    }
+
+    pub async fn files_for_run(&self, run_id: &str) -> Result<Vec<UsageRecord>> {
+        check_run_id(run_id)?;
+        self.read(&self.root.child("runs").child(run_id)).await
+    }
+
+    /// Every recorded use of `path`; also matches `<table>/<path>` for records that name their table.
+    pub async fn runs_for_file(&self, path: &str) -> Result<Vec<UsageRecord>> {
+        let all = self.read(&self.root.child("runs")).await?;
+        let hit = |r: &UsageRecord| {
+            r.path == path || r.table.as_deref().is_some_and(|t| format!("{}/{}", t.trim_end_matches('/'), r.path) == path)
+        };
+        Ok(all.into_iter().filter(hit).collect())
+    }
+}
+
+/// Flags records of table `h` whose file is no longer active at `version`; records of other tables are left unchecked.
+pub async fn join_table(records: Vec<UsageRecord>, h: &core::DeltaTableHandle, version: i64) -> Result<Vec<FileUse>> {
+    let active: HashSet<String> = core::list_active_files(h, Some(version)).await?.into_iter().map(|f| f.path).collect();
+    Ok(records
+        .into_iter()
+        .map(|record| {
+            let checked = match record.table.as_deref() { None => true, Some(t) => t.trim_end_matches('/') == h.uri.trim_end_matches('/') };
+            let removed = checked.then(|| !active.contains(&record.path));
+            FileUse { record, removed }
+        })
+        .collect())
+}
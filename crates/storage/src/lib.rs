@@ -30,13 +30,7 @@ pub fn parse_uri(uri: &str) -> Result<ParsedUri> {
     } else {
         Url::from_file_path(uri).map_err(|_| anyhow::anyhow!("invalid file path"))?
     };
-    let root_path = match url.scheme() {
-        "s3" | "gs" | "az" | "abfs" | "file" => {
-            let p = url.path().trim_start_matches('/');
-            ObjPath::from(p)
-        }
-        _ => ObjPath::from(url.path().trim_start_matches('/')),
-    };
+    let root_path = object_path_from_url(&url);
     Ok(ParsedUri { url, root: root_path })
 }
 
@@ -109,6 +103,106 @@ pub async fn list_recursively(
     Ok(entries)
 }
 
+/// Called with the running count of objects listed so far.
+#[derive(Clone)]
+pub struct ListProgress(pub Arc<dyn Fn(usize) + Send + Sync>);
+
+impl std::fmt::Debug for ListProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("ListProgress") }
+}
+
+/// What `list_partitioned` does with a sub-prefix it discovers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixListing {
+    /// keep splitting it with delimiter listings
+    Descend,
+    /// list everything below it in one request stream
+    Flat,
+    /// leave it out of the listing
+    Skip,
+}
+
+/// Lists everything under `prefix` like `list_recursively`, but first discovers sub-prefixes
+/// `depth` levels down with delimiter listings (e.g. one level per partition column) and then
+/// lists those concurrently, at most `concurrency` requests in flight. `filter` decides for every
+/// discovered prefix whether to descend into it, list it flat or skip it.
+///
+/// Objects are streamed a prefix at a time, so memory holds at most `concurrency` prefix listings:
+/// first the objects found during discovery, then every leaf prefix in order, each sorted by location.
+pub async fn list_partitioned<F>(
+    store: Arc<DynObjectStore>,
+    prefix: &ObjPath,
+    depth: usize,
+    concurrency: usize,
+    filter: F,
+    progress: Option<ListProgress>,
+) -> Result<futures::stream::BoxStream<'static, Result<object_store::ObjectMeta>>>
+where
+    F: Fn(&ObjPath) -> PrefixListing,
+{
+    use futures::{StreamExt, TryStreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let concurrency = concurrency.max(1);
+    let seen = Arc::new(AtomicUsize::new(0));
+    let bump = move |n: usize| {
+        let total = seen.fetch_add(n, Ordering::Relaxed) + n;
+        if let Some(p) = &progress { (p.0)(total); }
+    };
+    let mut entries = Vec::new();
+    let mut leaves = Vec::new();
+    let mut frontier = vec![prefix.clone()];
+    for _ in 0..depth {
+        if frontier.is_empty() { break; }
+        let levels: Vec<object_store::ListResult> = futures::stream::iter(frontier.into_iter().map(|p| {
+            let store = store.clone();
+            async move { store.list_with_delimiter(Some(&p)).await }
+        }))
+        .buffer_unordered(concurrency)
+        .try_collect()
+        .await?;
+        frontier = Vec::new();
+        for level in levels {
+            let objects: Vec<object_store::ObjectMeta> = level.objects.into_iter().filter(|m| !m.location.as_ref().ends_with('/')).collect();
+            bump(objects.len());
+            entries.extend(objects);
+            for p in level.common_prefixes {
+                match filter(&p) {
+                    PrefixListing::Descend => frontier.push(p),
+                    PrefixListing::Flat => leaves.push(p),
+                    PrefixListing::Skip => {}
+                }
+            }
+        }
+    }
+    leaves.extend(frontier);
+    leaves.sort();
+    entries.sort_by(|a, b| a.location.cmp(&b.location));
+    debug!(prefixes = leaves.len(), "listing discovered prefixes");
+    let listed = futures::stream::iter(leaves)
+        .map(move |p| {
+            let store = store.clone();
+            let bump = bump.clone();
+            async move {
+                let mut out = Vec::new();
+                let mut unreported = 0;
+                let mut stream = store.list(Some(&p));
+                while let Some(item) = stream.next().await {
+                    let meta = item?;
+                    if meta.location.as_ref().ends_with('/') { continue; }
+                    out.push(meta);
+                    unreported += 1;
+                    if unreported == 1000 { bump(unreported); unreported = 0; }
+                }
+                bump(unreported);
+                out.sort_by(|a, b| a.location.cmp(&b.location));
+                Ok::<_, anyhow::Error>(futures::stream::iter(out.into_iter().map(Ok)))
+            }
+        })
+        .buffered(concurrency)
+        .try_flatten();
+    Ok(futures::stream::iter(entries.into_iter().map(Ok)).chain(listed).boxed())
+}
+
 pub async fn head_range(
     store: Arc<DynObjectStore>,
     location: &ObjPath,
@@ -118,9 +212,66 @@ pub async fn head_range(
     Ok(data)
 }
 
+/// Writes `data` unless an object already exists at `location`; returns false in that case.
+///
+/// Stores without conditional writes (S3 unless `AWS_CONDITIONAL_PUT` is `etag` or `dynamo:<table>`) fall back
+/// to a HEAD followed by a plain put. That check is not atomic: two writers racing for the same
+/// key can both see it absent, and the later put wins.
+pub async fn put_if_absent(
+    store: Arc<DynObjectStore>,
+    location: &ObjPath,
+    data: bytes::Bytes,
+) -> Result<bool> {
+    match store.put_opts(location, data.clone(), object_store::PutMode::Create.into()).await {
+        Ok(_) => Ok(true),
+        Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+        Err(object_store::Error::NotImplemented) => {
+            debug!(%location, "conditional put not supported, checking for the object first");
+            if head_if_exists(store.clone(), location).await?.is_some() { return Ok(false); }
+            put_bytes(store, location, data).await?;
+            Ok(true)
+        }
+        Err(e) => Err(e).with_context(|| format!("writing {}", location)),
+    }
+}
+
+pub async fn get_bytes(store: Arc<DynObjectStore>, location: &ObjPath) -> Result<bytes::Bytes> {
+    let data = store.get(location).await?.bytes().await?;
+    Ok(data)
+}
+
+/// Like `get_bytes`, but None when nothing exists at `location`.
+pub async fn get_if_exists(store: Arc<DynObjectStore>, location: &ObjPath) -> Result<Option<bytes::Bytes>> {
+    match store.get(location).await {
+        Ok(r) => Ok(Some(r.bytes().await?)),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", location)),
+    }
+}
+
+/// Object metadata at `location`, or None when nothing exists there.
+pub async fn head_if_exists(store: Arc<DynObjectStore>, location: &ObjPath) -> Result<Option<object_store::ObjectMeta>> {
+    match store.head(location).await {
+        Ok(m) => Ok(Some(m)),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading metadata of {}", location)),
+    }
+}
+
+pub async fn put_bytes(store: Arc<DynObjectStore>, location: &ObjPath, data: bytes::Bytes) -> Result<()> {
+    store.put(location, data).await.with_context(|| format!("writing {}", location))?;
+    Ok(())
+}
+
+/// Object path of a URL; the URL's percent-encoding is decoded, so `my%20table` is the key `my table`.
 pub fn object_path_from_url(url: &Url) -> ObjPath {
     let p = url.path().trim_start_matches('/');
-    ObjPath::from(p)
+    ObjPath::from_url_path(p).unwrap_or_else(|_| ObjPath::from(p))
+}
+
+/// `base` followed by `rel`, a decoded key that may span several segments.
+pub fn object_path_join(base: &ObjPath, rel: &str) -> Result<ObjPath> {
+    ObjPath::parse(format!("{}/{}", base, rel.trim_start_matches('/'))).with_context(|| format!("invalid object path {}", rel))
 }
 
 #[cfg(test)]
@@ -134,6 +285,14 @@ mod tests {
         assert_eq!(p.root.as_ref(), "tmp/table");
     }
 
+    #[test]
+    fn test_object_paths_are_decoded() {
+        let p = parse_uri("s3://bucket/my%20table").unwrap();
+        assert_eq!(p.root.as_ref(), "my table");
+        let file = object_path_join(&p.root, "dt=50%/part 1.parquet").unwrap();
+        assert_eq!(file.as_ref(), "my table/dt=50%/part 1.parquet");
+    }
+
     #[test]
     fn test_parse_s3_uri() {
         let p = parse_uri("s3://bucket/path/to/table").unwrap();